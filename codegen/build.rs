@@ -0,0 +1,104 @@
+// Copyright 2014 The Gfx-rs Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// The extensions of `gfx_macros` are written with the `quote_*!` macros
+// built into libsyntax, which `syntex_syntax` only provides as expanders,
+// so they run over the shared sources into `$OUT_DIR/lib.rs` beforehand.
+
+extern crate syntex;
+extern crate syntex_syntax as syntax;
+
+use std::env;
+use std::path::Path;
+use syntax::ast;
+use syntax::codemap::Span;
+use syntax::ext::base::{DummyResult, ExtCtxt, MacEager, MacResult};
+use syntax::ext::build::AstBuilder;
+use syntax::ext::quote;
+use syntax::parse::token;
+
+include!("src/derive.rs");
+
+// `quote::expand_quote_ty` and its like return `'static` results, so they
+// need a signature that is generic over the lifetime of the context.
+fn quote_ty<'cx>(cx: &'cx mut ExtCtxt, sp: Span, tts: &[ast::TokenTree]) -> Box<MacResult + 'cx> {
+    quote::expand_quote_ty(cx, sp, tts)
+}
+
+fn quote_arm<'cx>(cx: &'cx mut ExtCtxt, sp: Span, tts: &[ast::TokenTree]) -> Box<MacResult + 'cx> {
+    quote::expand_quote_arm(cx, sp, tts)
+}
+
+fn quote_stmt<'cx>(cx: &'cx mut ExtCtxt, sp: Span, tts: &[ast::TokenTree]) -> Box<MacResult + 'cx> {
+    quote::expand_quote_stmt(cx, sp, tts)
+}
+
+// The expansion leaves the macros it doesn't know, like `vec!`, to the
+// compiler, without expanding the `quote_*!` calls in their arguments. So
+// `vec![a, b]` is spelled out as pushes to a `Vec`, and `vec![a; n]` as an
+// extension by `iter::repeat`.
+fn vec<'cx>(cx: &'cx mut ExtCtxt, sp: Span, tts: &[ast::TokenTree]) -> Box<MacResult + 'cx> {
+    let mut parser = cx.new_parser_from_tts(tts);
+    let mut elements = Vec::new();
+    let mut count = None;
+    while parser.token != token::Eof {
+        elements.push(parser.parse_expr());
+        let done = if elements.len() == 1 && parser.eat(&token::Semi).unwrap_or(false) {
+            count = Some(parser.parse_expr());
+            true
+        } else {
+            !parser.eat(&token::Comma).unwrap_or(false)
+        };
+        if done {
+            break
+        }
+    }
+    if parser.token != token::Eof {
+        cx.span_err(sp, "expected `vec![a, b, ...]` or `vec![a; n]`");
+        return DummyResult::expr(sp);
+    }
+    let v = cx.ident_of("__vec");
+    let new = vec![cx.ident_of("std"), cx.ident_of("vec"), cx.ident_of("Vec"), cx.ident_of("new")];
+    let mut stmts = vec![cx.stmt_let(sp, true, v, cx.expr_call_global(sp, new, Vec::new()))];
+    match count {
+        Some(count) => {
+            let repeat = vec![cx.ident_of("std"), cx.ident_of("iter"), cx.ident_of("repeat")];
+            let repeat = cx.expr_call_global(sp, repeat, elements);
+            let items = cx.expr_method_call(sp, repeat, cx.ident_of("take"), vec![count]);
+            let extend = cx.expr_method_call(sp, cx.expr_ident(sp, v), cx.ident_of("extend"),
+                                             vec![items]);
+            stmts.push(cx.stmt_expr(extend));
+        },
+        None => for element in elements {
+            let push = cx.expr_method_call(sp, cx.expr_ident(sp, v), cx.ident_of("push"),
+                                           vec![element]);
+            stmts.push(cx.stmt_expr(push));
+        },
+    }
+    MacEager::expr(cx.expr_block(cx.block(sp, stmts, Some(cx.expr_ident(sp, v)))))
+}
+
+fn main() {
+    let out_dir = env::var_os("OUT_DIR").unwrap();
+    let mut registry = syntex::Registry::new();
+    registry.register_macro("quote_expr", quote::expand_quote_expr);
+    registry.register_macro("quote_ty", quote_ty);
+    registry.register_macro("quote_item", quote::expand_quote_item);
+    registry.register_macro("quote_arm", quote_arm);
+    registry.register_macro("quote_stmt", quote_stmt);
+    registry.register_macro("vec", vec);
+    registry.register_macro("derive", derive);
+    registry.expand("gfx_macros_codegen", Path::new("../src/lib.rs.in"),
+                    &Path::new(&out_dir).join("lib.rs")).unwrap();
+}
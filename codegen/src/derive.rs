@@ -0,0 +1,26 @@
+// Copyright 2014 The Gfx-rs Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+// Shared with the build script, which includes this file, so the paths are
+// spelled out in full.
+
+/// Registered in place of the `#[derive]` of `syntex_syntax`, whose
+/// implementations call into unstable parts of the standard library. Being a
+/// macro, it makes the expansion keep the attribute for the compiler itself.
+pub fn derive<'cx>(cx: &'cx mut ::syntax::ext::base::ExtCtxt, sp: ::syntax::codemap::Span,
+                   _: &[::syntax::ast::TokenTree]) -> Box<::syntax::ext::base::MacResult + 'cx> {
+    cx.span_err(sp, "`derive` is an attribute, not a macro");
+    ::syntax::ext::base::DummyResult::any(sp)
+}
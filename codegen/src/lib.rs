@@ -0,0 +1,139 @@
+// Copyright 2014 The Gfx-rs Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Source expansion of the `gfx_macros` extensions for build scripts.
+//!
+//! The attributes and macros of the plugin are run over an annotated file
+//! and the result is written out as Rust source, which compiles on a stable
+//! compiler:
+//!
+//! ```ignore
+//! // build.rs
+//! extern crate gfx_macros_codegen;
+//!
+//! use std::env;
+//! use std::path::Path;
+//!
+//! fn main() {
+//!     let out_dir = env::var_os("OUT_DIR").unwrap();
+//!     gfx_macros_codegen::expand(Path::new("src/vertex.rs.in"),
+//!                                &Path::new(&out_dir).join("vertex.rs")).unwrap();
+//! }
+//! ```
+//!
+//! The crate then pulls the generated items in with
+//! `include!(concat!(env!("OUT_DIR"), "/vertex.rs"));`.
+
+extern crate syntex_syntax as syntax;
+
+// The extensions of `gfx_macros`, with their quasi-quoting macros expanded
+// by the build script.
+include!(concat!(env!("OUT_DIR"), "/lib.rs"));
+
+mod derive;
+
+use std::io::{self, Write};
+use syntax::ext::base::SyntaxExtension;
+use syntax::fold;
+use syntax::ext::expand::{self, ExpansionConfig};
+use syntax::feature_gate::KNOWN_ATTRIBUTES;
+use syntax::print::pprust;
+
+/// Prepares the expanded crate for printing:
+///
+/// - removes the attributes that only the extensions understand, like
+///   `#[vertex_format]` or `#[name = "..."]`, which would be rejected as custom
+///   attributes by a stable compiler once the code has been generated,
+/// - numbers the `gfx` reexport modules, whose identifiers are only unique
+///   to the compiler until they are printed.
+struct Printable;
+
+impl Folder for Printable {
+    fn fold_attribute(&mut self, attribute: ast::Attribute) -> Option<ast::Attribute> {
+        use syntax::attr::AttrMetaMethods;
+        if KNOWN_ATTRIBUTES.iter().any(|&(name, _)| &attribute.name()[..] == name) {
+            Some(attribute)
+        } else {
+            None
+        }
+    }
+
+    fn fold_ident(&mut self, ident: ast::Ident) -> ast::Ident {
+        if &token::get_ident(ident)[..] == EXTERN_CRATE_HACK {
+            token::str_to_ident(&format!("{}_{}", EXTERN_CRATE_HACK, ident.name.usize()))
+        } else {
+            ident
+        }
+    }
+
+    fn fold_mac(&mut self, mac: ast::Mac) -> ast::Mac {
+        fold::noop_fold_mac(mac, self)
+    }
+}
+
+/// Expand the attributes and macros of `gfx_macros` in the file `src`, and
+/// write the resulting source to `dst`. The errors are reported to the
+/// standard error output, like the plugin does.
+pub fn expand(src: &Path, dst: &Path) -> io::Result<()> {
+    use syntax::parse;
+    clear_registries();
+    let sess = parse::new_parse_sess();
+    let krate = parse::parse_crate_from_file(src, Vec::new(), &sess);
+    let config = ExpansionConfig::default("gfx_macros".to_string());
+    let mut extensions = syntax_extensions();
+    extensions.push((token::intern("derive"),
+                     SyntaxExtension::NormalTT(Box::new(derive::derive), None, false)));
+    let krate = expand::expand_crate(&sess, config, Vec::new(), extensions, krate);
+    if sess.span_diagnostic.handler.has_errors() {
+        return Err(io::Error::new(io::ErrorKind::Other,
+                                  format!("Failed to expand `{}`", src.display())));
+    }
+    let krate = Printable.fold_crate(krate);
+    let text = pprust::to_string(|s| s.print_mod(&krate.module, &krate.attrs));
+    let mut file = try!(File::create(dst));
+    file.write_all(text.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::path::Path;
+    use super::expand;
+
+    #[test]
+    fn test_expand_vertex_format() {
+        // named after the test, in the output directory of this build
+        let dir = Path::new(env!("OUT_DIR"));
+        let (src, dst) = (dir.join("test_expand_vertex_format.rs.in"),
+                          dir.join("test_expand_vertex_format.rs"));
+        File::create(&src).unwrap().write_all(b"
+            #[vertex_format]
+            #[derive(Copy, Clone)]
+            pub struct Vertex {
+                #[name = \"a_Pos\"]
+                pos: [f32; 3],
+            }
+        ").unwrap();
+        expand(&src, &dst).unwrap();
+        let mut text = String::new();
+        File::open(&dst).unwrap().read_to_string(&mut text).unwrap();
+        assert!(text.contains("pub struct Vertex"));
+        assert!(text.contains("fn generate"));
+        assert!(text.contains("\"a_Pos\""));
+        assert!(!text.contains("#[vertex_format"));
+        assert!(!text.contains("#[name"));
+        assert!(!text.contains("mod __gfx_extern_crate_hack {"));
+    }
+}
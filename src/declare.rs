@@ -0,0 +1,151 @@
+// Copyright 2015 The Gfx-rs Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The `gfx_vertex!` and `gfx_parameters!` macros, declaring a vertex format
+//! or a shader parameter struct in one go:
+//!
+//! ```ignore
+//! gfx_vertex!(pub Vertex {
+//!     pos: [f32; 3] = "a_Pos",
+//!     #[normalized]
+//!     color: [u8; 4] = "a_Color",
+//! });
+//!
+//! gfx_parameters!(pub Material<R: gfx::Resources> {
+//!     color: [f32; 4] = "u_Color",
+//!     diffuse: gfx::shade::TextureParam<R> = "t_Diffuse",
+//! });
+//! ```
+
+use syntax::{ast, codemap, ext};
+use syntax::ext::base::{MacEager, MacResult};
+use syntax::ext::build::AstBuilder;
+use syntax::parse::attr::ParserAttr;
+use syntax::parse::token;
+use syntax::ptr::P;
+use syntax::util::small_vector::SmallVector;
+
+/// Parse an optional `pub` qualifier.
+fn parse_visibility(parser: &mut ::syntax::parse::parser::Parser) -> ast::Visibility {
+    if panictry!(parser.eat_keyword(token::keywords::Pub)) {
+        ast::Public
+    } else {
+        ast::Inherited
+    }
+}
+
+/// A struct declaration as given to the macros.
+struct Declaration {
+    attributes: Vec<ast::Attribute>,
+    visibility: ast::Visibility,
+    ident: ast::Ident,
+    generics: ast::Generics,
+    fields: Vec<ast::StructField>,
+}
+
+/// Parse a struct declaration with the compact field syntax, where a field
+/// given as `name: T = "a_Name"` gets a `#[name = "a_Name"]` attribute.
+fn parse_declaration(cx: &mut ext::base::ExtCtxt,
+                     tts: &[ast::TokenTree]) -> Declaration {
+    let mut parser = cx.new_parser_from_tts(tts);
+    let attributes = parser.parse_outer_attributes();
+    let visibility = parse_visibility(&mut parser);
+    let ident = panictry!(parser.parse_ident());
+    let generics = panictry!(parser.parse_generics());
+    panictry!(parser.expect(&token::OpenDelim(token::Brace)));
+    let mut fields = Vec::new();
+    while !parser.check(&token::CloseDelim(token::Brace)) {
+        let lo = parser.span.lo;
+        let mut field_attributes = parser.parse_outer_attributes();
+        let field_visibility = parse_visibility(&mut parser);
+        let field_ident = panictry!(parser.parse_ident());
+        panictry!(parser.expect(&token::Colon));
+        let ty = parser.parse_ty();
+        let field_span = codemap::mk_sp(lo, parser.last_span.hi);
+        if panictry!(parser.eat(&token::Eq)) {
+            let (name, style) = panictry!(parser.parse_str());
+            field_attributes.push(cx.attribute(field_span, cx.meta_name_value(
+                field_span, token::InternedString::new("name"), ast::LitStr(name, style)
+            )));
+        }
+        // configured right away, as `#[derive]` would see the field otherwise
+        if super::in_cfg(cx, &field_attributes) {
+            fields.push(codemap::Spanned {
+                node: ast::StructField_ {
+                    kind: ast::NamedField(field_ident, field_visibility),
+                    id: ast::DUMMY_NODE_ID,
+                    ty: ty,
+                    attrs: field_attributes,
+                },
+                span: field_span,
+            });
+        }
+        if !panictry!(parser.eat(&token::Comma)) {
+            break
+        }
+    }
+    panictry!(parser.expect(&token::CloseDelim(token::Brace)));
+    panictry!(parser.expect(&token::Eof));
+    Declaration {
+        attributes: attributes,
+        visibility: visibility,
+        ident: ident,
+        generics: generics,
+        fields: fields,
+    }
+}
+
+/// Build the declared struct item, with the extra attributes appended.
+fn declare(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+           declaration: Declaration, extra: Vec<ast::Attribute>) -> Box<MacResult + 'static> {
+    let Declaration { mut attributes, visibility, ident, generics, fields } = declaration;
+    attributes.extend(extra.into_iter());
+    let item = cx.item(span, ident, attributes, ast::ItemStruct(
+        P(ast::StructDef {
+            fields: fields,
+            ctor_id: None,
+        }),
+        generics,
+    )).map(|mut item| {
+        item.vis = visibility;
+        item
+    });
+    MacEager::items(SmallVector::one(item))
+}
+
+/// Expand `gfx_vertex!` into a `#[vertex_format]` struct, which gets
+/// `#[repr(C)]` from the attribute and derives `Copy` and `Clone`.
+pub fn expand_vertex(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+                     tts: &[ast::TokenTree]) -> Box<MacResult + 'static> {
+    let declaration = parse_declaration(cx, tts);
+    let extra = vec![
+        cx.attribute(span, cx.meta_word(span, token::InternedString::new("vertex_format"))),
+        cx.attribute(span, cx.meta_list(span, token::InternedString::new("derive"), vec![
+            cx.meta_word(span, token::InternedString::new("Copy")),
+            cx.meta_word(span, token::InternedString::new("Clone")),
+        ])),
+    ];
+    declare(cx, span, declaration, extra)
+}
+
+/// Expand `gfx_parameters!` into a `#[shader_param]` struct, generating the
+/// link struct and the `ShaderParam` implementation.
+pub fn expand_parameters(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+                         tts: &[ast::TokenTree]) -> Box<MacResult + 'static> {
+    let declaration = parse_declaration(cx, tts);
+    let extra = vec![
+        cx.attribute(span, cx.meta_word(span, token::InternedString::new("shader_param"))),
+    ];
+    declare(cx, span, declaration, extra)
+}
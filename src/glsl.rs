@@ -0,0 +1,399 @@
+// Copyright 2015 The Gfx-rs Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Expansion-time knowledge of GLSL declarations, used to check Rust
+//! structs against the shader sources they are bound to.
+
+use std::cmp;
+use syntax::{ast, codemap, ext};
+use syntax::ext::build::AstBuilder;
+use syntax::parse::token;
+use syntax::ptr::P;
+
+use layout;
+
+/// The kind of a shader variable.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Kind {
+    /// A vertex attribute (`attribute` or vertex shader `in`)
+    Attribute,
+    /// A plain uniform value
+    Uniform,
+    /// A uniform block
+    Block,
+    /// A sampler uniform
+    Texture,
+}
+
+/// A shader variable, as declared in GLSL or implied by a Rust field.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Var {
+    pub kind: Kind,
+    pub name: String,
+    /// The GLSL type (`vec3`, `mat4`, ...), unknown for blocks and textures
+    /// of Rust fields
+    pub ty: Option<String>,
+    /// The element count of arrays
+    pub count: Option<usize>,
+}
+
+/// The GLSL type of a vector of `count` components of `base` (`float`,
+/// `int`, `uint`, `double` or `bool`).
+pub fn vector_type(base: &str, count: usize) -> String {
+    if count == 1 {
+        return base.to_string()
+    }
+    let prefix = match base {
+        "int" => "i",
+        "uint" => "u",
+        "double" => "d",
+        "bool" => "b",
+        _ => "",
+    };
+    format!("{}vec{}", prefix, count)
+}
+
+/// The GLSL type of a matrix of `columns` columns of `rows` components of
+/// `base` (`float` or `double`), `mat3` or `mat4x2` for example.
+pub fn matrix_type(base: &str, columns: usize, rows: usize) -> String {
+    let prefix = if base == "double" { "d" } else { "" };
+    if columns == rows {
+        format!("{}mat{}", prefix, columns)
+    } else {
+        format!("{}mat{}x{}", prefix, columns, rows)
+    }
+}
+
+/// Spell square matrix types the short way, so that `mat3x3` and `mat3`
+/// compare equal.
+fn normalize_type(ty: &str) -> String {
+    let (prefix, rest) = if ty.starts_with("dmat") { ("d", &ty[4..]) }
+                         else if ty.starts_with("mat") { ("", &ty[3..]) }
+                         else { return ty.to_string() };
+    let sizes: Vec<&str> = rest.split('x').collect();
+    if sizes.len() == 2 && sizes[0] == sizes[1] {
+        format!("{}mat{}", prefix, sizes[0])
+    } else {
+        ty.to_string()
+    }
+}
+
+/// Extract the name of a single-segment path type.
+fn path_name(ty: &ast::Ty) -> Option<String> {
+    match ty.node {
+        ast::TyPath(None, ref path) if path.segments.len() == 1 =>
+            Some(path.segments[0].identifier.as_str().to_string()),
+        _ => None,
+    }
+}
+
+/// The GLSL scalar type of a Rust uniform component.
+fn uniform_scalar(name: &str) -> Option<&'static str> {
+    match name {
+        "f32" => Some("float"),
+        "i32" => Some("int"),
+        "u32" => Some("uint"),
+        "f64" => Some("double"),
+        "bool" => Some("bool"),
+        _ => None,
+    }
+}
+
+/// The GLSL type of a Rust uniform value: scalars, vectors as `[T; N]` and
+/// square matrices as `[[f32; N]; N]` (or `f64` ones), or `None` for other
+/// types. The outer array holds the columns, as GLSL stores matrices in
+/// column-major order.
+pub fn uniform_type(ty: &ast::Ty) -> Option<String> {
+    match ty.node {
+        ast::TyPath(..) => path_name(ty).and_then(|name| uniform_scalar(&name))
+                                        .map(|base| base.to_string()),
+        ast::TyFixedLengthVec(ref elem, ref count) => {
+            let count = match layout::array_len(count) {
+                Some(count) if count >= 2 && count <= 4 => count,
+                _ => return None,
+            };
+            match elem.node {
+                ast::TyPath(..) => path_name(elem).and_then(|name| uniform_scalar(&name))
+                                                  .map(|base| vector_type(base, count)),
+                ast::TyFixedLengthVec(ref column, ref rows) => {
+                    let base = match path_name(column) {
+                        Some(ref name) if &name[..] == "f32" => "float",
+                        Some(ref name) if &name[..] == "f64" => "double",
+                        _ => return None,
+                    };
+                    if layout::array_len(rows) == Some(count) {
+                        Some(matrix_type(base, count, count))
+                    } else {
+                        None
+                    }
+                },
+                _ => None,
+            }
+        },
+        _ => None,
+    }
+}
+
+/// Whether a shader declaration of type `declared` can be bound to a Rust
+/// one of type `expected`. Sampler types match any texture.
+pub fn is_compatible(expected: &Var, declared: &Var) -> bool {
+    match (&expected.ty, &declared.ty) {
+        (&Some(ref expected_ty), &Some(ref declared_ty)) if expected_ty != declared_ty => false,
+        _ => expected.count.is_none() || expected.count == declared.count,
+    }
+}
+
+/// Split a GLSL source into identifier, number and punctuation tokens,
+/// leaving out comments and preprocessor directives.
+fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line_start = true;
+    while let Some(c) = chars.next() {
+        let next = chars.peek().cloned();
+        if c == '\n' {
+            line_start = true;
+            continue
+        }
+        if c.is_whitespace() {
+            continue
+        }
+        if c == '#' && line_start {
+            while chars.peek().map_or(false, |&c| c != '\n') {
+                chars.next();
+            }
+            continue
+        }
+        line_start = false;
+        if c == '/' && next == Some('/') {
+            while chars.peek().map_or(false, |&c| c != '\n') {
+                chars.next();
+            }
+        } else if c == '/' && next == Some('*') {
+            chars.next();
+            let mut last = ' ';
+            while let Some(c) = chars.next() {
+                if last == '*' && c == '/' {
+                    break
+                }
+                last = c;
+            }
+        } else if c.is_alphanumeric() || c == '_' {
+            let mut token = c.to_string();
+            while let Some(&c) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_' || c == '.') {
+                    break
+                }
+                token.push(c);
+                chars.next();
+            }
+            tokens.push(token);
+        } else {
+            tokens.push(c.to_string());
+        }
+    }
+    tokens
+}
+
+/// Qualifiers that may precede the type of a declaration
+static QUALIFIERS: &'static [&'static str] = &[
+    "const", "centroid", "flat", "smooth", "noperspective", "invariant",
+    "highp", "mediump", "lowp", "precise",
+];
+
+/// Extract the attribute, uniform, block and sampler declarations of a GLSL
+/// source. Vertex shader inputs are only collected when `with_inputs` is
+/// set, as `in` declares varyings in the other stages.
+pub fn declarations(text: &str, with_inputs: bool) -> Vec<Var> {
+    let tokens = tokenize(text);
+    let mut vars = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        // collect a statement, up to `;` or to the start of a body
+        let start = i;
+        while i < tokens.len() && tokens[i] != ";" && tokens[i] != "{" {
+            i += 1;
+        }
+        let mut statement: Vec<&str> = tokens[start..i].iter().map(|t| &t[..]).collect();
+        let has_body = i < tokens.len() && tokens[i] == "{";
+        i += 1;
+        // drop `layout(...)` qualifiers
+        while let Some(pos) = statement.iter().position(|&t| t == "layout") {
+            let end = statement[pos..].iter().position(|&t| t == ")")
+                                      .map_or(statement.len(), |end| pos + end + 1);
+            statement = statement[..pos].iter().chain(statement[end..].iter())
+                                        .cloned().collect();
+        }
+        statement.retain(|t| !QUALIFIERS.contains(t));
+        if has_body {
+            // skip the body, along with the instance name of blocks
+            let mut depth = 1;
+            while i < tokens.len() && depth > 0 {
+                if tokens[i] == "{" {
+                    depth += 1;
+                } else if tokens[i] == "}" {
+                    depth -= 1;
+                }
+                i += 1;
+            }
+            if statement.len() == 2 && statement[0] == "uniform" {
+                while i < tokens.len() && tokens[i] != ";" {
+                    i += 1;
+                }
+                i += 1;
+                vars.push(Var {
+                    kind: Kind::Block,
+                    name: statement[1].to_string(),
+                    ty: None,
+                    count: None,
+                });
+            }
+            continue
+        }
+        let kind = match statement.first() {
+            Some(&"uniform") => Kind::Uniform,
+            Some(&"attribute") => Kind::Attribute,
+            Some(&"in") if with_inputs => Kind::Attribute,
+            _ => continue,
+        };
+        if statement.len() < 3 {
+            continue
+        }
+        let ty = statement[1];
+        let kind = if kind == Kind::Uniform && ty.contains("sampler") {
+            Kind::Texture
+        } else {
+            kind
+        };
+        // one or more comma separated names, with optional array sizes
+        let mut j = 2;
+        while j < statement.len() {
+            let name = statement[j];
+            j += 1;
+            let mut count = None;
+            if statement.get(j) == Some(&"[") {
+                count = statement.get(j + 1).and_then(|n| n.parse().ok());
+                while j < statement.len() && statement[j] != "]" {
+                    j += 1;
+                }
+                j += 1;
+            }
+            vars.push(Var {
+                kind: kind,
+                name: name.to_string(),
+                ty: Some(normalize_type(ty)),
+                count: count,
+            });
+            while j < statement.len() && statement[j] != "," {
+                j += 1;
+            }
+            j += 1;
+        }
+    }
+    vars
+}
+
+/// Check if `name` appears as an identifier anywhere in a GLSL source,
+/// outside of comments.
+pub fn mentions(text: &str, name: &str) -> bool {
+    tokenize(text).iter().any(|token| &token[..] == name)
+}
+
+/// Check if `name` can be declared in GLSL: letters, digits and underscores,
+/// not starting with a digit or the reserved `gl_` prefix.
+pub fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some('a'...'z') | Some('A'...'Z') | Some('_') => (),
+        _ => return false,
+    }
+    chars.all(|c| match c {
+        'a'...'z' | 'A'...'Z' | '0'...'9' | '_' => true,
+        _ => false,
+    }) && !name.starts_with("gl_")
+}
+
+/// Report the variable names GLSL can not declare, and the ones bound by
+/// two fields, pointing at both of them.
+pub fn check_names(cx: &mut ext::base::ExtCtxt, names: &[(String, codemap::Span)]) {
+    for (i, &(ref name, span)) in names.iter().enumerate() {
+        if !is_identifier(name) {
+            cx.span_err(span, &format!("`{}` is not a valid GLSL identifier", name));
+        }
+        if let Some(&(_, first)) = names[..i].iter().find(|&&(ref other, _)| other == name) {
+            cx.span_err(span, &format!("Shader variable `{}` is bound twice", name));
+            cx.span_note(first, "first bound here");
+        }
+    }
+}
+
+/// The number of single character edits turning `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..b.len() + 1).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for j in 0..b.len() {
+            let cost = if ca == b[j] { 0 } else { 1 };
+            let value = cmp::min(cmp::min(row[j + 1], row[j]) + 1, diagonal + cost);
+            diagonal = row[j + 1];
+            row[j + 1] = value;
+        }
+    }
+    row[b.len()]
+}
+
+/// Find the declared variable of the given kind with the name closest to
+/// `name`, if it is likely to be a typo of it.
+pub fn closest<'a>(vars: &'a [Var], kind: Kind, name: &str) -> Option<&'a Var> {
+    let max_distance = cmp::max(1, name.len() / 4);
+    vars.iter().filter(|var| var.kind == kind)
+        .map(|var| (edit_distance(&var.name, name), var))
+        .filter(|&(distance, _)| distance <= max_distance)
+        .fold(None, |best: Option<(usize, &'a Var)>, (distance, var)| match best {
+            Some((best_distance, _)) if best_distance <= distance => best,
+            _ => Some((distance, var)),
+        })
+        .map(|(_, var)| var)
+}
+
+/// Write the GLSL declarations of the variables, one per line. Textures
+/// are declared as `sampler2D`, and blocks only mentioned in a comment as
+/// their members are not known.
+pub fn declare(vars: &[Var]) -> String {
+    vars.iter().map(|var| {
+        let array = var.count.map_or(String::new(), |count| format!("[{}]", count));
+        match (var.kind, &var.ty) {
+            (Kind::Attribute, &Some(ref ty)) => format!("attribute {} {}{};\n", ty, var.name, array),
+            (Kind::Uniform, &Some(ref ty)) => format!("uniform {} {}{};\n", ty, var.name, array),
+            (Kind::Texture, _) => format!("uniform sampler2D {}{};\n", var.name, array),
+            (Kind::Block, _) => format!("// uniform block {}\n", var.name),
+            (_, &None) => format!("// {}: no GLSL type\n", var.name),
+        }
+    }).collect::<Vec<_>>().concat()
+}
+
+/// Build `pub fn <name>() -> &'static str` for `#[emit_glsl_decl]`,
+/// returning the declarations of the variables.
+pub fn decl_method(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+                   name: &str, vars: &[Var]) -> P<ast::ImplItem> {
+    let text = declare(vars);
+    let text = cx.expr_str(span, token::intern_and_get_ident(&text));
+    let decl = cx.fn_decl(Vec::new(), quote_ty!(cx, &'static str));
+    let mut method = super::impl_method(cx, span, name, false, super::no_generics(),
+                                        decl, cx.block_expr(text));
+    method.vis = ast::Public;
+    P(method)
+}
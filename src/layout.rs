@@ -0,0 +1,155 @@
+// Copyright 2015 The Gfx-rs Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Expansion-time layout computation for the plain data types understood
+//! by the decorators: scalars and (nested) fixed-size arrays of scalars.
+
+use syntax::ast;
+use syntax::parse::token;
+
+/// Size and alignment of a type, in bytes.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Layout {
+    pub size: usize,
+    pub align: usize,
+}
+
+/// Round `value` up to the next multiple of `align`.
+pub fn round_up(value: usize, align: usize) -> usize {
+    (value + align - 1) / align * align
+}
+
+/// Extract the name of a single-segment path type (`f32`, `u8`, etc).
+fn scalar_name(ty: &ast::Ty) -> Option<token::InternedString> {
+    match ty.node {
+        ast::TyPath(None, ref path) if path.segments.len() == 1 =>
+            Some(token::get_ident(path.segments[0].identifier)),
+        _ => None,
+    }
+}
+
+/// Size of a primitive scalar type.
+fn scalar_size(name: &str) -> Option<usize> {
+    match name {
+        "u8" | "i8" | "bool" => Some(1),
+        "u16" | "i16" => Some(2),
+        "u32" | "i32" | "f32" => Some(4),
+        "u64" | "i64" | "f64" => Some(8),
+        _ => None,
+    }
+}
+
+/// Extract the length of a fixed-size array, which has to be a literal.
+pub fn array_len(expr: &ast::Expr) -> Option<usize> {
+    match expr.node {
+        ast::ExprLit(ref lit) => match lit.node {
+            ast::LitInt(count, _) => Some(count as usize),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Compute the natural (`#[repr(C)]`) layout of a type.
+pub fn rust_layout(ty: &ast::Ty) -> Option<Layout> {
+    match ty.node {
+        ast::TyPath(..) => scalar_name(ty)
+            .and_then(|name| scalar_size(&name))
+            .map(|size| Layout { size: size, align: size }),
+        ast::TyFixedLengthVec(ref elem, ref count) => {
+            match (rust_layout(elem), array_len(count)) {
+                (Some(elem), Some(count)) => Some(Layout {
+                    size: elem.size * count,
+                    align: elem.align,
+                }),
+                _ => None,
+            }
+        },
+        _ => None,
+    }
+}
+
+/// Compute the field offsets and the layout of a `#[repr(C)]` struct, or of
+/// a `#[repr(packed)]` one, whose fields are not aligned.
+pub fn struct_layout(fields: &[&ast::Ty], packed: bool) -> Option<(Vec<usize>, Layout)> {
+    let mut offsets = Vec::with_capacity(fields.len());
+    let mut offset = 0;
+    let mut align = 1;
+    for ty in fields.iter() {
+        let field = match rust_layout(ty) {
+            Some(field) => field,
+            None => return None,
+        };
+        let field_align = if packed { 1 } else { field.align };
+        offset = round_up(offset, field_align);
+        offsets.push(offset);
+        offset += field.size;
+        align = ::std::cmp::max(align, field_align);
+    }
+    Some((offsets, Layout {
+        size: round_up(offset, align),
+        align: align,
+    }))
+}
+
+/// Compute the std140 layout of a uniform block member. `[T; 2]` to `[T; 4]`
+/// of scalars are vectors, aligned to two or four scalars (so `dvec3` and
+/// `dvec4` to 32 bytes), any other array (including matrices, which are laid
+/// out as arrays of column vectors) has its element stride rounded up to 16
+/// bytes.
+pub fn std140_layout(ty: &ast::Ty) -> Option<Layout> {
+    block_layout(ty, true)
+}
+
+/// Compute the std430 layout of a storage buffer member, which matches the
+/// std140 one except that array strides are only rounded up to the
+/// alignment of their element.
+pub fn std430_layout(ty: &ast::Ty) -> Option<Layout> {
+    block_layout(ty, false)
+}
+
+fn block_layout(ty: &ast::Ty, std140: bool) -> Option<Layout> {
+    match ty.node {
+        ast::TyPath(..) => match scalar_name(ty) {
+            Some(ref name) if ["f32", "i32", "u32"].contains(&&name[..]) =>
+                Some(Layout { size: 4, align: 4 }),
+            Some(ref name) if &name[..] == "f64" =>
+                Some(Layout { size: 8, align: 8 }),
+            _ => None,
+        },
+        ast::TyFixedLengthVec(ref elem, ref count) => {
+            let is_scalar = match elem.node {
+                ast::TyPath(..) => true,
+                _ => false,
+            };
+            match (block_layout(elem, std140), array_len(count)) {
+                (Some(elem), Some(count)) if is_scalar && count >= 2 && count <= 4 =>
+                    Some(Layout {
+                        size: elem.size * count,
+                        align: if count == 2 { 2 * elem.size } else { 4 * elem.size },
+                    }),
+                (Some(elem), Some(count)) => {
+                    let align = if std140 { round_up(elem.align, 16) } else { elem.align };
+                    let stride = round_up(elem.size, align);
+                    Some(Layout {
+                        size: stride * count,
+                        align: align,
+                    })
+                },
+                _ => None,
+            }
+        },
+        _ => None,
+    }
+}
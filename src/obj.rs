@@ -0,0 +1,152 @@
+// Copyright 2015 The Gfx-rs Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A minimal Wavefront OBJ reader for embedding meshes at expansion time.
+//! Only positions, normals, texture coordinates and faces are read, other
+//! statements (groups, materials, smoothing) are ignored.
+
+use std::collections::HashMap;
+
+/// A face corner, indexing the position, texture coordinate and normal.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Corner {
+    pub position: usize,
+    pub tex_coord: Option<usize>,
+    pub normal: Option<usize>,
+}
+
+/// The contents of an OBJ file. Positions have a `w` of `1.0` and texture
+/// coordinates a `w` of `0.0` unless given.
+pub struct Mesh {
+    pub positions: Vec<[f32; 4]>,
+    pub tex_coords: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    /// Triangles, with polygons split into fans
+    pub triangles: Vec<[Corner; 3]>,
+}
+
+/// Read up to `N` floats of a statement, padding with the defaults.
+fn floats(args: &[&str], defaults: &[f32]) -> Result<Vec<f32>, String> {
+    if args.len() > defaults.len() {
+        return Err(format!("Expected at most {} values, found {}", defaults.len(), args.len()))
+    }
+    defaults.iter().enumerate().map(|(i, &default)| match args.get(i) {
+        Some(arg) => arg.parse().map_err(|_| format!("Invalid number `{}`", arg)),
+        None => Ok(default),
+    }).collect()
+}
+
+/// Resolve a 1-based index, or a negative one relative to the end.
+fn index(arg: &str, count: usize) -> Result<usize, String> {
+    let value: i64 = try!(arg.parse().map_err(|_| format!("Invalid index `{}`", arg)));
+    let resolved = if value < 0 { count as i64 + value } else { value - 1 };
+    if value == 0 || resolved < 0 || resolved >= count as i64 {
+        Err(format!("Index {} is out of range", value))
+    } else {
+        Ok(resolved as usize)
+    }
+}
+
+/// Parse a `v`, `v/t`, `v//n` or `v/t/n` face corner.
+fn corner(arg: &str, mesh: &Mesh) -> Result<Corner, String> {
+    let parts: Vec<&str> = arg.split('/').collect();
+    if parts.len() > 3 {
+        return Err(format!("Invalid face corner `{}`", arg))
+    }
+    let optional = |part: Option<&&str>, count: usize| match part {
+        Some(part) if !part.is_empty() => index(part, count).map(Some),
+        _ => Ok(None),
+    };
+    Ok(Corner {
+        position: try!(index(parts[0], mesh.positions.len())),
+        tex_coord: try!(optional(parts.get(1), mesh.tex_coords.len())),
+        normal: try!(optional(parts.get(2), mesh.normals.len())),
+    })
+}
+
+/// Parse the text of an OBJ file, returning the line and message of the
+/// first problem on failure.
+pub fn parse(text: &str) -> Result<Mesh, (usize, String)> {
+    let mut mesh = Mesh {
+        positions: Vec::new(),
+        tex_coords: Vec::new(),
+        normals: Vec::new(),
+        triangles: Vec::new(),
+    };
+    for (number, line) in text.lines().enumerate() {
+        let line = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        let words: Vec<&str> = line.split(|c: char| c.is_whitespace())
+                                   .filter(|word| !word.is_empty()).collect();
+        let (statement, args) = match words.first() {
+            Some(&statement) => (statement, &words[1..]),
+            None => continue,
+        };
+        let result = match statement {
+            "v" => floats(args, &[0.0, 0.0, 0.0, 1.0]).and_then(|v| if args.len() < 3 {
+                Err("Positions need at least 3 values".to_string())
+            } else {
+                mesh.positions.push([v[0], v[1], v[2], v[3]]);
+                Ok(())
+            }),
+            "vt" => floats(args, &[0.0, 0.0, 0.0]).map(|v|
+                mesh.tex_coords.push([v[0], v[1], v[2]])
+            ),
+            "vn" => floats(args, &[0.0, 0.0, 0.0]).and_then(|v| if args.len() < 3 {
+                Err("Normals need 3 values".to_string())
+            } else {
+                mesh.normals.push([v[0], v[1], v[2]]);
+                Ok(())
+            }),
+            "f" => {
+                let corners: Result<Vec<_>, _> = args.iter().map(|arg| corner(arg, &mesh)).collect();
+                corners.and_then(|corners| if corners.len() < 3 {
+                    Err("Faces need at least 3 corners".to_string())
+                } else {
+                    for i in 1..corners.len() - 1 {
+                        mesh.triangles.push([corners[0], corners[i], corners[i + 1]]);
+                    }
+                    Ok(())
+                })
+            },
+            _ => Ok(()),
+        };
+        if let Err(message) = result {
+            return Err((number + 1, message))
+        }
+    }
+    Ok(mesh)
+}
+
+impl Mesh {
+    /// The distinct corners of the triangles in order of appearance, along
+    /// with the index of every triangle corner into them.
+    pub fn indexed(&self) -> (Vec<Corner>, Vec<u32>) {
+        let mut corners = Vec::new();
+        let mut seen = HashMap::new();
+        let mut indices = Vec::with_capacity(self.triangles.len() * 3);
+        for triangle in self.triangles.iter() {
+            for corner in triangle.iter() {
+                let index = *seen.entry(*corner).or_insert_with(|| {
+                    corners.push(*corner);
+                    corners.len() - 1
+                });
+                indices.push(index as u32);
+            }
+        }
+        (corners, indices)
+    }
+}
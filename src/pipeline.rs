@@ -0,0 +1,410 @@
+// Copyright 2015 The Gfx-rs Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The `pipeline!` and `effect!` macros, tying a vertex format, a parameter struct, the
+//! render targets and the fixed-function state into one pipeline type:
+//!
+//! ```ignore
+//! pipeline!(pub LitPipeline<R: gfx::Resources> {
+//!     vertex: Vertex,
+//!     params: LitParams<R>,
+//!     targets: GBuffer<R>,
+//!     blend: gfx::BlendPreset::Alpha,
+//!     depth: (gfx::state::Comparison::LessEqual, true),
+//! });
+//! ```
+//!
+//! The pipeline holds the program, the parameters and the `gfx::DrawState`
+//! built from the declared state, and is created with
+//! `LitPipeline::init(program, params)`. The `effect!` macro additionally
+//! embeds the shaders, linking them in `new`.
+
+use syntax::{ast, codemap, ext};
+use syntax::ext::base::{DummyResult, MacEager, MacResult};
+use syntax::ext::build::AstBuilder;
+use syntax::owned_slice::OwnedSlice;
+use syntax::parse::attr::ParserAttr;
+use syntax::parse::token;
+use syntax::ptr::P;
+use syntax::util::small_vector::SmallVector;
+
+/// The pieces of a pipeline declaration.
+struct Pipeline {
+    attributes: Vec<ast::Attribute>,
+    visibility: ast::Visibility,
+    ident: ast::Ident,
+    generics: ast::Generics,
+    vertex: Option<P<ast::Ty>>,
+    params: Option<P<ast::Ty>>,
+    targets: Option<P<ast::Ty>>,
+    blend: Option<P<ast::Expr>>,
+    depth: Option<P<ast::Expr>>,
+    rasterizer: Option<P<ast::Expr>>,
+    vertex_shader: Option<Vec<ast::TokenTree>>,
+    fragment_shader: Option<Vec<ast::TokenTree>>,
+}
+
+/// Store a pipeline value, returning whether it was given before.
+fn set<T>(slot: &mut Option<T>, value: T) -> bool {
+    let duplicate = slot.is_some();
+    *slot = Some(value);
+    duplicate
+}
+
+/// Parse the `Name<R> { key: value, ... }` declaration. The type keys take
+/// types and the state keys expressions, checked by the compiler where the
+/// state is built. The shader keys of effects take `{ ... }` blocks in the
+/// syntax of `shaders!`.
+fn parse_pipeline(cx: &mut ext::base::ExtCtxt, tts: &[ast::TokenTree],
+                  with_shaders: bool) -> Pipeline {
+    let mut parser = cx.new_parser_from_tts(tts);
+    let attributes = parser.parse_outer_attributes();
+    let visibility = if panictry!(parser.eat_keyword(token::keywords::Pub)) {
+        ast::Public
+    } else {
+        ast::Inherited
+    };
+    let ident = panictry!(parser.parse_ident());
+    let generics = panictry!(parser.parse_generics());
+    let mut pipeline = Pipeline {
+        attributes: attributes,
+        visibility: visibility,
+        ident: ident,
+        generics: generics,
+        vertex: None,
+        params: None,
+        targets: None,
+        blend: None,
+        depth: None,
+        rasterizer: None,
+        vertex_shader: None,
+        fragment_shader: None,
+    };
+    panictry!(parser.expect(&token::OpenDelim(token::Brace)));
+    while !parser.check(&token::CloseDelim(token::Brace)) {
+        let key_span = parser.span;
+        let key = token::get_ident(panictry!(parser.parse_ident()));
+        panictry!(parser.expect(&token::Colon));
+        let duplicate = match &key[..] {
+            "vertex" => set(&mut pipeline.vertex, parser.parse_ty()),
+            "params" => set(&mut pipeline.params, parser.parse_ty()),
+            "targets" => set(&mut pipeline.targets, parser.parse_ty()),
+            "blend" => set(&mut pipeline.blend, parser.parse_expr()),
+            "depth" => set(&mut pipeline.depth, parser.parse_expr()),
+            "rasterizer" => set(&mut pipeline.rasterizer, parser.parse_expr()),
+            "vertex_shader" | "fragment_shader" if with_shaders => {
+                let slot = if &key[..] == "vertex_shader" {
+                    &mut pipeline.vertex_shader
+                } else {
+                    &mut pipeline.fragment_shader
+                };
+                match panictry!(parser.parse_token_tree()) {
+                    ast::TtDelimited(_, ref delimited) => set(slot, delimited.tts.clone()),
+                    _ => {
+                        cx.span_err(parser.last_span, "Expected a `{ ... }` block of shader sources");
+                        false
+                    },
+                }
+            },
+            _ => {
+                cx.span_err(key_span, &format!(
+                    "Unknown {} key `{}`, expected one of: vertex, params, \
+                    targets, blend, depth, rasterizer{}",
+                    if with_shaders { "effect" } else { "pipeline" }, key,
+                    if with_shaders { ", vertex_shader, fragment_shader" } else { "" }
+                ));
+                parser.parse_expr();
+                false
+            },
+        };
+        if duplicate {
+            cx.span_warn(key_span, &format!("Duplicate `{}` - using the last one", key));
+        }
+        if !panictry!(parser.eat(&token::Comma)) {
+            break
+        }
+    }
+    panictry!(parser.expect(&token::CloseDelim(token::Brace)));
+    panictry!(parser.expect(&token::Eof));
+    pipeline
+}
+
+/// Expand `pipeline!` into the pipeline struct and its implementation:
+///
+/// - `pub fn init(program: gfx::ProgramHandle<R>, params: Params) -> Self`
+/// - `pub fn draw_state() -> gfx::DrawState`, with the declared `blend`
+///   preset, `depth` comparison and write flag, and `rasterizer` primitive
+///   state applied to the default one
+/// - `pub fn vertex_attributes(buffer: gfx::RawBufferHandle<R>) -> Vec<gfx::Attribute<R>>`
+///   describing the buffer as the vertex format
+/// - `pub fn render_targets()`, the layout of the `#[render_target]`
+///   struct, if `targets` is given
+pub fn expand(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+              tts: &[ast::TokenTree]) -> Box<MacResult + 'static> {
+    let pipeline = parse_pipeline(cx, tts, false);
+    if pipeline.vertex.is_none() {
+        cx.span_err(span, "Pipelines need a `vertex` format");
+        return DummyResult::any(span)
+    }
+    generate(cx, span, pipeline)
+}
+
+/// Expand `effect!`, which declares a pipeline along with its shaders:
+///
+/// ```ignore
+/// effect!(pub Textured<R: gfx::Resources> {
+///     vertex_shader: { glsl_150: file("shaders/textured_150.vert") },
+///     fragment_shader: { glsl_150: file("shaders/textured_150.frag") },
+///     params: TexturedParams<R>,
+///     blend: gfx::BlendPreset::Alpha,
+/// });
+/// ```
+///
+/// Besides the methods of `pipeline!`, the effect gets:
+///
+/// - `pub fn new<D: gfx::Factory<R>>(device: &mut D, caps: &gfx::device::Capabilities,
+///   params: Params) -> Result<Self, gfx::ProgramError>`, linking the embedded
+///   shaders
+/// - `pub fn params_mut(&mut self) -> &mut Params`
+/// - `pub fn bind(&self, mesh: gfx::Mesh<R>) -> Result<gfx::batch::OwnedBatch<Params>,
+///   gfx::batch::Error>`, creating a batch drawing the mesh with the effect,
+///   for `Clone` parameters
+///
+/// The `vertex` format is optional for effects.
+pub fn expand_effect(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+                     tts: &[ast::TokenTree]) -> Box<MacResult + 'static> {
+    let pipeline = parse_pipeline(cx, tts, true);
+    if pipeline.vertex_shader.is_none() || pipeline.fragment_shader.is_none() {
+        cx.span_err(span, "Effects need both a `vertex_shader` and a `fragment_shader`");
+        return DummyResult::any(span)
+    }
+    generate(cx, span, pipeline)
+}
+
+/// Generate the pipeline struct and its implementation
+fn generate(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+            pipeline: Pipeline) -> Box<MacResult + 'static> {
+    let Pipeline {
+        attributes, visibility, ident, generics,
+        vertex, params, targets, blend, depth, rasterizer,
+        vertex_shader, fragment_shader,
+    } = pipeline;
+    let resource = match generics.ty_params.iter().next() {
+        Some(ty_param) => ty_param.ident,
+        None => {
+            cx.span_err(span, "Pipelines need a `gfx::Resources` type parameter, \
+                               like `MyPipeline<R: gfx::Resources>`");
+            return DummyResult::any(span)
+        },
+    };
+    let params = match params {
+        Some(params) => params,
+        None => {
+            cx.span_err(span, "Pipelines need a `params` type");
+            return DummyResult::any(span)
+        },
+    };
+
+    let mut items = Vec::new();
+    let crate_root = super::crate_root(cx, span, &attributes, |item| items.push(item));
+    let path_root = crate_root.ident;
+
+    // `pub struct Name<R> { pub program, pub params, pub state }`
+    let program_ty = quote_ty!(cx, $path_root::gfx::ProgramHandle<$resource>);
+    let state_ty = quote_ty!(cx, $path_root::gfx::DrawState);
+    let fields = vec![
+        ("program", program_ty.clone()),
+        ("params", params.clone()),
+        ("state", state_ty.clone()),
+    ].into_iter().map(|(name, ty)| codemap::Spanned {
+        node: ast::StructField_ {
+            kind: ast::NamedField(cx.ident_of(name), ast::Public),
+            id: ast::DUMMY_NODE_ID,
+            ty: ty,
+            attrs: Vec::new(),
+        },
+        span: span,
+    }).collect();
+    let struct_item = cx.item(span, ident, attributes, ast::ItemStruct(
+        P(ast::StructDef {
+            fields: fields,
+            ctor_id: None,
+        }),
+        generics.clone(),
+    )).map(|mut item| {
+        item.vis = visibility;
+        item.attrs.push(super::allow_dead_code(cx, span));
+        item
+    });
+
+    let struct_ty = super::struct_type(cx, span, ident, &generics);
+    let mut methods = Vec::new();
+
+    // `pub fn draw_state() -> gfx::DrawState`
+    let mut state = quote_expr!(cx, $path_root::gfx::DrawState::new());
+    if let Some(blend) = blend {
+        state = quote_expr!(cx, $state.blend($blend));
+    }
+    if let Some(depth) = depth {
+        state = quote_expr!(cx, { let (fun, write) = $depth; $state.depth(fun, write) });
+    }
+    if let Some(rasterizer) = rasterizer {
+        state = quote_expr!(cx, { let mut state = $state; state.primitive = $rasterizer; state });
+    }
+    let decl = cx.fn_decl(Vec::new(), state_ty);
+    methods.push(super::impl_method(cx, span, "draw_state", false, super::no_generics(),
+                                    decl, cx.block_expr(state)));
+
+    // `pub fn init(program: gfx::ProgramHandle<R>, params: Params) -> Self`
+    let program = cx.ident_of("program");
+    let params_ident = cx.ident_of("params");
+    let init_body = cx.expr_struct_ident(span, ident, vec![
+        cx.field_imm(span, program, cx.expr_ident(span, program)),
+        cx.field_imm(span, params_ident, cx.expr_ident(span, params_ident)),
+        cx.field_imm(span, cx.ident_of("state"), quote_expr!(cx, <$struct_ty>::draw_state())),
+    ]);
+    let decl = cx.fn_decl(vec![
+        cx.arg(span, program, program_ty),
+        cx.arg(span, params_ident, params.clone()),
+    ], struct_ty.clone());
+    methods.push(super::impl_method(cx, span, "init", false, super::no_generics(),
+                                    decl, cx.block_expr(init_body)));
+
+    // `pub fn vertex_attributes(buffer: gfx::RawBufferHandle<R>) -> Vec<gfx::Attribute<R>>`
+    if let Some(vertex) = vertex {
+        let buffer = cx.ident_of("buffer");
+        let attributes_body = quote_expr!(cx,
+            <$vertex as $path_root::gfx::VertexFormat>::generate($buffer)
+        );
+        let decl = cx.fn_decl(
+            vec![cx.arg(span, buffer, quote_ty!(cx, $path_root::gfx::RawBufferHandle<$resource>))],
+            quote_ty!(cx, Vec<$path_root::gfx::Attribute<$resource>>)
+        );
+        methods.push(super::impl_method(cx, span, "vertex_attributes", false, super::no_generics(),
+                                        decl, cx.block_expr(attributes_body)));
+    }
+
+    // `pub fn render_targets() -> Vec<(String, usize, Option<gfx::tex::Format>, gfx::state::ColorMask)>`
+    if let Some(targets) = targets {
+        let decl = cx.fn_decl(Vec::new(), quote_ty!(cx,
+            Vec<(String, usize, Option<$path_root::gfx::tex::Format>, $path_root::gfx::state::ColorMask)>
+        ));
+        let body = quote_expr!(cx, <$targets>::render_targets());
+        methods.push(super::impl_method(cx, span, "render_targets", false, super::no_generics(),
+                                        decl, cx.block_expr(body)));
+    }
+
+    if let (Some(vertex_shader), Some(fragment_shader)) = (vertex_shader, fragment_shader) {
+        methods.extend(effect_methods(cx, span, &struct_ty, &params, resource, path_root,
+                                      vertex_shader, fragment_shader).into_iter());
+    }
+
+    let methods = methods.into_iter().map(|mut method| {
+        method.vis = ast::Public;
+        P(method)
+    }).collect();
+    let impl_item = cx.item(span, ident, vec![super::allow_dead_code(cx, span)], ast::ItemImpl(
+        ast::Unsafety::Normal,
+        ast::ImplPolarity::Positive,
+        generics,
+        None,
+        struct_ty,
+        methods
+    ));
+    items.push(super::fixup_extern_crate_paths(struct_item, &crate_root));
+    items.push(super::fixup_extern_crate_paths(impl_item, &crate_root));
+    MacEager::items(SmallVector::many(items))
+}
+
+/// Build the `new`, `params_mut` and `bind` methods of an effect
+fn effect_methods(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+                  struct_ty: &P<ast::Ty>, params: &P<ast::Ty>,
+                  resource: ast::Ident, path_root: ast::Ident,
+                  vertex_shader: Vec<ast::TokenTree>,
+                  fragment_shader: Vec<ast::TokenTree>) -> Vec<ast::ImplItem> {
+    let mut methods = Vec::new();
+
+    // `pub fn new<D: gfx::Factory<R>>(device: &mut D, caps: &gfx::device::Capabilities,
+    // params: Params) -> Result<Self, gfx::ProgramError>`
+    let device_ty = cx.ident_of("__D");
+    let factory_bound = cx.typarambound(cx.path_all(span, false, vec![
+        path_root,
+        cx.ident_of("gfx"),
+        cx.ident_of("Factory"),
+    ], Vec::new(), vec![cx.ty_ident(span, resource)], Vec::new()));
+    let mut new_generics = super::no_generics();
+    new_generics.ty_params = OwnedSlice::from_vec(vec![
+        cx.typaram(span, device_ty, OwnedSlice::from_vec(vec![factory_bound]), None)
+    ]);
+    let device = cx.ident_of("device");
+    let caps = cx.ident_of("caps");
+    let params_ident = cx.ident_of("params");
+    let new_body = quote_expr!(cx, {
+        let program = try!($path_root::gfx::traits::FactoryExt::link_program_source(
+            $device, shaders!($vertex_shader), shaders!($fragment_shader), $caps
+        ));
+        Ok(<$struct_ty>::init(program, $params_ident))
+    });
+    let decl = cx.fn_decl(vec![
+        cx.arg(span, device, cx.ty_rptr(span, cx.ty_ident(span, device_ty), None, ast::MutMutable)),
+        cx.arg(span, caps, cx.ty_rptr(span, quote_ty!(cx, $path_root::gfx::device::Capabilities),
+                                      None, ast::MutImmutable)),
+        cx.arg(span, params_ident, params.clone()),
+    ], quote_ty!(cx, Result<$struct_ty, $path_root::gfx::ProgramError>));
+    methods.push(super::impl_method(cx, span, "new", false, new_generics,
+                                    decl, cx.block_expr(new_body)));
+
+    // `pub fn params_mut(&mut self) -> &mut Params`
+    let decl = cx.fn_decl(vec![ast::Arg::new_self(span, ast::MutImmutable, cx.ident_of("self"))],
+                          cx.ty_rptr(span, params.clone(), None, ast::MutMutable));
+    let mut params_mut = super::impl_method(cx, span, "params_mut", true, super::no_generics(),
+                                            decl, cx.block_expr(quote_expr!(cx, &mut self.params)));
+    if let ast::MethodImplItem(ref mut sig, _) = params_mut.node {
+        sig.explicit_self.node = ast::SelfRegion(None, ast::MutMutable, cx.ident_of("self"));
+    }
+    methods.push(params_mut);
+
+    // `pub fn bind(&self, mesh: gfx::Mesh<R>) -> Result<gfx::batch::OwnedBatch<Params>,
+    // gfx::batch::Error> where Params: Clone`
+    let mut bind_generics = super::no_generics();
+    bind_generics.where_clause.predicates.push(ast::WherePredicate::BoundPredicate(
+        ast::WhereBoundPredicate {
+            span: span,
+            bound_lifetimes: Vec::new(),
+            bounded_ty: params.clone(),
+            bounds: OwnedSlice::from_vec(vec![cx.typarambound(cx.path_global(span, vec![
+                cx.ident_of("std"),
+                cx.ident_of("clone"),
+                cx.ident_of("Clone"),
+            ]))]),
+        }
+    ));
+    let mesh = cx.ident_of("mesh");
+    let bind_body = quote_expr!(cx, {
+        let mut batch = try!($path_root::gfx::batch::OwnedBatch::new(
+            $mesh, self.program.clone(), self.params.clone()
+        ));
+        batch.state = self.state.clone();
+        Ok(batch)
+    });
+    let decl = cx.fn_decl(
+        vec![ast::Arg::new_self(span, ast::MutImmutable, cx.ident_of("self")),
+             cx.arg(span, mesh, quote_ty!(cx, $path_root::gfx::Mesh<$resource>))],
+        quote_ty!(cx, Result<$path_root::gfx::batch::OwnedBatch<$params>,
+                             $path_root::gfx::batch::Error>)
+    );
+    methods.push(super::impl_method(cx, span, "bind", true, bind_generics,
+                                    decl, cx.block_expr(bind_body)));
+    methods
+}
@@ -0,0 +1,248 @@
+// Copyright 2015 The Gfx-rs Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ascii::AsciiExt;
+use syntax::{ast, attr, codemap, ext};
+use syntax::ext::base::ItemDecorator;
+use syntax::ext::build::AstBuilder;
+use syntax::parse::token;
+use syntax::ptr::P;
+
+/// The maximum number of color targets, which is the minimum of
+/// `GL_MAX_DRAW_BUFFERS` required by GL 3
+const MAX_TARGETS: usize = 8;
+
+/// Build the `gfx::state::ColorMask` expression of a `#[mask = "RGB"]`
+/// attribute, writing all channels when there is none
+fn mask_expr(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+             mask: Option<token::InternedString>, path_root: ast::Ident) -> P<ast::Expr> {
+    let mask = match mask {
+        Some(mask) => mask,
+        None => return quote_expr!(cx, $path_root::gfx::state::MASK_ALL),
+    };
+    let mut channels = Vec::new();
+    for c in mask.chars() {
+        let channel = match c.to_ascii_uppercase() {
+            'R' => "RED",
+            'G' => "GREEN",
+            'B' => "BLUE",
+            'A' => "ALPHA",
+            _ => {
+                cx.span_err(span, &format!(
+                    "Unknown color channel `{}` in mask, expected a combination of `RGBA`", c
+                ));
+                continue
+            },
+        };
+        if channels.contains(&channel) {
+            cx.span_warn(span, &format!("Channel `{}` appears more than once in mask", c));
+        } else {
+            channels.push(channel);
+        }
+    }
+    let empty = quote_expr!(cx, $path_root::gfx::state::ColorMask::empty());
+    channels.into_iter().fold(empty, |mask, channel| {
+        let channel = cx.ident_of(channel);
+        quote_expr!(cx, $mask | $path_root::gfx::state::$channel)
+    })
+}
+
+#[derive(Copy, Clone)]
+pub struct RenderTarget;
+
+impl ItemDecorator for RenderTarget {
+    /// Generate the output layout of a `#[render_target]` struct, which
+    /// declares one color target of the fragment shader per field:
+    ///
+    /// ```ignore
+    /// #[render_target]
+    /// struct GBuffer<R: gfx::Resources> {
+    ///     #[name = "o_Albedo"]
+    ///     #[format = "RGBA8"]
+    ///     albedo: gfx::Plane<R>,
+    ///     #[name = "o_Normal"]
+    ///     #[mask = "RGB"]
+    ///     normal: gfx::Plane<R>,
+    /// }
+    /// ```
+    ///
+    /// Targets are numbered in declaration order, unless placed with
+    /// `#[index = "N"]`. `#[format]` names a `gfx::tex` format constant and
+    /// `#[mask]` selects the channels written. The layout is returned by
+    /// `pub fn render_targets() -> Vec<(String, usize, Option<gfx::tex::Format>,
+    /// gfx::state::ColorMask)>`, sorted by index.
+    ///
+    /// An output marked `#[blend_src = "1"]` is the second source of
+    /// dual-source blending, written to the target 0 along with the first
+    /// one. `pub fn output_indices() -> Vec<(String, usize, usize)>` lists
+    /// the target and blend source index of every output.
+    fn expand(&self, context: &mut ext::base::ExtCtxt, span: codemap::Span,
+              _meta_item: &ast::MetaItem, item: &ast::Item,
+              push: &mut FnMut(P<ast::Item>)) {
+        let item = &super::strip_unconfigured(context, item);
+        // Insert the `gfx` reexport module, unless a `#[gfx_crate]` path is given
+        let crate_root = super::crate_root(context, span, &item.attrs, |i| (*push)(i));
+        let path_root = crate_root.ident;
+
+        let (definition, generics) = match item.node {
+            ast::ItemStruct(ref definition, ref generics) => (definition, generics),
+            _ => {
+                context.span_err(span, "Only structs can be annotated with #[render_target]");
+                return;
+            }
+        };
+
+        let mut targets: Vec<Option<P<ast::Expr>>> = (0..MAX_TARGETS).map(|_| None).collect();
+        let mut names: Vec<Option<String>> = (0..MAX_TARGETS).map(|_| None).collect();
+        // the second source of dual-source blending, along with its field span
+        let mut second_source: Option<(String, codemap::Span)> = None;
+        let mut next_index = 0;
+        for (index, field) in definition.fields.iter().enumerate() {
+            if super::is_skipped(field) {
+                continue
+            }
+            let attrs = &field.node.attrs;
+            let name = match (super::find_name(context, span, attrs),
+                              super::FieldAccess::of(field, index)) {
+                (Some(name), _) => name,
+                (None, super::FieldAccess::Named(ident)) => token::get_ident(ident),
+                (None, super::FieldAccess::Indexed(_)) => {
+                    context.span_err(field.span, "Tuple struct fields need a `#[name = \"...\"]` \
+                                                  to be used as render targets");
+                    continue
+                },
+            };
+            match super::find_int(context, span, attrs, "blend_src") {
+                Some(0) | None => (),
+                Some(1) => {
+                    if super::find_int(context, span, attrs, "index").map_or(false, |i| i != 0) {
+                        context.span_err(field.span, "The second blend source is an output \
+                                                      of the target 0, dual-source blending \
+                                                      having one target");
+                    }
+                    if attr::contains_name(attrs, "format") || attr::contains_name(attrs, "mask") {
+                        context.span_err(field.span, "The second blend source is not a target \
+                                                      of its own, and has no `#[format]` or \
+                                                      `#[mask]`");
+                    }
+                    if second_source.is_some() {
+                        context.span_err(field.span, "Only one output can be the second blend source");
+                    }
+                    second_source = Some((name.to_string(), field.span));
+                    continue
+                },
+                Some(source) => {
+                    context.span_err(field.span, &format!(
+                        "Blend source {} is out of range, expected 0 or 1", source
+                    ));
+                    continue
+                },
+            }
+            let target_index = match super::find_int(context, span, attrs, "index") {
+                Some(target_index) => target_index as usize,
+                None => next_index,
+            };
+            next_index = target_index + 1;
+            if target_index >= MAX_TARGETS {
+                context.span_err(field.span, &format!(
+                    "Render target index {} is out of range, at most {} targets are supported",
+                    target_index, MAX_TARGETS
+                ));
+                continue
+            }
+            if targets[target_index].is_some() {
+                context.span_err(field.span, &format!(
+                    "Render target index {} is used more than once", target_index
+                ));
+                continue
+            }
+            let format = match super::find_str(context, span, attrs, "format") {
+                Some(format) => {
+                    let format = context.ident_of(&format);
+                    quote_expr!(context, Some($path_root::gfx::tex::$format))
+                },
+                None => quote_expr!(context, None),
+            };
+            let mask = super::find_str(context, span, attrs, "mask");
+            let mask = mask_expr(context, field.span, mask, path_root);
+            names[target_index] = Some(name.to_string());
+            let name = &name[..];
+            let target_expr = context.expr_usize(span, target_index);
+            targets[target_index] = Some(quote_expr!(context, {
+                targets.push(($name.to_string(), $target_expr, $format, $mask));
+            }));
+        }
+        if let Some((_, second_span)) = second_source {
+            if names[0].is_none() {
+                context.span_err(second_span, "Dual-source blending needs the first blend \
+                                               source at the target 0");
+            } else if names.iter().skip(1).any(|name| name.is_some()) {
+                context.span_err(second_span, "Dual-source blending only writes the target 0, \
+                                               there can be no other targets");
+            }
+        }
+        let target_pushes: Vec<_> = targets.into_iter().filter_map(|target| target).collect();
+        let capacity = target_pushes.len();
+        let body = quote_expr!(context, {
+            let mut targets = Vec::with_capacity($capacity);
+            $target_pushes;
+            targets
+        });
+
+        // `pub fn render_targets() -> Vec<(String, usize, Option<gfx::tex::Format>, gfx::state::ColorMask)>`
+        let decl = context.fn_decl(Vec::new(), quote_ty!(context,
+            Vec<(String, usize, Option<$path_root::gfx::tex::Format>, $path_root::gfx::state::ColorMask)>
+        ));
+        let mut method = super::impl_method(context, span, "render_targets", false,
+                                            super::no_generics(), decl,
+                                            context.block_expr(body));
+        method.vis = ast::Public;
+
+        // `pub fn output_indices() -> Vec<(String, usize, usize)>`, the target
+        // and the blend source index of every output, for binding the outputs
+        // with `glBindFragDataLocationIndexed`
+        let index_pushes: Vec<_> = names.iter().enumerate()
+            .filter_map(|(index, name)| name.as_ref().map(|name| (name.clone(), index, 0)))
+            .chain(second_source.into_iter().map(|(name, _)| (name, 0, 1)))
+            .map(|(name, index, source)| {
+                let name = &name[..];
+                let index = context.expr_usize(span, index);
+                let source = context.expr_usize(span, source);
+                quote_stmt!(context, {
+                    outputs.push(($name.to_string(), $index, $source));
+                }).unwrap()
+            }).collect();
+        let body = quote_expr!(context, {
+            let mut outputs = Vec::new();
+            $index_pushes
+            outputs
+        });
+        let decl = context.fn_decl(Vec::new(), quote_ty!(context, Vec<(String, usize, usize)>));
+        let mut indices_method = super::impl_method(context, span, "output_indices", false,
+                                                    super::no_generics(), decl,
+                                                    context.block_expr(body));
+        indices_method.vis = ast::Public;
+        let struct_ty = super::struct_type(context, span, item.ident, generics);
+        let attrs = vec![super::allow_dead_code(context, span)];
+        let impl_item = context.item(span, item.ident, attrs, ast::ItemImpl(
+            ast::Unsafety::Normal,
+            ast::ImplPolarity::Positive,
+            generics.clone(),
+            None,
+            struct_ty,
+            vec![P(method), P(indices_method)]
+        ));
+        (*push)(super::fixup_extern_crate_paths(impl_item, &crate_root));
+    }
+}
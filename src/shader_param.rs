@@ -12,24 +12,32 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use syntax::{abi, ast, codemap, ext};
+use std::ascii::AsciiExt;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use syntax::{ast, attr, codemap, ext};
 use syntax::ext::base::ItemDecorator;
 use syntax::ext::build::AstBuilder;
 use syntax::owned_slice::OwnedSlice;
-use syntax::parse::token;
+use syntax::parse::{self, token};
 use syntax::ptr::P;
 
+use glsl;
+use layout;
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 enum Param {
     Uniform,
     Block,
     Texture,
-    Special,
 }
 
 #[derive(Copy, Clone, Debug)]
 enum ParamError {
     DeprecatedTexture,
+    StandaloneSampler,
+    StorageBuffer,
+    Double,
 }
 
 /// Classify variable types (`i32`, `TextureParam`, etc) into the `Param`
@@ -38,63 +46,430 @@ fn classify(node: &ast::Ty_) -> Result<Param, ParamError> {
         ast::TyPath(_,ref path) => match path.segments.last() {
             Some(segment) => match segment.identifier.name.as_str() {
                 "RawBufferHandle" => Ok(Param::Block),
+                "BufferHandle"    => Ok(Param::Block),
                 "TextureParam"    => Ok(Param::Texture),
                 "TextureHandle"   => Err(ParamError::DeprecatedTexture),
-                "PhantomData"     => Ok(Param::Special),
+                "SamplerHandle"   => Err(ParamError::StandaloneSampler),
                 _ => Ok(Param::Uniform),
             },
             None => Ok(Param::Uniform),
         },
+        // arrays of textures are recognized to be rejected
+        ast::TyFixedLengthVec(ref elem, _) => match classify(&elem.node) {
+            Ok(Param::Texture) => Ok(Param::Texture),
+            Err(e) => Err(e),
+            _ => Ok(Param::Uniform),
+        },
         _ => Ok(Param::Uniform),
     }
 }
 
+/// Extract `T` out of an optional `Option<T>` parameter type
+fn optional_inner(ty: &ast::Ty) -> Option<&ast::Ty> {
+    match ty.node {
+        ast::TyPath(_, ref path) => path.segments.last().and_then(|segment| {
+            if segment.identifier.name.as_str() != "Option" {
+                return None
+            }
+            match segment.parameters {
+                ast::AngleBracketedParameters(ref data) => data.types.iter().next().map(|ty| &**ty),
+                ast::ParenthesizedParameters(_) => None,
+            }
+        }),
+        _ => None,
+    }
+}
+
+/// The type of the parameter value, which is `T` for `Option<T>` fields
+fn value_type(field: &ast::StructField) -> &ast::Ty {
+    optional_inner(&field.node.ty).unwrap_or(&field.node.ty)
+}
+
+/// Check if the type is `f64`, or a vector, matrix or array of them
+fn is_double(node: &ast::Ty_) -> bool {
+    match *node {
+        ast::TyPath(None, ref path) => path.segments.len() == 1 &&
+            path.segments[0].identifier.name.as_str() == "f64",
+        ast::TyFixedLengthVec(ref elem, _) => is_double(&elem.node),
+        _ => false,
+    }
+}
+
+/// Classify a struct field, honoring the `#[uniform_block]` marker
+fn classify_field(field: &ast::StructField) -> Result<Param, ParamError> {
+    if attr::contains_name(&field.node.attrs, "storage_buffer") {
+        Err(ParamError::StorageBuffer)
+    } else if attr::contains_name(&field.node.attrs, "uniform_block") {
+        Ok(Param::Block)
+    } else if is_double(&value_type(field).node) {
+        Err(ParamError::Double)
+    } else {
+        classify(&value_type(field).node)
+    }
+}
+
+/// Check if the type is a typed `gfx::BufferHandle`, which is bound
+/// through its raw handle
+fn is_typed_buffer(node: &ast::Ty_) -> bool {
+    match *node {
+        ast::TyPath(_, ref path) => path.segments.last().map_or(false, |segment|
+            segment.identifier.name.as_str() == "BufferHandle"
+        ),
+        _ => false,
+    }
+}
+
+/// Extract the element count of an array uniform (`[[f32; 4]; 8]`). Vectors
+/// of 2 to 4 components and square matrices (`mat2`, `mat3` and `mat4`) are
+/// single uniform values, other arrays of vectors are arrays.
+fn uniform_array_len(ty: &ast::Ty) -> Option<usize> {
+    match ty.node {
+        ast::TyFixedLengthVec(ref elem, ref count) => {
+            let count = match layout::array_len(count) {
+                Some(count) => count,
+                None => return None,
+            };
+            let is_single = match elem.node {
+                ast::TyPath(..) => count >= 2 && count <= 4,
+                ast::TyFixedLengthVec(ref column, ref rows) => match column.node {
+                    ast::TyPath(..) => count >= 2 && count <= 4 &&
+                        layout::array_len(rows) == Some(count),
+                    _ => false,
+                },
+                _ => false,
+            };
+            if is_single { None } else { Some(count) }
+        },
+        _ => None,
+    }
+}
+
+/// The columns and rows of a type shaped like a non-square matrix, like
+/// `[[f32; 2]; 4]` for a `mat4x2`
+fn non_square_matrix(ty: &ast::Ty) -> Option<(usize, usize)> {
+    match ty.node {
+        ast::TyFixedLengthVec(ref column, ref columns) => match column.node {
+            ast::TyFixedLengthVec(ref scalar, ref rows) => match (&scalar.node,
+                                                                  layout::array_len(columns),
+                                                                  layout::array_len(rows)) {
+                (&ast::TyPath(..), Some(columns), Some(rows))
+                    if columns != rows && columns >= 2 && columns <= 4 &&
+                       rows >= 2 && rows <= 4 => Some((columns, rows)),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// How often a parameter changes, given by `#[rate(frame)]` or
+/// `#[rate(object)]`. Per-frame parameters can be uploaded once for all the
+/// draws of a frame, and parameters are per-object by default.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Rate {
+    Frame,
+    Object,
+}
+
+/// Extract the `#[rate(...)]` of a field, if any
+fn find_rate(cx: &mut ext::base::ExtCtxt, field: &ast::StructField) -> Option<Rate> {
+    field.node.attrs.iter().fold(None, |rate, attribute| {
+        match attribute.node.value.node {
+            ast::MetaList(ref name, ref items) if &name[..] == "rate" => {
+                attr::mark_used(attribute);
+                let new_rate = match items.first().map(|item| &item.node) {
+                    Some(&ast::MetaWord(ref word)) if items.len() == 1 && &word[..] == "frame" =>
+                        Rate::Frame,
+                    Some(&ast::MetaWord(ref word)) if items.len() == 1 && &word[..] == "object" =>
+                        Rate::Object,
+                    _ => {
+                        cx.span_err(attribute.span, "Expected `#[rate(frame)]` or `#[rate(object)]`");
+                        return rate
+                    },
+                };
+                if rate.is_some() {
+                    cx.span_warn(attribute.span, "Extra `#[rate]` detected - ignoring it");
+                    return rate
+                }
+                Some(new_rate)
+            },
+            _ => rate,
+        }
+    })
+}
+
+/// A single shader parameter, either a field of the struct itself or a
+/// field of a nested parameter struct
+#[derive(Clone)]
+struct ParamField {
+    /// Definition of the field holding the value
+    field: ast::StructField,
+    /// Field of the link struct storing the variable id
+    link: ast::Ident,
+    /// Fields leading from `self` to the value
+    path: Vec<super::FieldAccess>,
+    /// Name of the shader variable
+    name: token::InternedString,
+    /// Upload rate, inherited from the field holding a nested struct
+    rate: Rate,
+}
+
+/// Access the parameters of the `#[shader_param]` structs expanded so far,
+/// by `item_key`, so that later structs can nest them
+fn with_param_structs<T, F>(f: F) -> T
+    where F: FnOnce(&RefCell<HashMap<String, Vec<ParamField>>>) -> T
+{
+    // in a function, like the formats of `vertex_format`
+    thread_local!(
+        static PARAM_STRUCTS: RefCell<HashMap<String, Vec<ParamField>>> =
+            RefCell::new(HashMap::new())
+    );
+    PARAM_STRUCTS.with(f)
+}
+
+/// Forget the parameter structs expanded so far
+pub fn clear_registry() {
+    with_param_structs(|structs| structs.borrow_mut().clear());
+}
+
+/// Look up the parameters of the nested `#[shader_param]` struct a field
+/// refers to, if any, reporting ambiguous paths
+fn nested_params(cx: &mut ext::base::ExtCtxt, field: &ast::StructField) -> Option<Vec<ParamField>> {
+    let path = match field.node.ty.node {
+        ast::TyPath(None, ref path) => path,
+        _ => return None,
+    };
+    match with_param_structs(|structs| super::find_entry(cx, &structs.borrow(), path)) {
+        Ok(params) => Some(params),
+        Err(ref candidates) if candidates.is_empty() => None,
+        Err(candidates) => {
+            cx.span_err(field.span, &super::entry_error(path, "`#[shader_param]` struct",
+                                                        "this struct", &candidates));
+            Some(Vec::new())
+        },
+    }
+}
+
+/// The shader variables of the previously expanded `#[shader_param]` struct
+/// a path refers to, see `find_entry`
+pub fn signature(cx: &ext::base::ExtCtxt, path: &ast::Path) -> Result<Vec<glsl::Var>, Vec<String>> {
+    with_param_structs(|structs| super::find_entry(cx, &structs.borrow(), path))
+        .map(|params| vars(&params))
+}
+
+/// The shader variables of the parameters
+fn vars(params: &[ParamField]) -> Vec<glsl::Var> {
+    params.iter().filter_map(|param| {
+        let (kind, ty, count) = match classify_field(&param.field) {
+            Ok(Param::Uniform) =>
+                (glsl::Kind::Uniform, glsl::uniform_type(value_type(&param.field)), None),
+            Ok(Param::Block) => (glsl::Kind::Block, None, None),
+            Ok(Param::Texture) => (glsl::Kind::Texture, None, None),
+            Err(_) => return None,
+        };
+        Some(glsl::Var {
+            kind: kind,
+            name: param.name.to_string(),
+            ty: ty,
+            count: count,
+        })
+    }).collect()
+}
+
+/// The additional shader names of a field, from its `#[alias = "..."]`
+/// attributes
+fn find_aliases(cx: &mut ext::base::ExtCtxt, field: &ast::StructField) -> Vec<token::InternedString> {
+    field.node.attrs.iter().filter_map(|attribute| match attribute.node.value.node {
+        ast::MetaNameValue(ref name, ref value) if &name[..] == "alias" => {
+            attr::mark_used(attribute);
+            match value.node {
+                ast::LitStr(ref alias, _) => Some(alias.clone()),
+                _ => {
+                    cx.span_err(attribute.span, "`#[alias]` expects a string value");
+                    None
+                },
+            }
+        },
+        _ => None,
+    }).collect()
+}
+
+/// Flatten the struct fields into parameters, inlining the fields of nested
+/// parameter structs and prepending their `#[prefix]` to the variable names.
+/// A field with `#[alias]` names is uploaded under each of them as well.
+/// Names bound twice or not valid in GLSL are reported.
+fn collect_params(cx: &mut ext::base::ExtCtxt, definition: &ast::StructDef,
+                  naming: &super::Naming) -> Vec<ParamField> {
+    let mut params = Vec::new();
+    // shader names along with their field, to report collisions
+    let mut names = Vec::new();
+    for (index, field) in definition.fields.iter().enumerate() {
+        if super::is_skipped(field) {
+            continue
+        }
+        if attr::contains_name(&field.node.attrs, "push_constants") {
+            cx.span_err(field.span, "Push constants can not be bound, as gfx::ParamStorage \
+                                     has no constant ranges and GL has no push constants. \
+                                     Nest the struct as plain parameters, or bind it as \
+                                     a #[uniform_block]");
+            continue
+        }
+        // tuple struct fields are linked as `_0`, `_1`, etc
+        let access = super::FieldAccess::of(field, index);
+        let link_name = match access {
+            super::FieldAccess::Named(ident) => ident.as_str().to_string(),
+            super::FieldAccess::Indexed(index) => format!("_{}", index),
+        };
+        let prefix = super::find_str(cx, field.span, &field.node.attrs, "prefix");
+        let rate = find_rate(cx, field);
+        let aliases = find_aliases(cx, field);
+        match nested_params(cx, field) {
+            Some(_) if !aliases.is_empty() => {
+                cx.span_err(field.span, "`#[alias]` names a single parameter, nested parameter \
+                                         structs can be renamed with a `#[prefix]` only");
+            },
+            Some(nested) => for param in nested.into_iter() {
+                let link = format!("{}_{}", link_name, param.link.as_str());
+                let name = match prefix {
+                    Some(ref prefix) => token::intern_and_get_ident(
+                        &format!("{}{}", prefix, param.name)),
+                    None => param.name,
+                };
+                let mut path = vec![access];
+                path.extend(param.path.into_iter());
+                names.push((name.to_string(), field.span));
+                params.push(ParamField {
+                    field: param.field,
+                    link: cx.ident_of(&link),
+                    path: path,
+                    name: name,
+                    rate: rate.unwrap_or(param.rate),
+                });
+            },
+            None => {
+                if prefix.is_some() {
+                    cx.span_err(field.span, "`#[prefix]` is only allowed on nested parameter \
+                                             structs, which need to be declared with \
+                                             #[shader_param] before use");
+                }
+                if let Some((columns, rows)) = non_square_matrix(value_type(field)) {
+                    cx.span_err(field.span, &format!(
+                        "Non-square matrices are not supported, as gfx::shade::UniformValue \
+                        has square matrices only. Pad the `mat{}x{}` to a square matrix, or \
+                        move it into a #[uniform_block]", columns, rows
+                    ));
+                    continue
+                }
+                // GL reflects an array as a single `name[0]` variable with a
+                // count, while `gfx::ParamStorage` holds a single value per
+                // variable, uploaded at its location. Only the first element
+                // would ever reach the shader.
+                match (classify_field(field), &value_type(field).node) {
+                    (Ok(Param::Uniform), _) if uniform_array_len(value_type(field)).is_some() => {
+                        cx.span_err(field.span, "Uniform arrays are not supported, as \
+                                                 gfx::shade::UniformValue has no array values and \
+                                                 the program reflects an array as a single \
+                                                 variable. Move the array into a #[uniform_block]");
+                        continue
+                    },
+                    (Ok(Param::Texture), &ast::TyFixedLengthVec(..)) => {
+                        cx.span_err(field.span, "Texture arrays are not supported, as the program \
+                                                 reflects a sampler array as a single variable, \
+                                                 bound to a single texture unit. Declare the \
+                                                 samplers one by one, each in a field of its own");
+                        continue
+                    },
+                    _ => (),
+                }
+                let name = match (super::find_name(cx, field.span, &field.node.attrs), access) {
+                    (Some(name), _) => name,
+                    (None, super::FieldAccess::Named(ident)) => naming.name(ident),
+                    (None, super::FieldAccess::Indexed(_)) => {
+                        cx.span_err(field.span, "Tuple struct fields need a `#[name = \"...\"]` \
+                                                 to be used as `ShaderParam`");
+                        continue
+                    },
+                };
+                names.push((name.to_string(), field.span));
+                params.push(ParamField {
+                    field: field.clone(),
+                    link: cx.ident_of(&link_name),
+                    path: vec![access],
+                    name: name.clone(),
+                    rate: rate.unwrap_or(Rate::Object),
+                });
+                // every alias gets a link of its own, uploading the same value
+                for (i, alias) in aliases.into_iter().enumerate() {
+                    if alias == name {
+                        cx.span_err(field.span, &format!("`#[alias]` repeats the name `{}`", name));
+                        continue
+                    }
+                    names.push((alias.to_string(), field.span));
+                    params.push(ParamField {
+                        field: field.clone(),
+                        link: cx.ident_of(&format!("{}_alias{}", link_name, i)),
+                        path: vec![access],
+                        name: alias,
+                        rate: rate.unwrap_or(Rate::Object),
+                    });
+                }
+            },
+        }
+    }
+    glsl::check_names(cx, &names);
+    params
+}
+
 /// Generates the the method body for `gfx::shade::ShaderParam::create_link`
 fn method_create(cx: &mut ext::base::ExtCtxt,
                  span: codemap::Span,
-                 definition: &ast::StructDef,
+                 params: &[ParamField],
                  input: ast::Ident,
                  link_ident: ast::Ident,
                  path_root: ast::Ident)
                  -> P<ast::Block> {
+    // marker-only structs link to a unit struct, as long as the program
+    // has no variables either
+    if params.is_empty() {
+        let link = cx.expr_ident(span, link_ident);
+        return cx.block_expr(quote_expr!(cx, {
+            if let Some(u) = $input.uniforms.first() {
+                return Err($path_root::gfx::shade::ParameterError::MissingUniform(u.name.clone()))
+            }
+            if let Some(b) = $input.blocks.first() {
+                return Err($path_root::gfx::shade::ParameterError::MissingBlock(b.name.clone()))
+            }
+            if let Some(t) = $input.textures.first() {
+                return Err($path_root::gfx::shade::ParameterError::MissingTexture(t.name.clone()))
+            }
+            Ok($link)
+        }))
+    }
     let init_expr = cx.expr_struct_ident(
         span, link_ident,
-        definition.fields.iter().scan((), |_, field| {
-            field.node.ident().map(|name|
-                cx.field_imm(field.span, name, cx.expr_none(field.span))
-            )
-        }).collect()
+        params.iter().map(|param|
+            cx.field_imm(param.field.span, param.link, cx.expr_none(param.field.span))
+        ).collect()
     );
-    let class_info: Vec<(Param, P<ast::Expr>)> = definition.fields.iter().scan((), |_, field|
-        match (field.node.ident(), classify(&field.node.ty.node)) {
-            (None, _) => {
-                cx.span_err(field.span, "Named fields are required for `ShaderParam`");
-                None
-            },
-            (Some(fname), Ok(c)) => {
-                let name = match super::find_name(cx, field.span, &field.node.attrs) {
-                    Some(name) => name,
-                    None => token::get_ident(fname),
-                };
-                Some((c, cx.expr_str(field.span, name)))
-            },
-            (Some(fname), Err(e)) => {
-                cx.span_err(field.span, &format!(
-                    "Unrecognized parameter ({:?}) type {:?}",
-                    fname.as_str(), e
-                ));
-                None
-            },
-        }
+    // unrecognized types are reported along with the link struct
+    let class_info: Vec<Option<Param>> = params.iter().map(|param|
+        classify_field(&param.field).ok()
     ).collect();
     let gen_arms = |ptype: Param, var: ast::Ident| -> Vec<ast::Arm> {
-        class_info.iter().zip(definition.fields.iter())
-                  .filter(|&(&(ref class, _), _)| *class == ptype)
-                  .scan((), |_, (&(_, ref name_expr), field)|
-            field.node.ident().map(|name| quote_arm!(cx,
+        let mut arms = Vec::new();
+        for (&class, param) in class_info.iter().zip(params.iter()) {
+            let (field, name, var_name) = match class {
+                Some(class) if class == ptype =>
+                    (&param.field, param.link, &param.name),
+                _ => continue,
+            };
+            let name_expr = cx.expr_str(field.span, var_name.clone());
+            arms.push(quote_arm!(cx,
                 $name_expr => {out.$name = Some(i as $path_root::gfx::shade::$var)}
-            ))
-        ).collect()
+            ));
+        }
+        arms
     };
     let uniform_arms = gen_arms(Param::Uniform, cx.ident_of("VarUniform"));
     let block_arms = gen_arms(Param::Block, cx.ident_of("VarBlock"));
@@ -133,65 +508,562 @@ fn method_create(cx: &mut ext::base::ExtCtxt,
 /// Generates the the method body for `gfx::shade::ShaderParam::fill_params`
 fn method_fill(cx: &mut ext::base::ExtCtxt,
                span: codemap::Span,
-               definition: &ast::StructDef,
+               params: &[ParamField],
                path_root: ast::Ident)
                -> P<ast::Block> {
-    let max_num = cx.expr_usize(span, definition.fields.len());
-    let mut calls = vec![
-        quote_stmt!(cx, use self::$path_root::gfx::shade::ToUniform;),
-        quote_stmt!(cx, out.uniforms.reserve($max_num);),
-        quote_stmt!(cx, out.blocks.reserve($max_num);),
-        quote_stmt!(cx, out.textures.reserve($max_num);),
-    ];
-    calls.extend(definition.fields.iter().scan((), |_, field| {
-        let name = match field.node.ident() {
-            Some(n) => n,
-            None => {
-                cx.span_err(span, "Named fields are required for `ShaderParam`");
-                return None
+    let mut calls = fill_prelude(cx, span, params.len(), path_root);
+    let self_expr = cx.expr_self(span);
+    calls.extend(fill_stmts(cx, params, self_expr).into_iter());
+    cx.block_all(span, calls, None)
+}
+
+/// The statements preparing `out` for the upload of `count` parameters,
+/// only marking `link` and `out` used when there are none
+fn fill_prelude(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+                count: usize, path_root: ast::Ident) -> Vec<P<ast::Stmt>> {
+    if count == 0 {
+        return vec![quote_stmt!(cx, let _ = (link, out);).unwrap()]
+    }
+    let max_num = cx.expr_usize(span, count);
+    vec![
+        // unused by parameters made of textures and blocks only
+        quote_stmt!(cx,
+            #[allow(unused_imports)] use self::$path_root::gfx::shade::ToUniform;
+        ).unwrap(),
+        quote_stmt!(cx, out.uniforms.reserve($max_num);).unwrap(),
+        quote_stmt!(cx, out.blocks.reserve($max_num);).unwrap(),
+        quote_stmt!(cx, out.textures.reserve($max_num);).unwrap(),
+    ]
+}
+
+/// Collect the parameters of every variant of a `#[shader_param]` enum into
+/// their union, sharing the link of the variables used by several variants,
+/// and generate the `fill_params` body uploading the active variant only
+fn enum_params(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+               enum_ident: ast::Ident, definition: &ast::EnumDef, naming: &super::Naming,
+               path_root: ast::Ident) -> (Vec<ParamField>, P<ast::Block>) {
+    let mut union: Vec<ParamField> = Vec::new();
+    let mut arms = Vec::new();
+    for variant in definition.variants.iter() {
+        let variant_ident = variant.node.name;
+        let variant_path = cx.path(variant.span, vec![enum_ident, variant_ident]);
+        let variant_def = match variant.node.kind {
+            ast::StructVariantKind(ref variant_def) => variant_def,
+            ast::TupleVariantKind(ref args) if args.is_empty() => {
+                // unit variants have no parameters to upload
+                let pat = cx.pat(variant.span, ast::PatEnum(variant_path, None));
+                arms.push(cx.arm(variant.span, vec![pat], quote_expr!(cx, ())));
+                continue
+            },
+            ast::TupleVariantKind(_) => {
+                cx.span_err(variant.span, "Variants of #[shader_param] enums need named \
+                                           fields, like `Variant { color: [f32; 4] }`");
+                continue
+            },
+        };
+        let prefix = variant_ident.as_str().to_ascii_lowercase();
+        let mut stmts = Vec::new();
+        let mut bindings = Vec::new();
+        for mut param in collect_params(cx, variant_def, naming).into_iter() {
+            let shared = union.iter().find(|other| other.name == param.name)
+                              .map(|other| (other.link, classify_field(&other.field).ok()));
+            match shared {
+                Some((link, class)) => {
+                    if class != classify_field(&param.field).ok() {
+                        cx.span_err(param.field.span, &format!(
+                            "Parameter `{}` is of a different kind in another variant", param.name
+                        ));
+                    }
+                    param.link = link;
+                },
+                None => {
+                    param.link = cx.ident_of(&format!("{}_{}", prefix, param.link.as_str()));
+                    union.push(param.clone());
+                },
             }
+            // the value is reached through the `ref` binding of the
+            // variant field holding it
+            let binding = match param.path[0] {
+                super::FieldAccess::Named(ident) => ident,
+                super::FieldAccess::Indexed(_) => continue,
+            };
+            if !bindings.contains(&binding) {
+                bindings.push(binding);
+            }
+            let binding_expr = cx.expr_ident(param.field.span, binding);
+            let base = cx.expr(param.field.span, ast::ExprParen(
+                cx.expr_deref(param.field.span, binding_expr)
+            ));
+            param.path.remove(0);
+            stmts.extend(fill_stmts(cx, &[param], base).into_iter());
+        }
+        let field_pats = bindings.into_iter().map(|binding| codemap::Spanned {
+            node: ast::FieldPat {
+                ident: binding,
+                pat: cx.pat_ident_binding_mode(variant.span, binding,
+                                               ast::BindByRef(ast::MutImmutable)),
+                is_shorthand: true,
+            },
+            span: variant.span,
+        }).collect();
+        let pat = cx.pat(variant.span, ast::PatStruct(variant_path, field_pats, true));
+        arms.push(cx.arm(variant.span, vec![pat], cx.expr_block(cx.block(span, stmts, None))));
+    }
+    let mut calls = fill_prelude(cx, span, union.len(), path_root);
+    let self_expr = cx.expr_deref(span, cx.expr_self(span));
+    calls.push(cx.stmt_expr(cx.expr_match(span, self_expr, arms)));
+    (union, cx.block_all(span, calls, None))
+}
+
+/// Generates the statements uploading every parameter, reached from `base`
+/// through its path
+fn fill_stmts(cx: &mut ext::base::ExtCtxt, params: &[ParamField],
+              base: P<ast::Expr>) -> Vec<P<ast::Stmt>> {
+    params.iter().filter_map(|param| {
+        let field = &param.field;
+        let name = param.link;
+        let access = param.path.iter().fold(base.clone(), |expr, access|
+            access.expr(cx, field.span, expr)
+        );
+        // optional values are only uploaded when present
+        let optional = optional_inner(&field.node.ty).is_some();
+        let value = if optional {
+            quote_expr!(cx, value)
+        } else {
+            access.clone()
         };
-        classify(&field.node.ty.node).ok().map(|param| match param {
-            Param::Uniform => quote_stmt!(cx,
-                link.$name.map_or((), |id| {
-                    out.uniforms[id as usize] = Some(self.$name.to_uniform());
-                })
-            ),
-            Param::Block   => quote_stmt!(cx,
-                link.$name.map_or((), |id| {
-                    out.blocks[id as usize] = Some(self.$name.clone());
-                })
-            ),
-            Param::Texture => quote_stmt!(cx,
-                link.$name.map_or((), |id| {
-                    out.textures[id as usize] = Some(self.$name.clone());
-                })
-            ),
-            Param::Special => quote_stmt!(cx, ()),
+        classify_field(field).ok().map(|class| {
+            let upload = match class {
+                Param::Uniform => quote_expr!(cx,
+                    link.$name.map_or((), |id| {
+                        out.uniforms[id as usize] = Some($value.to_uniform());
+                    })
+                ),
+                Param::Block if is_typed_buffer(&value_type(field).node) => quote_expr!(cx,
+                    link.$name.map_or((), |id| {
+                        out.blocks[id as usize] = Some($value.raw().clone());
+                    })
+                ),
+                Param::Block   => quote_expr!(cx,
+                    link.$name.map_or((), |id| {
+                        out.blocks[id as usize] = Some($value.clone());
+                    })
+                ),
+                Param::Texture => quote_expr!(cx,
+                    link.$name.map_or((), |id| {
+                        out.textures[id as usize] = Some($value.clone());
+                    })
+                ),
+            };
+            cx.stmt_expr(if optional {
+                quote_expr!(cx, if let Some(ref value) = $access { $upload })
+            } else {
+                upload
+            })
         })
-    }));
-    let calls = calls.map_in_place(Option::unwrap);
-    cx.block_all(span, calls, None)
+    }).collect()
 }
 
-/// A helper function that translates variable type (`i32`, `TextureHandle`, etc)
+/// A helper function that translates field type (`i32`, `TextureHandle`, etc)
 /// into the corresponding shader var id type (`VarUniform`, `VarBlock`, or `VarTexture`)
-fn node_to_var_type(cx: &mut ext::base::ExtCtxt,
-                    span: codemap::Span, node: &ast::Ty_,
-                    path_root: ast::Ident) -> P<ast::Ty> {
-    let id = cx.ident_of(match classify(node) {
+fn field_to_var_type(cx: &mut ext::base::ExtCtxt,
+                     span: codemap::Span, field: &ast::StructField,
+                     path_root: ast::Ident) -> P<ast::Ty> {
+    let param = classify_field(field);
+    let problem = match param {
+        Ok(_) => None,
+        Err(ParamError::DeprecatedTexture) =>
+            Some("Use gfx::shade::TextureParam for texture vars instead of gfx::shade::TextureHandle"),
+        Err(ParamError::StandaloneSampler) =>
+            Some("Samplers are bound together with their texture, \
+                  use gfx::shade::TextureParam instead of gfx::SamplerHandle"),
+        Err(ParamError::StorageBuffer) =>
+            Some("Storage buffers can not be bound yet, as gfx::ParamStorage \
+                  has no storage buffer slots. The layout of the element \
+                  type can be checked with #[storage_buffer] on its struct"),
+        Err(ParamError::Double) =>
+            Some("Double precision uniforms (`double`, `dvec` and `dmat`, which need \
+                  GL 4.0) can not be uploaded yet, as gfx::shade::UniformValue has \
+                  no `f64` values. Use `f32` fields, or a uniform block"),
+    };
+    if let Some(problem) = problem {
+        cx.span_err(span, problem);
+        cx.span_note(span, "Parameters are uniforms (scalars, vectors and square matrices, \
+                            optionally in an `Option`), blocks \
+                            (`gfx::RawBufferHandle` or `gfx::BufferHandle`) and textures \
+                            (`gfx::shade::TextureParam`)");
+    }
+    let id = cx.ident_of(match param {
         Ok(Param::Uniform) => "VarUniform",
         Ok(Param::Block)   => "VarBlock",
         Ok(Param::Texture) => "VarTexture",
-        Ok(Param::Special) => return quote_ty!(cx, Option<()>),
-        Err(ParamError::DeprecatedTexture) => {
-            cx.span_err(span, "Use gfx::shade::TextureParam for texture vars instead of gfx::shade::TextureHandle");
-            ""
-        },
+        Err(_) => "",
     });
     quote_ty!(cx, Option<$path_root::gfx::shade::$id>)
 }
 
+/// Extract the `#[default = "..."]` value of a field, parsed as an
+/// expression (`#[default = "32.0"]` or `#[default = "[1.0, 1.0, 1.0, 1.0]"]`).
+fn find_default(cx: &mut ext::base::ExtCtxt,
+                field: &ast::StructField) -> Option<P<ast::Expr>> {
+    field.node.attrs.iter().fold(None, |value, attribute| {
+        match attribute.node.value.node {
+            ast::MetaNameValue(ref name, ref lit) if &name[..] == "default" => {
+                attr::mark_used(attribute);
+                if value.is_some() {
+                    cx.span_warn(attribute.span, "Extra `#[default]` value detected - \
+                                                  ignoring in favour of the first one");
+                    return value
+                }
+                Some(match lit.node {
+                    ast::LitStr(ref source, _) => parse::parse_expr_from_source_str(
+                        "<default>".to_string(), source.to_string(),
+                        cx.cfg(), cx.parse_sess()
+                    ),
+                    ref lit => cx.expr_lit(attribute.span, lit.clone()),
+                })
+            },
+            _ => value,
+        }
+    })
+}
+
+/// The default value of a field without a `#[default]`, spelled out for
+/// arrays and `PhantomData`, which have no `Default` implementation
+fn default_expr(cx: &mut ext::base::ExtCtxt, ty: &ast::Ty) -> P<ast::Expr> {
+    match ty.node {
+        ast::TyFixedLengthVec(ref inner, ref count) => {
+            let value = default_expr(cx, inner);
+            quote_expr!(cx, [$value; $count])
+        },
+        ast::TyPath(_, ref p) if p.segments.last().map_or(false, |segment|
+            segment.identifier.name.as_str() == "PhantomData"
+        ) => quote_expr!(cx, ::std::marker::PhantomData),
+        _ => quote_expr!(cx, ::std::default::Default::default()),
+    }
+}
+
+/// Generates a `Default` implementation from the `#[default]` values of the
+/// fields, unless none are given. Other fields use their own default.
+fn impl_default(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+                item: &ast::Item, definition: &ast::StructDef,
+                generics: &ast::Generics, struct_ty: &P<ast::Ty>) -> Option<P<ast::Item>> {
+    let values: Vec<Option<P<ast::Expr>>> = definition.fields.iter()
+        .map(|field| find_default(cx, field)).collect();
+    if values.iter().all(|value| value.is_none()) {
+        return None
+    }
+    let values: Vec<P<ast::Expr>> = values.into_iter().zip(definition.fields.iter())
+        .map(|(value, field)| value.unwrap_or_else(|| default_expr(cx, &field.node.ty)))
+        .collect();
+    let is_tuple = definition.fields.iter().any(|field| field.node.ident().is_none());
+    let body = if is_tuple {
+        cx.expr_call_ident(span, item.ident, values)
+    } else {
+        let fields = definition.fields.iter().zip(values.into_iter())
+            .map(|(field, value)| cx.field_imm(field.span, field.node.ident().unwrap(), value))
+            .collect();
+        cx.expr_struct_ident(span, item.ident, fields)
+    };
+    let decl = cx.fn_decl(Vec::new(), struct_ty.clone());
+    let method = super::impl_method(cx, span, "default", false, super::no_generics(),
+                                    decl, cx.block_expr(body));
+    let default_path = cx.path_global(span, vec![
+        cx.ident_of("std"),
+        cx.ident_of("default"),
+        cx.ident_of("Default"),
+    ]);
+    Some(cx.item(span, item.ident, Vec::new(), ast::ItemImpl(
+        ast::Unsafety::Normal,
+        ast::ImplPolarity::Positive,
+        generics.clone(),
+        Some(cx.trait_ref(default_path)),
+        struct_ty.clone(),
+        vec![P(method)]
+    )))
+}
+
+/// Generates `PartialEq`, `Eq` and `Hash` implementations over the parameter
+/// values for `#[param_cache]` structs. Uniforms are compared and hashed by
+/// their bytes, so that equality is consistent with the hash, while blocks
+/// and textures are compared by handle and left out of the hash.
+fn impl_cache(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+              item: &ast::Item, params: &[ParamField],
+              generics: &ast::Generics, struct_ty: &P<ast::Ty>) -> Vec<P<ast::Item>> {
+    let bytes_fn = quote_item!(cx,
+        fn bytes<T>(value: &T) -> &[u8] {
+            unsafe {
+                ::std::slice::from_raw_parts(value as *const T as *const u8,
+                                             ::std::mem::size_of::<T>())
+            }
+        }
+    ).unwrap();
+    let mut comparisons = Vec::new();
+    let mut hashes = Vec::new();
+    for param in params.iter() {
+        let class = match classify_field(&param.field) {
+            Ok(class) => class,
+            Err(_) => continue,
+        };
+        let this = param.path.iter().fold(cx.expr_self(span), |expr, access|
+            access.expr(cx, span, expr)
+        );
+        let other = cx.expr_ident(span, cx.ident_of("other"));
+        let other = param.path.iter().fold(other, |expr, access|
+            access.expr(cx, span, expr)
+        );
+        let optional = optional_inner(&param.field.node.ty).is_some();
+        let (compare, hash) = match (class, optional) {
+            (Param::Uniform, false) => (
+                quote_expr!(cx, bytes(&$this) == bytes(&$other)),
+                quote_expr!(cx, bytes(&$this).hash(state)),
+            ),
+            (Param::Uniform, true) => (
+                quote_expr!(cx, match (&$this, &$other) {
+                    (&Some(ref a), &Some(ref b)) => bytes(a) == bytes(b),
+                    (&None, &None) => true,
+                    _ => false,
+                }),
+                quote_expr!(cx, match $this {
+                    Some(ref value) => { true.hash(state); bytes(value).hash(state) },
+                    None => false.hash(state),
+                }),
+            ),
+            (_, _) => (quote_expr!(cx, $this == $other), quote_expr!(cx, ())),
+        };
+        comparisons.push(compare);
+        hashes.push(cx.stmt_expr(hash));
+    }
+    let comparison = comparisons.into_iter().fold(cx.expr_bool(span, true), |all, compare|
+        cx.expr_binary(span, ast::BiAnd, all, compare)
+    );
+    let impl_trait = |cx: &mut ext::base::ExtCtxt, path: Vec<&str>,
+                      methods: Vec<P<ast::ImplItem>>| {
+        let path = cx.path_global(span, path.into_iter().map(|p| cx.ident_of(p)).collect());
+        cx.item(span, item.ident, Vec::new(), ast::ItemImpl(
+            ast::Unsafety::Normal,
+            ast::ImplPolarity::Positive,
+            generics.clone(),
+            Some(cx.trait_ref(path)),
+            struct_ty.clone(),
+            methods
+        ))
+    };
+
+    // `fn eq(&self, other: &Self) -> bool`
+    let decl_eq = cx.fn_decl(vec![
+        ast::Arg::new_self(span, ast::MutImmutable, cx.ident_of("self")),
+        cx.arg(span, cx.ident_of("other"), cx.ty_rptr(span, struct_ty.clone(), None, ast::MutImmutable)),
+    ], quote_ty!(cx, bool));
+    let body_eq = cx.block(span, vec![cx.stmt_item(span, bytes_fn.clone())], Some(comparison));
+    let method_eq = super::impl_method(cx, span, "eq", true, super::no_generics(), decl_eq, body_eq);
+
+    // `fn hash<H: Hasher>(&self, state: &mut H)`
+    let hasher = cx.ident_of("__H");
+    let hasher_bound = cx.typarambound(cx.path_global(span, vec![
+        cx.ident_of("std"),
+        cx.ident_of("hash"),
+        cx.ident_of("Hasher"),
+    ]));
+    let mut hash_generics = super::no_generics();
+    hash_generics.ty_params = OwnedSlice::from_vec(vec![
+        cx.typaram(span, hasher, OwnedSlice::from_vec(vec![hasher_bound]), None)
+    ]);
+    let decl_hash = cx.fn_decl(vec![
+        ast::Arg::new_self(span, ast::MutImmutable, cx.ident_of("self")),
+        cx.arg(span, cx.ident_of("state"), quote_ty!(cx, &mut $hasher)),
+    ], quote_ty!(cx, ()));
+    let mut hash_stmts = vec![
+        cx.stmt_item(span, bytes_fn),
+        quote_stmt!(cx, use std::hash::Hash;).unwrap(),
+    ];
+    hash_stmts.extend(hashes.into_iter());
+    let body_hash = cx.block(span, hash_stmts, None);
+    let method_hash = super::impl_method(cx, span, "hash", true, hash_generics, decl_hash, body_hash);
+
+    vec![
+        impl_trait(cx, vec!["std", "cmp", "PartialEq"], vec![P(method_eq)]),
+        impl_trait(cx, vec!["std", "cmp", "Eq"], Vec::new()),
+        impl_trait(cx, vec!["std", "hash", "Hash"], vec![P(method_hash)]),
+    ]
+}
+
+/// Generates a `Debug` implementation for `#[debug_params]` structs, showing
+/// every parameter by its shader name, as `Name { u_Color: [1, 0, 0, 1] }`.
+/// Nested parameters are shown along with the others, by their prefixed name.
+fn impl_debug(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+              item: &ast::Item, params: &[ParamField],
+              generics: &ast::Generics, struct_ty: &P<ast::Ty>) -> P<ast::Item> {
+    let mut stmts = vec![{
+        let header = cx.expr_str(span, token::intern_and_get_ident(
+            &format!("{} {{{{", item.ident.as_str())));
+        quote_stmt!(cx, try!(write!(f, $header));).unwrap()
+    }];
+    for (i, param) in params.iter().enumerate() {
+        let value = param.path.iter().fold(cx.expr_self(span), |expr, access|
+            access.expr(cx, span, expr)
+        );
+        let line = cx.expr_str(span, token::intern_and_get_ident(&format!(
+            "{} {}: {{:?}}", if i == 0 { "" } else { "," }, param.name
+        )));
+        stmts.push(quote_stmt!(cx, try!(write!(f, $line, &$value));).unwrap());
+    }
+    stmts.push(quote_stmt!(cx, try!(write!(f, " }}"));).unwrap());
+    let decl = cx.fn_decl(vec![
+        ast::Arg::new_self(span, ast::MutImmutable, cx.ident_of("self")),
+        cx.arg(span, cx.ident_of("f"), quote_ty!(cx, &mut ::std::fmt::Formatter)),
+    ], quote_ty!(cx, ::std::fmt::Result));
+    let body = cx.block(span, stmts, Some(quote_expr!(cx, Ok(()))));
+    let method = super::impl_method(cx, span, "fmt", true, super::no_generics(), decl, body);
+    let path = cx.path_global(span, vec![
+        cx.ident_of("std"),
+        cx.ident_of("fmt"),
+        cx.ident_of("Debug"),
+    ]);
+    cx.item(span, item.ident, Vec::new(), ast::ItemImpl(
+        ast::Unsafety::Normal,
+        ast::ImplPolarity::Positive,
+        generics.clone(),
+        Some(cx.trait_ref(path)),
+        struct_ty.clone(),
+        vec![P(method)]
+    ))
+}
+
+/// Generates a `NameBuilder` for `#[builder]` structs, with a setter per
+/// field and a `build()` returning the name of the first missing field.
+/// Fields with a `#[default]` value, optional fields and `PhantomData`
+/// markers may be left unset.
+fn impl_builder(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+                item: &ast::Item, definition: &ast::StructDef,
+                generics: &ast::Generics, struct_ty: &P<ast::Ty>) -> Vec<P<ast::Item>> {
+    if definition.fields.iter().any(|field| field.node.ident().is_none()) {
+        cx.span_err(span, "#[builder] is not supported on tuple structs");
+        return Vec::new()
+    }
+    let builder_ident = cx.ident_of(&format!("{}Builder", item.ident.as_str()));
+    let builder_ty = super::struct_type(cx, span, builder_ident, generics);
+    let builder_def = ast::StructDef {
+        fields: definition.fields.iter().map(|field| {
+            let ty = &field.node.ty;
+            codemap::Spanned {
+                node: ast::StructField_ {
+                    kind: ast::NamedField(field.node.ident().unwrap(), ast::Inherited),
+                    id: ast::DUMMY_NODE_ID,
+                    ty: quote_ty!(cx, Option<$ty>),
+                    attrs: Vec::new(),
+                },
+                span: field.span,
+            }
+        }).collect(),
+        ctor_id: None,
+    };
+    let builder_item = cx.item_struct_poly(span, builder_ident, builder_def, generics.clone())
+                         .map(|mut bitem| {
+        bitem.vis = item.vis;
+        bitem
+    });
+
+    let self_ident = cx.ident_of("self");
+    let mut methods = Vec::new();
+
+    // `pub fn new() -> NameBuilder`
+    let unset = definition.fields.iter().map(|field|
+        cx.field_imm(field.span, field.node.ident().unwrap(), quote_expr!(cx, None))
+    ).collect();
+    let body_new = cx.block_expr(cx.expr_struct_ident(span, builder_ident, unset));
+    let decl_new = cx.fn_decl(Vec::new(), builder_ty.clone());
+    methods.push(super::impl_method(cx, span, "new", false, super::no_generics(),
+                                    decl_new, body_new));
+
+    // `pub fn field(mut self, value: T) -> NameBuilder`
+    for field in definition.fields.iter() {
+        let ident = field.node.ident().unwrap();
+        let name = ident.name.as_str();
+        if name == "new" || name == "build" {
+            cx.span_err(field.span, &format!("Field `{}` collides with the builder method \
+                                              of the same name", name));
+            continue
+        }
+        let decl_set = cx.fn_decl(vec![
+            ast::Arg::new_self(span, ast::MutMutable, self_ident),
+            cx.arg(span, cx.ident_of("value"), field.node.ty.clone()),
+        ], builder_ty.clone());
+        let this = cx.expr_field_access(span, cx.expr_self(span), ident);
+        let body_set = cx.block(span, vec![
+            quote_stmt!(cx, $this = Some(value);).unwrap(),
+        ], Some(cx.expr_self(span)));
+        methods.push(by_value(super::impl_method(cx, span, name, true,
+                                                 super::no_generics(), decl_set, body_set)));
+    }
+
+    // `pub fn build(self) -> Result<Name, &'static str>`
+    let values = definition.fields.iter().map(|field| {
+        let ident = field.node.ident().unwrap();
+        let name = cx.expr_str(field.span, token::get_ident(ident));
+        let this = cx.expr_field_access(span, cx.expr_self(span), ident);
+        let is_phantom = super::is_skipped(field) &&
+                         !attr::contains_name(&field.node.attrs, "skip");
+        let value = match find_default(cx, field) {
+            Some(default) => quote_expr!(cx, $this.unwrap_or_else(|| $default)),
+            None if optional_inner(&field.node.ty).is_some() =>
+                quote_expr!(cx, $this.unwrap_or(None)),
+            None if is_phantom =>
+                quote_expr!(cx, $this.unwrap_or(::std::marker::PhantomData)),
+            None => quote_expr!(cx, match $this {
+                Some(value) => value,
+                None => return Err($name),
+            }),
+        };
+        cx.field_imm(field.span, ident, value)
+    }).collect();
+    let result = cx.expr_ok(span, cx.expr_struct_ident(span, item.ident, values));
+    let decl_build = cx.fn_decl(vec![
+        ast::Arg::new_self(span, ast::MutImmutable, self_ident),
+    ], quote_ty!(cx, Result<$struct_ty, &'static str>));
+    methods.push(by_value(super::impl_method(cx, span, "build", true, super::no_generics(),
+                                             decl_build, cx.block_expr(result))));
+    let attrs = vec![super::allow_dead_code(cx, span)];
+    let builder_impl = cx.item(span, builder_ident, attrs, ast::ItemImpl(
+        ast::Unsafety::Normal,
+        ast::ImplPolarity::Positive,
+        generics.clone(),
+        None,
+        builder_ty.clone(),
+        methods.into_iter().map(|mut method| {
+            method.vis = ast::Public;
+            P(method)
+        }).collect()
+    ));
+
+    // `pub fn builder() -> NameBuilder` on the parameter struct
+    let decl_builder = cx.fn_decl(Vec::new(), builder_ty.clone());
+    let body_builder = cx.block_expr(quote_expr!(cx, $builder_ident::new()));
+    let mut method_builder = super::impl_method(cx, span, "builder", false, super::no_generics(),
+                                                decl_builder, body_builder);
+    method_builder.vis = ast::Public;
+    let attrs = vec![super::allow_dead_code(cx, span)];
+    let struct_impl = cx.item(span, item.ident, attrs, ast::ItemImpl(
+        ast::Unsafety::Normal,
+        ast::ImplPolarity::Positive,
+        generics.clone(),
+        None,
+        struct_ty.clone(),
+        vec![P(method_builder)]
+    ));
+    vec![builder_item, builder_impl, struct_impl]
+}
+
+/// Turn a `&self` method into one taking `self` by value
+fn by_value(mut method: ast::ImplItem) -> ast::ImplItem {
+    if let ast::MethodImplItem(ref mut sig, _) = method.node {
+        if let ast::SelfRegion(_, _, ident) = sig.explicit_self.node {
+            sig.explicit_self.node = ast::SelfValue(ident);
+        }
+    }
+    method
+}
+
 fn impl_type(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
              name: &str, type_ident: ast::Ident) -> ast::ImplItem {
     ast::ImplItem {
@@ -204,40 +1076,11 @@ fn impl_type(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
     }
 }
 
-fn impl_method(cx: &mut ext::base::ExtCtxt, span: codemap::Span, name: &str,
-               with_self: bool, declaration: P<ast::FnDecl>, body: P<ast::Block>)
-               -> ast::ImplItem {
-    ast::ImplItem {
-        id: ast::DUMMY_NODE_ID,
-        ident: cx.ident_of(name),
-        vis: ast::Visibility::Inherited,
-        attrs: Vec::new(),
-        node: ast::MethodImplItem(
-            ast::MethodSig {
-                unsafety: ast::Unsafety::Normal,
-                abi: abi::Abi::Rust,
-                decl: declaration,
-                generics: ast::Generics {
-                    lifetimes: Vec::new(),
-                    ty_params: OwnedSlice::empty(),
-                    where_clause: ast::WhereClause {
-                        id: ast::DUMMY_NODE_ID,
-                        predicates: Vec::new()
-                    }
-                },
-                explicit_self: codemap::Spanned {
-                    node: if with_self {
-                        ast::SelfRegion(None, ast::MutImmutable, cx.ident_of("self"))
-                    } else {
-                        ast::SelfStatic
-                    },
-                    span: span
-                }
-            },
-            body
-        ),
-        span: span
-    }
+/// Check whether the struct is also a vertex format, expanded by the
+/// `VertexFormat` decorator alongside this one
+fn is_vertex_format(attributes: &[ast::Attribute]) -> bool {
+    attr::contains_name(attributes, "vertex_format_impl") ||
+    attr::contains_name(attributes, "derive_VertexFormat")
 }
 
 #[derive(Copy, Clone)]
@@ -247,32 +1090,51 @@ impl ItemDecorator for ShaderParam {
     fn expand(&self, context: &mut ext::base::ExtCtxt, span: codemap::Span,
               meta_item: &ast::MetaItem, item: &ast::Item,
               push: &mut FnMut(P<ast::Item>)) {
-        // Insert the `gfx` reexport module
+        let item = &super::strip_unconfigured(context, item);
+        // Insert the `gfx` reexport module, unless a `#[gfx_crate]` path is given
         let extern_hack = context.ident_of(super::EXTERN_CRATE_HACK);
-        let path_root = super::extern_crate_hack(context, span, |i| (*push)(i));
+        let crate_root = super::crate_root(context, span, &item.attrs, |i| (*push)(i));
+        let path_root = crate_root.ident;
 
-        // constructing the Link struct
-        let (base_def, generics, link_def) = match item.node {
+        // constructing the Link struct, out of the fields of a struct or
+        // of all the variants of an enum
+        let naming = super::Naming::of(context, span, &item.attrs);
+        let (base_def, generics, params, body_fill) = match item.node {
             ast::ItemStruct(ref definition, ref generics) => {
-                (definition, generics.clone(), ast::StructDef {
-                    fields: definition.fields.iter()
-                        .map(|f| codemap::Spanned {
-                            node: ast::StructField_ {
-                                kind: f.node.kind,
-                                id: f.node.id,
-                                ty: node_to_var_type(context, f.span, &f.node.ty.node, path_root),
-                                attrs: Vec::new(),
-                            },
-                            span: f.span,
-                        }).collect(),
-                    ctor_id: None,
-                })
+                let params = collect_params(context, definition, &naming);
+                let key = super::item_key(context, item.ident);
+                with_param_structs(|structs| structs.borrow_mut().insert(key, params.clone()));
+                let body_fill = method_fill(context, span, &params, path_root);
+                (Some(definition), generics.clone(), params, body_fill)
+            },
+            ast::ItemEnum(ref definition, ref generics) => {
+                let (params, body_fill) = enum_params(context, span, item.ident,
+                                                      definition, &naming, path_root);
+                (None, generics.clone(), params, body_fill)
             },
             _ => {
-                context.span_err(span, "Only free-standing named structs allowed to derive ShaderParam");
+                context.span_err(span, "Only free-standing structs and enums allowed to \
+                                        derive ShaderParam");
                 return;
             }
         };
+        // a unit struct without parameters, as empty braces are not allowed
+        let link_def = ast::StructDef {
+            fields: params.iter()
+                .map(|param| codemap::Spanned {
+                    node: ast::StructField_ {
+                        kind: match param.field.node.kind {
+                            ast::NamedField(_, vis) | ast::UnnamedField(vis) =>
+                                ast::NamedField(param.link, vis),
+                        },
+                        id: ast::DUMMY_NODE_ID,
+                        ty: field_to_var_type(context, param.field.span, &param.field, path_root),
+                        attrs: Vec::new(),
+                    },
+                    span: param.field.span,
+                }).collect(),
+            ctor_id: if params.is_empty() { Some(ast::DUMMY_NODE_ID) } else { None },
+        };
 
         // derive and push
         let link_name = format!("_{}Link", item.ident.as_str());
@@ -289,7 +1151,7 @@ impl ItemDecorator for ShaderParam {
             litem.vis = item.vis;
             litem
         });
-        (*push)(link_item);
+        (*push)(super::fixup_extern_crate_paths(link_item, &crate_root));
 
         // process meta parameters (expecting none)
         match meta_item.node {
@@ -299,13 +1161,24 @@ impl ItemDecorator for ShaderParam {
             }
         }
 
-        // find the generic `Resources` bound
+        // find the generic `Resources` bound, inline or in the where clause
+        let is_resources = |b: &ast::TyParamBound| match *b {
+            ast::TraitTyParamBound(ref poly_trait, _) =>
+                poly_trait.trait_ref.path.segments.last().unwrap().identifier.as_str() == "Resources",
+            ast::RegionTyParamBound(_) => false,
+        };
         let resource_ident = match generics.ty_params.iter().find(|typ|
-            typ.bounds.iter().find(|b| match **b {
-                ast::TraitTyParamBound(ref poly_trait, _) =>
-                    poly_trait.trait_ref.path.segments.last().unwrap().identifier.as_str() == "Resources",
-                ast::RegionTyParamBound(_) => false,
-            }).is_some()
+            typ.bounds.iter().any(|b| is_resources(b)) ||
+            generics.where_clause.predicates.iter().any(|predicate| match *predicate {
+                ast::WherePredicate::BoundPredicate(ref bound) => match bound.bounded_ty.node {
+                    ast::TyPath(None, ref path) =>
+                        path.segments.len() == 1 &&
+                        path.segments[0].identifier.name == typ.ident.name &&
+                        bound.bounds.iter().any(|b| is_resources(b)),
+                    _ => false,
+                },
+                _ => false,
+            })
         ){
             Some(typ) => typ.ident,
             None => {
@@ -315,19 +1188,9 @@ impl ItemDecorator for ShaderParam {
         };
 
         // construct `create_link()`
-        let lifetimes = generics.lifetimes.iter().map(|ld| ld.lifetime).collect();
-        let generic_parameters = generics.ty_params.iter().map(|ty|
-            context.ty_ident(span, ty.ident)
-        ).collect();
-        let struct_ty = context.ty_path(context.path_all(
-            span, false,
-            vec![item.ident],
-            lifetimes,
-            generic_parameters,
-            Vec::new(),
-        ));
+        let struct_ty = super::struct_type(context, span, item.ident, &generics);
         let create_param = context.ident_of("params");
-        let body_create = method_create(context, span, base_def, create_param, link_ident, path_root);
+        let body_create = method_create(context, span, &params, create_param, link_ident, path_root);
         let decl_create = context.fn_decl(
             vec![
                 ast::Arg {
@@ -351,7 +1214,6 @@ impl ItemDecorator for ShaderParam {
         );
 
         // construct `fill_params()`
-        let body_fill = method_fill(context, span, base_def, path_root);
         let decl_fill = context.fn_decl(
              vec![
                 ast::Arg::new_self(span, ast::MutImmutable, context.ident_of("self")),
@@ -369,14 +1231,130 @@ impl ItemDecorator for ShaderParam {
             quote_ty!(context, ())
         );
 
+        // `pub fn fill_frame_params()` and `pub fn fill_object_params()`,
+        // filling the parameters of one rate only
+        if base_def.is_none() && params.iter().any(|param| param.rate == Rate::Frame) {
+            context.span_err(span, "`#[rate]` is only supported on structs");
+        } else if params.iter().any(|param| param.rate == Rate::Frame) {
+            let methods = [("fill_frame_params", Rate::Frame), ("fill_object_params", Rate::Object)]
+                .iter().map(|&(name, rate)| {
+                    let rate_params: Vec<ParamField> = params.iter()
+                        .filter(|param| param.rate == rate).cloned().collect();
+                    let body = method_fill(context, span, &rate_params, path_root);
+                    let mut method = super::impl_method(context, span, name, true,
+                                                        super::no_generics(), decl_fill.clone(), body);
+                    method.vis = ast::Public;
+                    P(method)
+                }).collect();
+            let attrs = vec![super::allow_dead_code(context, span)];
+            let rate_impl = context.item(span, item.ident, attrs, ast::ItemImpl(
+                ast::Unsafety::Normal,
+                ast::ImplPolarity::Positive,
+                generics.clone(),
+                None,
+                struct_ty.clone(),
+                methods
+            ));
+            (*push)(super::fixup_extern_crate_paths(rate_impl, &crate_root));
+        }
+
+        // `pub fn relink(&mut self, program: &gfx::ProgramHandle<R>)` on the
+        // link for `#[relink]`, to follow shader reloads without a new batch
+        if attr::contains_name(&item.attrs, "relink") {
+            let program = context.ident_of("program");
+            let decl_relink = context.fn_decl(
+                vec![
+                    ast::Arg::new_self(span, ast::MutImmutable, context.ident_of("self")),
+                    context.arg(span, program,
+                        quote_ty!(context, &$path_root::gfx::ProgramHandle<$resource_ident>)),
+                ],
+                quote_ty!(context, Result<(), $path_root::gfx::shade::ParameterError>)
+            );
+            let body_relink = quote_expr!(context, {
+                *self = try!(<$struct_ty as $path_root::gfx::shade::ShaderParam>::create_link(
+                    None, $program.get_info()));
+                Ok(())
+            });
+            let mut method = super::mut_self(super::impl_method(context, span, "relink", true,
+                                                                generics.clone(), decl_relink,
+                                                                context.block_expr(body_relink)));
+            method.vis = ast::Public;
+            let attrs = vec![super::allow_dead_code(context, span)];
+            let relink_impl = context.item(span, link_ident, attrs, ast::ItemImpl(
+                ast::Unsafety::Normal,
+                ast::ImplPolarity::Positive,
+                super::no_generics(),
+                None,
+                context.ty_ident(span, link_ident),
+                vec![P(method)]
+            ));
+            (*push)(super::fixup_extern_crate_paths(relink_impl, &crate_root));
+        }
+
         // construct implementations for types and methods
         let impls = vec![
             P(impl_type(context, span, "Resources", resource_ident)),
             P(impl_type(context, span, "Link", link_ident)),
-            P(impl_method(context, span, "create_link", false, decl_create, body_create)),
-            P(impl_method(context, span, "fill_params", true, decl_fill, body_fill))
+            P(super::impl_method(context, span, "create_link", false,
+                                 super::no_generics(), decl_create, body_create)),
+            P(super::impl_method(context, span, "fill_params", true,
+                                 super::no_generics(), decl_fill, body_fill))
         ];
 
+        // `Default` implementation from the `#[default]` values
+        match base_def.and_then(|base_def|
+            impl_default(context, span, item, base_def, &generics, &struct_ty)
+        ) {
+            Some(default_item) => (*push)(default_item),
+            None => (),
+        }
+
+        // `PartialEq`, `Eq` and `Hash` implementations for `#[param_cache]`
+        if attr::contains_name(&item.attrs, "param_cache") {
+            if base_def.is_none() {
+                context.span_err(span, "#[param_cache] is only supported on structs");
+            } else {
+                for cache_item in impl_cache(context, span, item, &params, &generics, &struct_ty) {
+                    (*push)(cache_item);
+                }
+            }
+        }
+
+        // `Debug` implementation by shader names for `#[debug_params]`
+        if attr::contains_name(&item.attrs, "debug_params") {
+            match base_def {
+                Some(_) => (*push)(impl_debug(context, span, item, &params, &generics, &struct_ty)),
+                None => context.span_err(span, "#[debug_params] is only supported on structs"),
+            }
+        }
+
+        // `pub fn glsl_decl() -> &'static str` for `#[emit_glsl_decl]`, called
+        // `glsl_uniform_decl()` when the vertex format has taken the name
+        if attr::contains_name(&item.attrs, "emit_glsl_decl") {
+            let name = if is_vertex_format(&item.attrs) { "glsl_uniform_decl" } else { "glsl_decl" };
+            let method = glsl::decl_method(context, span, name, &vars(&params));
+            let attrs = vec![super::allow_dead_code(context, span)];
+            (*push)(context.item(span, item.ident, attrs, ast::ItemImpl(
+                ast::Unsafety::Normal,
+                ast::ImplPolarity::Positive,
+                generics.clone(),
+                None,
+                struct_ty.clone(),
+                vec![method]
+            )));
+        }
+
+        // `NameBuilder` for `#[builder]`
+        if attr::contains_name(&item.attrs, "builder") {
+            match base_def {
+                Some(base_def) => for builder_item in impl_builder(context, span, item, base_def,
+                                                                   &generics, &struct_ty) {
+                    (*push)(builder_item);
+                },
+                None => context.span_err(span, "#[builder] is only supported on structs"),
+            }
+        }
+
         // final implementation item
         let item = context.item(span, item.ident, Vec::new(), ast::Item_::ItemImpl(
                 ast::Unsafety::Normal,
@@ -391,6 +1369,21 @@ impl ItemDecorator for ShaderParam {
                 struct_ty,
                 impls
         ));
-        (*push)(super::fixup_extern_crate_paths(item, path_root));
+        (*push)(super::fixup_extern_crate_paths(item, &crate_root));
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct ComputeParam;
+impl ItemDecorator for ComputeParam {
+    /// Reject `#[compute_param]`, which would otherwise be accepted as an
+    /// unknown custom attribute without generating anything
+    fn expand(&self, context: &mut ext::base::ExtCtxt, span: codemap::Span,
+              _meta_item: &ast::MetaItem, _item: &ast::Item,
+              _push: &mut FnMut(P<ast::Item>)) {
+        context.span_err(span, "Compute parameters are not supported: gfx has no compute \
+                                pipeline yet, `gfx::ParamStorage` only fills the uniforms, \
+                                blocks and textures of a raster program, and there is no \
+                                image binding or dispatch to generate code for");
     }
 }
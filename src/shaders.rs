@@ -0,0 +1,1139 @@
+// Copyright 2015 The Gfx-rs Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ascii::AsciiExt;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use syntax::{ast, codemap, ext};
+use syntax::ext::base::{DummyResult, ItemDecorator, MacEager, MacResult};
+use syntax::ext::build::AstBuilder;
+use syntax::parse::attr::ParserAttr;
+use syntax::parse::{parser, token};
+use syntax::print::pprust;
+use syntax::ptr::P;
+use syntax::util::small_vector::SmallVector;
+
+use glsl;
+use shader_param;
+use vertex_format;
+
+/// GLSL versions carried by `gfx::ShaderSource`, in field order, which their
+/// names compare in as well
+static VERSIONS: &'static [&'static str] = &["glsl_120", "glsl_130", "glsl_140", "glsl_150",
+                                             "glsl_430"];
+
+/// Shader stages, along with the first GLSL version supporting them
+static STAGES: &'static [(&'static str, &'static str)] = &[
+    ("vertex", "glsl_120"),
+    ("tess_control", "glsl_400"),
+    ("tess_evaluation", "glsl_400"),
+    ("geometry", "glsl_150"),
+    ("fragment", "glsl_120"),
+];
+
+/// Sampler types of GLSL 1.20, along with the prefix of their lookup
+/// functions, which is followed by `Proj`, `Lod` or `ProjLod` like the GLSL
+/// 1.50 functions are
+static SAMPLERS_120: &'static [(&'static str, &'static str)] = &[
+    ("sampler1D", "texture1D"),
+    ("sampler2D", "texture2D"),
+    ("sampler3D", "texture3D"),
+    ("samplerCube", "textureCube"),
+    ("sampler1DShadow", "shadow1D"),
+    ("sampler2DShadow", "shadow2D"),
+];
+
+/// Texture functions of GLSL 1.30 and later that GLSL 1.20 has no
+/// counterpart of
+static TEXTURE_FUNCTIONS_130: &'static [&'static str] = &[
+    "texelFetch", "texelFetchOffset", "textureGather", "textureGatherOffset",
+    "textureGatherOffsets", "textureGrad", "textureGradOffset", "textureLodOffset",
+    "textureOffset", "textureProjGrad", "textureProjGradOffset", "textureProjLodOffset",
+    "textureProjOffset", "textureQueryLevels", "textureQueryLod", "textureSize",
+];
+
+/// Access the values of the `#[shader_constant]` items expanded so far, by
+/// `item_key`
+fn with_constants<T, F>(f: F) -> T where F: FnOnce(&RefCell<HashMap<String, String>>) -> T {
+    thread_local!(
+        static CONSTANTS: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new())
+    );
+    CONSTANTS.with(f)
+}
+
+/// Forget the constants expanded so far
+pub fn clear_registry() {
+    with_constants(|constants| constants.borrow_mut().clear());
+}
+
+/// Extract the GLSL text of a literal constant value
+fn constant_text(expr: &ast::Expr) -> Option<String> {
+    match expr.node {
+        ast::ExprLit(ref lit) => match lit.node {
+            ast::LitInt(value, _) => Some(value.to_string()),
+            ast::LitFloat(ref value, _) | ast::LitFloatUnsuffixed(ref value) =>
+                Some(value.to_string()),
+            ast::LitBool(value) => Some(value.to_string()),
+            _ => None,
+        },
+        ast::ExprUnary(ast::UnNeg, ref inner) => constant_text(inner).map(|text| format!("-{}", text)),
+        ast::ExprParen(ref inner) => constant_text(inner),
+        _ => None,
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct ShaderConstant;
+
+impl ItemDecorator for ShaderConstant {
+    /// Record the value of a `const` or `static` item, so that shader sources
+    /// can refer to it as `$NAME`
+    fn expand(&self, context: &mut ext::base::ExtCtxt, span: codemap::Span,
+              _meta_item: &ast::MetaItem, item: &ast::Item,
+              _push: &mut FnMut(P<ast::Item>)) {
+        let expr = match item.node {
+            ast::ItemConst(_, ref expr) |
+            ast::ItemStatic(_, ast::MutImmutable, ref expr) => expr,
+            _ => {
+                context.span_err(span, "Only constants can be annotated with #[shader_constant]");
+                return
+            }
+        };
+        match constant_text(expr) {
+            Some(text) => {
+                let key = super::item_key(context, item.ident);
+                with_constants(|constants| constants.borrow_mut().insert(key, text))
+            },
+            None => {
+                context.span_err(expr.span, "Shader constants need a literal value");
+                return
+            },
+        };
+    }
+}
+
+/// Replace the `$NAME` references to `#[shader_constant]` items by their value
+fn substitute(cx: &mut ext::base::ExtCtxt, span: codemap::Span, line: &str) -> String {
+    let mut text = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find('$') {
+        text.push_str(&rest[..start]);
+        let name_start = start + 1;
+        let name_end = rest[name_start..].find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                                         .map_or(rest.len(), |end| name_start + end);
+        let name = &rest[name_start..name_end];
+        let path = cx.path_ident(span, cx.ident_of(name));
+        match with_constants(|constants| super::find_entry(cx, &constants.borrow(), &path)) {
+            Ok(value) => text.push_str(&value),
+            Err(candidates) => {
+                if candidates.is_empty() {
+                    cx.span_err(span, &format!(
+                        "Unknown shader constant `${}`, constants need to be declared \
+                        with #[shader_constant] before use", name
+                    ));
+                } else {
+                    cx.span_err(span, &super::entry_error(&path, "`#[shader_constant]` item",
+                                                          "use", &candidates));
+                }
+                text.push_str(&rest[start..name_end]);
+            },
+        }
+        rest = &rest[name_end..];
+    }
+    text.push_str(rest);
+    text
+}
+
+/// Options given as attributes at the start of the `shaders!` invocation
+struct Options {
+    /// `#[validate]`: check the structure of the sources at expansion time
+    validate: bool,
+    /// `#[stage = "..."]`: the stage the sources are written for, as an
+    /// index into `STAGES`
+    stage: Option<usize>,
+    /// `#[strip]`: remove comments and redundant whitespace, keeping the
+    /// line numbers intact with `#[strip = "keep_lines"]`
+    strip: Option<bool>,
+    /// `#[downgrade]`: derive a missing `glsl_120` source from the
+    /// `glsl_150` one, at the span of the attribute
+    downgrade: Option<codemap::Span>,
+}
+
+/// A problem found in a shader source, at the given byte offset
+struct Problem {
+    offset: usize,
+    message: String,
+}
+
+/// Check the structure of a GLSL source: comments are terminated, brackets
+/// are balanced, statements before a `}` end with `;`, and the `#version`
+/// directive comes first and matches the `target` version, if any.
+fn validate(text: &str, target: Option<&str>) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let bytes = text.as_bytes();
+    let mut brackets: Vec<(char, usize)> = Vec::new();
+    let mut last = None;
+    let mut line_start = true;
+    let mut seen_code = false;
+    let mut seen_version = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i] as char;
+        let next = bytes.get(i + 1).map(|b| *b as char);
+        if c == '/' && next == Some('/') {
+            i = text[i..].find('\n').map_or(bytes.len(), |end| i + end);
+            continue
+        }
+        if c == '/' && next == Some('*') {
+            match text[i + 2 ..].find("*/") {
+                Some(end) => i += end + 4,
+                None => {
+                    problems.push(Problem {
+                        offset: i,
+                        message: "Unterminated comment".to_string(),
+                    });
+                    break
+                },
+            }
+            continue
+        }
+        if c == '#' && line_start {
+            let end = text[i..].find('\n').map_or(bytes.len(), |end| i + end);
+            let directive = text[i..end].trim();
+            if directive.starts_with("#version") {
+                seen_version = true;
+                if seen_code {
+                    problems.push(Problem {
+                        offset: i,
+                        message: "`#version` has to be the first directive".to_string(),
+                    });
+                }
+                let number = directive["#version".len()..].split(' ')
+                                                          .filter(|s| !s.is_empty())
+                                                          .next().unwrap_or("");
+                match target {
+                    Some(target) if &target["glsl_".len()..] != number => {
+                        problems.push(Problem {
+                            offset: i,
+                            message: format!("`{}` source declares `#version {}`", target, number),
+                        });
+                    },
+                    _ => (),
+                }
+            }
+            seen_code = true;
+            i = end;
+            continue
+        }
+        match c {
+            '\n' => line_start = true,
+            '{' | '(' | '[' => brackets.push((c, i)),
+            '}' | ')' | ']' => {
+                let open = match c { '}' => '{', ')' => '(', _ => '[' };
+                match brackets.pop() {
+                    Some((opened, _)) if opened == open => (),
+                    Some((opened, at)) => problems.push(Problem {
+                        offset: i,
+                        message: format!("Mismatched `{}`, `{}` opened on line {} is not closed",
+                                         c, opened, line_of(text, at)),
+                    }),
+                    None => problems.push(Problem {
+                        offset: i,
+                        message: format!("Unmatched `{}`", c),
+                    }),
+                }
+                match last {
+                    Some(prev) if c == '}' && ![';', '{', '}'].contains(&prev) => {
+                        problems.push(Problem {
+                            offset: i,
+                            message: "Expected `;` before `}`".to_string(),
+                        });
+                    },
+                    _ => (),
+                }
+            },
+            _ => (),
+        }
+        if !c.is_whitespace() {
+            last = Some(c);
+            line_start = false;
+            seen_code = true;
+        }
+        i += 1;
+    }
+    for (opened, at) in brackets.into_iter() {
+        problems.push(Problem {
+            offset: at,
+            message: format!("Unclosed `{}`", opened),
+        });
+    }
+    match target {
+        Some(target) if !seen_version && target != "glsl_120" => problems.push(Problem {
+            offset: 0,
+            message: format!("Missing `#version {}` directive", &target["glsl_".len()..]),
+        }),
+        _ => (),
+    }
+    problems
+}
+
+/// The line number of the given byte offset, starting from 1
+fn line_of(text: &str, offset: usize) -> usize {
+    text[..offset].split('\n').count()
+}
+
+/// The byte offset of the source text within the literal at `span`, if the
+/// literal contains the text verbatim (without escapes)
+fn literal_offset(cx: &ext::base::ExtCtxt, span: codemap::Span, text: &str) -> Option<usize> {
+    let snippet = match cx.codemap().span_to_snippet(span) {
+        Ok(snippet) => snippet,
+        Err(_) => return None,
+    };
+    let start = match snippet.find('"') {
+        Some(quote) => quote + 1,
+        None => return None,
+    };
+    let hashes = snippet[..start].chars().filter(|&c| c == '#').count();
+    if snippet.len() < start + hashes + 1 {
+        return None
+    }
+    let end = snippet.len() - hashes - 1;
+    if &snippet[start..end] == text { Some(start) } else { None }
+}
+
+/// Report the problems found in a source, pointing inside the inline literal
+/// when possible, or mentioning the file and line otherwise
+fn report(cx: &mut ext::base::ExtCtxt, span: codemap::Span, text: &str,
+          file: Option<&Path>, problems: Vec<Problem>) {
+    let base = match file {
+        Some(_) => None,
+        None => literal_offset(cx, span, text),
+    };
+    for problem in problems {
+        let line = line_of(text, problem.offset);
+        match (file, base) {
+            (Some(path), _) => cx.span_err(span, &format!(
+                "{}:{}: {}", path.display(), line, problem.message
+            )),
+            (None, Some(base)) => {
+                let lo = span.lo + codemap::BytePos((base + problem.offset) as u32);
+                cx.span_err(codemap::mk_sp(lo, lo + codemap::BytePos(1)), &problem.message)
+            },
+            (None, None) => cx.span_err(span, &format!(
+                "line {}: {}", line, problem.message
+            )),
+        }
+    }
+}
+
+/// Include state shared by the preprocessing of a whole source
+struct Includes {
+    /// Files currently being included, used to detect cycles
+    stack: Vec<PathBuf>,
+    /// Files guarded by `#pragma once` that were already included
+    once: Vec<PathBuf>,
+}
+
+/// Resolve `#include "file"` directives recursively, and substitute the
+/// `$NAME` constants. Included paths are relative to the including file, or
+/// to the crate root for inline sources.
+fn preprocess(cx: &mut ext::base::ExtCtxt, span: codemap::Span, source: Vec<u8>,
+              file: Option<PathBuf>, target: Option<&str>, options: &Options,
+              includes: &mut Includes) -> Option<String> {
+    let text = match String::from_utf8(source) {
+        Ok(text) => text,
+        Err(_) => {
+            cx.span_err(span, "Shader source is not valid UTF-8");
+            return None
+        },
+    };
+    if options.validate {
+        let problems = validate(&text, target);
+        report(cx, span, &text, file.as_ref().map(|file| &**file), problems);
+    }
+    let dir = match file {
+        Some(ref file) => file.parent().map_or(PathBuf::new(), |dir| dir.to_path_buf()),
+        None => super::resolve_path(cx, span, ""),
+    };
+    if let Some(ref file) = file {
+        includes.stack.push(file.clone());
+    }
+    let mut lines = Vec::new();
+    for line in text.split('\n') {
+        let directive = line.trim();
+        if directive == "#pragma once" {
+            if let Some(ref file) = file {
+                includes.once.push(file.clone());
+            }
+            continue
+        }
+        if !directive.starts_with("#include") {
+            lines.push(substitute(cx, span, line));
+            continue
+        }
+        let name = directive["#include".len()..].trim();
+        if name.len() < 2 || !name.starts_with("\"") || !name.ends_with("\"") {
+            cx.span_err(span, &format!("Malformed shader include: `{}`", directive));
+            continue
+        }
+        let path = dir.join(&name[1 .. name.len() - 1]);
+        if includes.once.contains(&path) {
+            continue
+        }
+        if includes.stack.contains(&path) {
+            cx.span_err(span, &format!(
+                "Cyclic shader include of `{}`", path.display()
+            ));
+            continue
+        }
+        let included = super::read_file(cx, span, &path).and_then(|bytes|
+            preprocess(cx, span, bytes, Some(path), None, options, includes)
+        );
+        if let Some(included) = included {
+            lines.push(included.trim_right_matches('\n').to_string());
+        }
+    }
+    if file.is_some() {
+        includes.stack.pop();
+    }
+    Some(lines.connect("\n"))
+}
+
+/// Extract the shader source out of a `b"..."` or `"..."` literal, or
+/// out of a `file("...")` reference, and preprocess it
+fn source_bytes(cx: &mut ext::base::ExtCtxt, expr: &ast::Expr, target: &str,
+                options: &Options) -> Option<Vec<u8>> {
+    let source = match expr.node {
+        ast::ExprLit(ref lit) => match lit.node {
+            ast::LitBinary(ref bytes) => Some(((**bytes).clone(), None)),
+            ast::LitStr(ref s, _) => Some((s.as_bytes().to_vec(), None)),
+            _ => None,
+        },
+        ast::ExprCall(ref fun, ref args) if args.len() == 1 => match (&fun.node, &args[0].node) {
+            (&ast::ExprPath(None, ref path), &ast::ExprLit(ref lit))
+                if path.segments.len() == 1 &&
+                   path.segments[0].identifier.as_str() == "file" => match lit.node {
+                ast::LitStr(ref s, _) => {
+                    let path = super::resolve_path(cx, args[0].span, s);
+                    match super::read_file(cx, args[0].span, &path) {
+                        Some(bytes) => Some((bytes, Some(path))),
+                        None => return None,
+                    }
+                },
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    };
+    let (bytes, file) = match source {
+        Some(source) => source,
+        None => {
+            cx.span_err(expr.span, "Expected a shader source literal or `file(\"path\")`");
+            return None
+        },
+    };
+    let mut includes = Includes {
+        stack: Vec::new(),
+        once: Vec::new(),
+    };
+    let span = match expr.node {
+        ast::ExprCall(_, ref args) => args[0].span,
+        _ => expr.span,
+    };
+    preprocess(cx, span, bytes, file, Some(target), options, &mut includes)
+        .map(|text| match options.strip {
+            Some(keep_lines) => strip(&text, keep_lines),
+            None => text,
+        }.into_bytes())
+}
+
+/// Remove comments and redundant whitespace from a GLSL source. Blank lines
+/// are removed as well, unless `keep_lines` is set so that the line numbers
+/// of compile errors still match the original source.
+fn strip(text: &str, keep_lines: bool) -> String {
+    let mut code = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek().cloned()) {
+            ('/', Some('/')) => while chars.peek().map_or(false, |&c| c != '\n') {
+                chars.next();
+            },
+            ('/', Some('*')) => {
+                chars.next();
+                let mut last = ' ';
+                while let Some(c) = chars.next() {
+                    if c == '\n' {
+                        code.push('\n');
+                    } else if last == '*' && c == '/' {
+                        break
+                    }
+                    last = c;
+                }
+                code.push(' ');
+            },
+            _ => code.push(c),
+        }
+    }
+    let lines = code.split('\n').map(|line| {
+        line.split(|c: char| c.is_whitespace()).filter(|word| !word.is_empty())
+            .collect::<Vec<_>>().connect(" ")
+    });
+    let lines: Vec<String> = if keep_lines {
+        lines.collect()
+    } else {
+        lines.filter(|line| !line.is_empty()).collect()
+    };
+    let mut text = lines.connect("\n");
+    text.push('\n');
+    text
+}
+
+/// Parse the leading options and the `key: value` sources of a shader
+/// macro into one optional source per GLSL version. Options besides the
+/// common ones are handed to `extra_option`, which returns whether it
+/// recognized them.
+fn parse_sources<F>(cx: &mut ext::base::ExtCtxt, tts: &[ast::TokenTree], mut extra_option: F)
+                    -> (Vec<ast::Attribute>, Vec<Option<Vec<u8>>>)
+                    where F: FnMut(&mut ext::base::ExtCtxt, &ast::Attribute) -> bool {
+    let mut parser = cx.new_parser_from_tts(tts);
+    let mut options = Options {
+        validate: false,
+        stage: None,
+        strip: None,
+        downgrade: None,
+    };
+    let attributes = parser.parse_outer_attributes();
+    for attribute in attributes.iter() {
+        match attribute.node.value.node {
+            ast::MetaWord(ref word) if &word[..] == "validate" => options.validate = true,
+            ast::MetaWord(ref word) if &word[..] == "strip" => options.strip = Some(false),
+            ast::MetaWord(ref word) if &word[..] == "downgrade" =>
+                options.downgrade = Some(attribute.span),
+            ast::MetaNameValue(ref name, ref value) if &name[..] == "strip" => match value.node {
+                ast::LitStr(ref mode, _) if &mode[..] == "keep_lines" => options.strip = Some(true),
+                _ => cx.span_err(attribute.span, "Unknown strip mode, expected `#[strip]` \
+                                                  or `#[strip = \"keep_lines\"]`"),
+            },
+            ast::MetaNameValue(ref name, ref value) if &name[..] == "stage" => {
+                let stage = match value.node {
+                    ast::LitStr(ref stage, _) => STAGES.iter().position(|&(s, _)| s == &stage[..]),
+                    _ => None,
+                };
+                match stage {
+                    Some(stage) if STAGES[stage].1 > VERSIONS[VERSIONS.len() - 1] => {
+                        let (stage, first) = STAGES[stage];
+                        cx.span_err(attribute.span, &format!(
+                            "{} shaders require at least `{}`, which \
+                            `gfx::ShaderSource` does not carry", stage, first
+                        ));
+                    },
+                    Some(_) => (),
+                    None => {
+                        cx.span_err(attribute.span, &format!(
+                            "Unknown shader stage, expected one of: {}",
+                            STAGES.iter().map(|&(s, _)| s).collect::<Vec<_>>().connect(", ")
+                        ));
+                    },
+                }
+                options.stage = stage;
+            },
+            ast::MetaNameValue(ref name, _) if &name[..] == "gfx_crate" => (),
+            _ => if !extra_option(cx, attribute) {
+                cx.span_err(attribute.span, "Unknown shader macro option")
+            },
+        }
+    }
+    let mut sources: Vec<Option<Vec<u8>>> = VERSIONS.iter().map(|_| None).collect();
+    while !parser.check(&token::Eof) {
+        let key_span = parser.span;
+        let key = token::get_ident(panictry!(parser.parse_ident())).to_ascii_lowercase();
+        panictry!(parser.expect(&token::Colon));
+        let value = parser.parse_expr();
+        panictry!(parser.eat(&token::Comma));
+        let unsupported = options.stage.map_or(false, |stage| &key[..] < STAGES[stage].1);
+        match VERSIONS.iter().position(|version| *version == &key[..]) {
+            Some(_) if unsupported => {
+                let (stage, first) = STAGES[options.stage.unwrap()];
+                cx.span_err(key_span, &format!(
+                    "{} shaders require at least `{}`, `{}` is not supported",
+                    stage, first, key
+                ));
+            },
+            Some(i) => {
+                if sources[i].is_some() {
+                    cx.span_warn(key_span, &format!(
+                        "Duplicate `{}` source - using the last one", key
+                    ));
+                }
+                sources[i] = source_bytes(cx, &value, VERSIONS[i], &options);
+            },
+            None if key == "spirv" => {
+                cx.span_err(key_span, "SPIR-V is not supported: `gfx::ShaderSource` \
+                                       has no SPIR-V target and there is no GLSL \
+                                       compiler available at expansion time");
+            },
+            None if key.starts_with("glsl_es") => {
+                cx.span_err(key_span, &format!(
+                    "GLSL ES is not supported: `gfx::ShaderSource` has no `{}` source, \
+                    only desktop GLSL ones", key
+                ));
+            },
+            None if key == "metal" => {
+                cx.span_err(key_span, "Metal is not supported: `gfx::ShaderSource` \
+                                       only carries GLSL sources and has no room \
+                                       for MSL sources or their entry point names");
+            },
+            None if key.starts_with("hlsl") => {
+                cx.span_err(key_span, &format!(
+                    "HLSL is not supported: `gfx::ShaderSource` only carries GLSL \
+                    sources, so `{}` can not be bundled with them", key
+                ));
+            },
+            None => {
+                cx.span_err(key_span, &format!(
+                    "Unknown shader target `{}`, expected one of: {}",
+                    key, VERSIONS.connect(", ")
+                ));
+            },
+        }
+    }
+    if let Some(span) = options.downgrade {
+        downgrade_sources(cx, span, &options, &mut sources);
+    }
+    (attributes, sources)
+}
+
+/// Fill in the `glsl_120` source for `#[downgrade]`, out of the `glsl_150`
+/// one, unless it is given
+fn downgrade_sources(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+                     options: &Options, sources: &mut Vec<Option<Vec<u8>>>) {
+    let fragment = match options.stage.map(|stage| STAGES[stage].0) {
+        Some("vertex") => false,
+        Some("fragment") => true,
+        _ => {
+            cx.span_err(span, "`#[downgrade]` needs a `#[stage = \"vertex\"]` or \
+                               `#[stage = \"fragment\"]`, which are rewritten differently");
+            return
+        },
+    };
+    if sources[0].is_some() {
+        return
+    }
+    let text = match sources[3] {
+        Some(ref bytes) => String::from_utf8_lossy(bytes).into_owned(),
+        None => {
+            cx.span_err(span, "`#[downgrade]` derives the `glsl_120` source from a \
+                               `glsl_150` one, which is missing");
+            return
+        },
+    };
+    match downgrade(&text, fragment) {
+        Ok(text) => sources[0] = Some(text.into_bytes()),
+        Err(message) => cx.span_err(span, &format!("Unable to downgrade the source: {}", message)),
+    }
+}
+
+/// Rewrite a GLSL 1.50 vertex or fragment source into a GLSL 1.20 one:
+/// `in` and `out` declarations become `attribute` and `varying` ones, the
+/// fragment output is replaced by `gl_FragColor`, and `texture()`,
+/// `textureProj()`, `textureLod()` and `textureProjLod()` calls by the
+/// function of the type of their `uniform` sampler, like `texture3D()` or
+/// `shadow2DProj()`. Declarations with a `layout(...)` or an interpolation
+/// qualifier, sampler types and texture functions that GLSL 1.20 does not
+/// have, as well as lookups of other samplers have no GLSL 1.20 counterpart
+/// and are reported.
+fn downgrade(text: &str, fragment: bool) -> Result<String, String> {
+    let mut lines = Vec::new();
+    let mut output = None;
+    let mut samplers = Vec::new();
+    let mut depth = 0i32;
+    for line in text.lines() {
+        let trimmed = line.trim_left();
+        let indent = &line[..line.len() - trimmed.len()];
+        let words: Vec<&str> = trimmed.split(|c: char| c.is_whitespace() || c == ';' || c == ',')
+                                      .filter(|word| !word.is_empty()).collect();
+        let is_declaration = depth == 0 && words.len() >= 3;
+        if trimmed.starts_with("#version") {
+            lines.push("#version 120".to_string());
+            continue
+        }
+        if depth == 0 && trimmed.starts_with("layout") &&
+           trimmed["layout".len()..].trim_left().starts_with("(") {
+            return Err(format!("`{}` has a `layout` qualifier, which GLSL 1.20 does not \
+                                have", trimmed.trim_right()))
+        }
+        let interpolation = ["flat", "smooth", "noperspective", "centroid", "invariant", "sample"];
+        if is_declaration && interpolation.contains(&words[0]) &&
+           words.iter().any(|&word| word == "in" || word == "out") {
+            return Err(format!("`{}` has a `{}` qualifier, which can not be downgraded \
+                                to an `attribute` or a `varying`", trimmed.trim_right(), words[0]))
+        }
+        if is_declaration && words[0] == "uniform" &&
+           ["sampler", "isampler", "usampler"].iter().any(|&ty| words[1].starts_with(ty)) {
+            let prefix = match SAMPLERS_120.iter().find(|&&(ty, _)| ty == words[1]) {
+                Some(&(_, prefix)) => prefix,
+                None => return Err(format!("`{}` declares a `{}`, which GLSL 1.20 does not \
+                                            have", trimmed.trim_right(), words[1])),
+            };
+            samplers.extend(words[2..].iter().map(|name| {
+                (name.split('[').next().unwrap_or("").to_string(), prefix)
+            }));
+        }
+        let qualifier = match (words.first(), fragment) {
+            (Some(&"in"), false) if is_declaration => Some("attribute"),
+            (Some(&"in"), true) | (Some(&"out"), false) if is_declaration => Some("varying"),
+            (Some(&"out"), true) if is_declaration => {
+                if output.is_some() {
+                    return Err("GLSL 1.20 has a single fragment output".to_string())
+                }
+                if words[1] != "vec4" {
+                    return Err(format!("the fragment output is a `{}`, but `gl_FragColor` \
+                                        is a `vec4`", words[1]))
+                }
+                output = Some(words[2].to_string());
+                continue
+            },
+            _ => None,
+        };
+        depth += trimmed.chars().filter(|&c| c == '{').count() as i32 -
+                 trimmed.chars().filter(|&c| c == '}').count() as i32;
+        lines.push(match qualifier {
+            Some(qualifier) => format!("{}{}{}", indent, qualifier, &trimmed[words[0].len()..]),
+            None => line.to_string(),
+        });
+    }
+    let text = lines.connect("\n");
+
+    // replace the identifiers, looking at the sampler of texture lookups.
+    // The GLSL 1.20 shadow lookups return a `vec4` instead of a `float`, so
+    // the parenthesis depths of their calls are kept to take the `.r` part.
+    let mut result = String::with_capacity(text.len());
+    let mut parens = 0i32;
+    let mut shadow_calls = Vec::new();
+    let mut rest = &text[..];
+    while let Some(start) = rest.find(|c: char| c.is_alphabetic() || c == '_') {
+        let end = rest[start..].find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                               .map_or(rest.len(), |end| start + end);
+        let ident = &rest[start..end];
+        push_between(&mut result, &rest[..start], &mut parens, &mut shadow_calls);
+        let after = rest[end..].trim_left();
+        let suffix = match ident {
+            "texture" => Some(""),
+            "textureProj" => Some("Proj"),
+            "textureLod" => Some("Lod"),
+            "textureProjLod" => Some("ProjLod"),
+            _ => None,
+        };
+        match suffix {
+            Some(suffix) if after.starts_with("(") => {
+                let sampler = after[1..].trim_left()
+                                        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+                                        .next().unwrap_or("");
+                let prefix = match samplers.iter().find(|&&(ref name, _)| &name[..] == sampler) {
+                    Some(&(_, prefix)) => prefix,
+                    None => return Err(format!("the sampler `{}` of `{}()` is not a `uniform` \
+                                                declared before, so its type is unknown",
+                                               sampler, ident)),
+                };
+                if fragment && suffix.ends_with("Lod") {
+                    return Err(format!("`{}()` is only available to GLSL 1.20 vertex \
+                                        shaders", ident))
+                }
+                if prefix == "textureCube" && suffix.starts_with("Proj") {
+                    return Err(format!("`{}()` has no GLSL 1.20 counterpart for a \
+                                        `samplerCube`", ident))
+                }
+                if prefix.starts_with("shadow") {
+                    shadow_calls.push(parens);
+                }
+                result.push_str(prefix);
+                result.push_str(suffix);
+            },
+            _ if after.starts_with("(") && TEXTURE_FUNCTIONS_130.contains(&ident) =>
+                return Err(format!("`{}()` has no GLSL 1.20 counterpart", ident)),
+            _ if output.as_ref().map_or(false, |output| &output[..] == ident) =>
+                result.push_str("gl_FragColor"),
+            _ => result.push_str(ident),
+        }
+        rest = &rest[end..];
+    }
+    push_between(&mut result, rest, &mut parens, &mut shadow_calls);
+    result.push('\n');
+    Ok(result)
+}
+
+/// Append the text between identifiers, closing the shadow lookup calls
+/// opened at the given parenthesis depths with `.r`
+fn push_between(result: &mut String, text: &str, parens: &mut i32, shadow_calls: &mut Vec<i32>) {
+    for c in text.chars() {
+        result.push(c);
+        if c == '(' {
+            *parens += 1;
+        } else if c == ')' {
+            *parens -= 1;
+            if shadow_calls.last() == Some(parens) {
+                shadow_calls.pop();
+                result.push_str(".r");
+            }
+        }
+    }
+}
+
+/// Build the `gfx::ShaderSource` expression out of the sources
+fn source_expr(cx: &mut ext::base::ExtCtxt, span: codemap::Span, path_root: ast::Ident,
+               sources: Vec<Option<Vec<u8>>>) -> P<ast::Expr> {
+    let mut fields: Vec<ast::Field> = VERSIONS.iter().zip(sources.into_iter())
+        .map(|(version, source)| {
+            let value = match source {
+                Some(bytes) => {
+                    let lit = cx.expr_lit(span, ast::LitBinary(Rc::new(bytes)));
+                    cx.expr_some(span, lit)
+                },
+                None => cx.expr_none(span),
+            };
+            cx.field_imm(span, cx.ident_of(version), value)
+        }).collect();
+    fields.push(cx.field_imm(span, cx.ident_of("targets"), quote_expr!(cx, &[])));
+    cx.expr_struct(span, cx.path(span, vec![
+        path_root,
+        cx.ident_of("gfx"),
+        cx.ident_of("ShaderSource"),
+    ]), fields)
+}
+
+/// Expand the `shaders!` macro into a `gfx::ShaderSource`. Sources are
+/// keyed by GLSL version and given either inline or as files, which are
+/// read at expansion time. Options are given as leading attributes:
+///
+/// ```ignore
+/// static VERTEX_SRC: gfx::ShaderSource<'static> = shaders! {
+///     #[validate]
+///     glsl_120: b"#version 120 ..."
+///     glsl_150: file("shaders/triangle_150.vert")
+/// };
+/// ```
+///
+/// - `#[validate]` checks the structure of every source and reports
+///   problems as compile errors.
+/// - `#[gfx_crate = "path::to::gfx"]` uses the given path to the `gfx`
+///   crate, instead of inserting an `extern crate gfx`.
+/// - `#[stage = "geometry"]` declares the shader stage, rejecting the GLSL
+///   versions that do not support it. The stage itself is still picked
+///   when creating the shader.
+/// - `#[strip]` removes comments, blank lines and redundant whitespace to
+///   make the embedded sources smaller. `#[strip = "keep_lines"]` keeps the
+///   blank lines, so that compile errors point at the original lines.
+/// - `#[downgrade]` derives the `glsl_120` source from the `glsl_150` one
+///   when it is not given, for vertex and fragment stages. GLSL ES sources
+///   are not carried by `gfx::ShaderSource`.
+/// - `#[hash]` pairs the source with a `u64` hash of the embedded stages,
+///   `(gfx::ShaderSource, u64)`, to key program binary caches with.
+///
+/// Sources can refer to `#[shader_constant]` constants declared before the
+/// invocation as `$NAME`, which is replaced by the value of the constant.
+/// The constant of the invoking module is taken, or else the only one of
+/// that name.
+pub fn expand(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+              tts: &[ast::TokenTree]) -> Box<MacResult + 'static> {
+    let mut with_hash = false;
+    let (attributes, sources) = parse_sources(cx, tts, |_, attribute| {
+        match attribute.node.value.node {
+            ast::MetaWord(ref word) if &word[..] == "hash" => {
+                with_hash = true;
+                true
+            },
+            _ => false,
+        }
+    });
+    let hash = content_hash(&sources);
+    super::expand_with_root(cx, span, &attributes, |cx, path_root| {
+        let source = source_expr(cx, span, path_root, sources);
+        if with_hash {
+            let hash = cx.expr_lit(span, ast::LitInt(hash, ast::UnsignedIntLit(ast::TyU64)));
+            cx.expr_tuple(span, vec![source, hash])
+        } else {
+            source
+        }
+    })
+}
+
+/// A 64-bit FNV-1a hash of the sources along with their GLSL versions,
+/// which does not depend on the compiler or the platform
+fn content_hash(sources: &[Option<Vec<u8>>]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for (version, source) in VERSIONS.iter().zip(sources.iter()) {
+        let bytes = match *source {
+            Some(ref bytes) => bytes,
+            None => continue,
+        };
+        // terminate the versions and the sources, which keeps neighbouring
+        // ones from running into each other
+        let terminator = [0u8];
+        for &byte in version.as_bytes().iter().chain(terminator.iter())
+                            .chain(bytes.iter()).chain(terminator.iter()) {
+            hash = (hash ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+/// Insert a `#define` for every flag after the `#version` directive, or at
+/// the start of the source if there is none
+fn with_defines(source: &[u8], flags: &[String]) -> Vec<u8> {
+    let text = String::from_utf8_lossy(source);
+    let mut lines: Vec<String> = text.lines().map(|line| line.to_string()).collect();
+    let at = lines.iter().position(|line| line.trim_left().starts_with("#version"))
+                  .map_or(0, |version| version + 1);
+    for (i, flag) in flags.iter().enumerate() {
+        lines.insert(at + i, format!("#define {}", flag));
+    }
+    let mut text = lines.connect("\n");
+    text.push('\n');
+    text.into_bytes()
+}
+
+/// The maximum number of flags of `shader_variants!`, which generates up
+/// to `2^N` variants
+const MAX_FLAGS: usize = 8;
+
+/// Expand the `shader_variants!` macro into a table of named
+/// `gfx::ShaderSource` permutations of one base shader, each with a
+/// combination of `#define` flags inserted after the `#version`:
+///
+/// ```ignore
+/// static LIT_VARIANTS: &'static [(&'static str, gfx::ShaderSource<'static>)] = shader_variants! {
+///     #[flags = "SKINNED, SHADOWS"]
+///     glsl_150: file("shaders/lit_150.frag")
+/// };
+/// ```
+///
+/// Every combination is generated unless a subset is selected with one or
+/// more `#[variant = "SKINNED, SHADOWS"]`. Variants are named by their flags
+/// joined with `+`, the one without flags being `""`. The other options are
+/// the ones of `shaders!`.
+pub fn expand_variants(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+                       tts: &[ast::TokenTree]) -> Box<MacResult + 'static> {
+    fn split(list: &str) -> Vec<String> {
+        list.split(',').map(|flag| flag.trim().to_string())
+            .filter(|flag| !flag.is_empty()).collect()
+    }
+    let mut flags = Vec::new();
+    let mut variants = Vec::new();
+    let (attributes, sources) = parse_sources(cx, tts, |cx, attribute| {
+        match attribute.node.value.node {
+            ast::MetaNameValue(ref name, ref value) if &name[..] == "flags" ||
+                                                       &name[..] == "variant" => {
+                let list = match value.node {
+                    ast::LitStr(ref list, _) => split(list),
+                    _ => {
+                        cx.span_err(attribute.span, "Expected a list of flags like \"A, B\"");
+                        return true
+                    },
+                };
+                if &name[..] == "flags" {
+                    flags.extend(list.into_iter());
+                } else {
+                    variants.push((list, attribute.span));
+                }
+                true
+            },
+            _ => false,
+        }
+    });
+    if flags.len() > MAX_FLAGS {
+        cx.span_err(span, &format!(
+            "Too many flags ({}) for `shader_variants!`, at most {} are supported",
+            flags.len(), MAX_FLAGS
+        ));
+        return DummyResult::expr(span)
+    }
+    let variants: Vec<Vec<String>> = if variants.is_empty() {
+        (0..1usize << flags.len()).map(|mask| {
+            flags.iter().enumerate().filter(|&(i, _)| mask & (1 << i) != 0)
+                 .map(|(_, flag)| flag.clone()).collect()
+        }).collect()
+    } else {
+        variants.into_iter().map(|(variant, variant_span)| {
+            for flag in variant.iter() {
+                if !flags.contains(flag) {
+                    cx.span_err(variant_span, &format!("Unknown flag `{}` in variant", flag));
+                }
+            }
+            variant
+        }).collect()
+    };
+    super::expand_with_root(cx, span, &attributes, |cx, path_root| {
+        let entries = variants.into_iter().map(|variant| {
+            let name = cx.expr_str(span, token::intern_and_get_ident(&variant.connect("+")));
+            let sources = sources.iter().map(|source|
+                source.as_ref().map(|bytes| with_defines(bytes, &variant))
+            ).collect();
+            let source = source_expr(cx, span, path_root, sources);
+            cx.expr_tuple(span, vec![name, source])
+        }).collect();
+        cx.expr_vec_slice(span, entries)
+    })
+}
+
+/// Describe a shader variable for diagnostics, like `vec3` or `mat4[8]`
+fn describe(var: &glsl::Var) -> String {
+    let ty = match (var.kind, &var.ty) {
+        (_, &Some(ref ty)) => ty.clone(),
+        (glsl::Kind::Block, _) => "uniform block".to_string(),
+        (glsl::Kind::Texture, _) => "sampler".to_string(),
+        _ => "unknown type".to_string(),
+    };
+    match var.count {
+        Some(count) => format!("{}[{}]", ty, count),
+        None => ty,
+    }
+}
+
+/// Expand the `link_check!` macro, which checks a vertex format and a
+/// parameter struct against the shader stages they are used with:
+///
+/// ```ignore
+/// link_check!(MyVertex, MyParams,
+///     { glsl_150: file("shaders/lit_150.vert") },
+///     { glsl_150: file("shaders/lit_150.frag") });
+/// ```
+///
+/// Both structs have to be declared before the invocation, and can be given
+/// by their paths from the invoking module. The stages are
+/// given in the syntax of `shaders!`, the vertex shader first, and the
+/// highest GLSL version of every stage is checked. Every attribute has to
+/// be an input of the vertex shader and every parameter a uniform, block
+/// or sampler of one of the stages, with a matching type. Parameters whose
+/// name does not appear in any stage at all are reported as warnings,
+/// along with the closest declared name. The macro expands to nothing.
+pub fn expand_link_check(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+                         tts: &[ast::TokenTree]) -> Box<MacResult + 'static> {
+    let mut parser = cx.new_parser_from_tts(tts);
+    let vertex_lo = parser.span.lo;
+    let vertex = panictry!(parser.parse_path(parser::NoTypesAllowed));
+    let vertex_span = codemap::mk_sp(vertex_lo, parser.last_span.hi);
+    panictry!(parser.expect(&token::Comma));
+    let params_lo = parser.span.lo;
+    let params = panictry!(parser.parse_path(parser::NoTypesAllowed));
+    let params_span = codemap::mk_sp(params_lo, parser.last_span.hi);
+    let mut stages = Vec::new();
+    while panictry!(parser.eat(&token::Comma)) && !parser.check(&token::Eof) {
+        match panictry!(parser.parse_token_tree()) {
+            ast::TtDelimited(stage_span, ref delimited) => stages.push((stage_span, delimited.tts.clone())),
+            _ => {
+                cx.span_err(parser.last_span, "Expected a `{ ... }` block of shader sources");
+                return DummyResult::any(span)
+            },
+        }
+    }
+    if !parser.check(&token::Eof) {
+        cx.span_err(parser.span, "Expected `,` followed by a block of shader sources");
+        return DummyResult::any(span)
+    }
+    if stages.is_empty() {
+        cx.span_err(span, "`link_check!` needs at least the vertex shader sources");
+        return DummyResult::any(span)
+    }
+
+    // the declarations of the highest GLSL version of every stage
+    let mut texts = Vec::new();
+    let mut declarations = Vec::new();
+    for (i, &(stage_span, ref stage_tts)) in stages.iter().enumerate() {
+        let (_, sources) = parse_sources(cx, stage_tts, |_, _| false);
+        match sources.into_iter().filter_map(|source| source).last() {
+            Some(source) => {
+                let text = String::from_utf8_lossy(&source).into_owned();
+                declarations.extend(glsl::declarations(&text, i == 0).into_iter());
+                texts.push(text);
+            },
+            None => cx.span_err(stage_span, "Shader stage has no sources"),
+        }
+    }
+
+    let vertex_name = pprust::path_to_string(&vertex);
+    match vertex_format::signature(cx, &vertex) {
+        Ok(attributes) => for attribute in attributes.iter() {
+            match declarations.iter().find(|var| var.kind == glsl::Kind::Attribute &&
+                                                 var.name == attribute.name) {
+                Some(var) if !glsl::is_compatible(attribute, var) => cx.span_err(vertex_span, &format!(
+                    "Attribute `{}` of `{}` is `{}`, but the vertex shader declares it as `{}`",
+                    attribute.name, vertex_name, describe(attribute), describe(var)
+                )),
+                Some(_) => (),
+                None => cx.span_err(vertex_span, &format!(
+                    "Attribute `{}` of `{}` is not an input of the vertex shader",
+                    attribute.name, vertex_name
+                )),
+            }
+        },
+        Err(candidates) => cx.span_err(vertex_span, &super::entry_error(
+            &vertex, "`#[vertex_format]` struct", "`link_check!`", &candidates
+        )),
+    }
+
+    let params_name = pprust::path_to_string(&params);
+    match shader_param::signature(cx, &params) {
+        Ok(uniforms) => for uniform in uniforms.iter() {
+            let mentioned = texts.iter().any(|text| glsl::mentions(text, &uniform.name));
+            match declarations.iter().find(|var| var.kind != glsl::Kind::Attribute &&
+                                                 var.name == uniform.name) {
+                Some(var) if var.kind != uniform.kind || !glsl::is_compatible(uniform, var) => {
+                    cx.span_err(params_span, &format!(
+                        "Parameter `{}` of `{}` is `{}`, but the shader declares it as `{}`",
+                        uniform.name, params_name, describe(uniform), describe(var)
+                    ));
+                },
+                Some(_) => (),
+                // names appearing nowhere are most likely typos, which are
+                // only caught at runtime otherwise
+                None if !mentioned => {
+                    let hint = match glsl::closest(&declarations, uniform.kind, &uniform.name) {
+                        Some(var) => format!(", did you mean `{}`?", var.name),
+                        None => String::new(),
+                    };
+                    cx.span_warn(params_span, &format!(
+                        "Parameter `{}` of `{}` never appears in any shader stage{}",
+                        uniform.name, params_name, hint
+                    ));
+                },
+                None => cx.span_err(params_span, &format!(
+                    "Parameter `{}` of `{}` is not declared by any shader stage",
+                    uniform.name, params_name
+                )),
+            }
+        },
+        Err(candidates) => cx.span_err(params_span, &super::entry_error(
+            &params, "`#[shader_param]` struct", "`link_check!`", &candidates
+        )),
+    }
+    MacEager::items(SmallVector::zero())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::downgrade;
+
+    #[test]
+    fn test_downgrade_qualifiers() {
+        assert!(downgrade("layout(location = 0) in vec3 a_Pos;", false).is_err());
+        assert!(downgrade("layout (location = 0) out vec4 o_Color;", true).is_err());
+        assert!(downgrade("flat in int v_Index;", true).is_err());
+        assert!(downgrade("smooth out vec2 v_TexCoord;", false).is_err());
+        assert!(downgrade("centroid in vec2 v_TexCoord;", true).is_err());
+        assert_eq!(downgrade("in vec3 a_Pos;", false), Ok("attribute vec3 a_Pos;\n".to_string()));
+    }
+}
@@ -0,0 +1,380 @@
+// Copyright 2015 The Gfx-rs Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Macros and attributes building fixed-function `gfx::state` values, with
+//! the names of their factors, functions and operations checked at
+//! expansion time.
+
+use syntax::{ast, attr, codemap, ext};
+use syntax::ext::base::{DummyResult, ItemDecorator, MacResult};
+use syntax::ext::build::AstBuilder;
+use syntax::parse::attr::ParserAttr;
+use syntax::parse::token;
+use syntax::ptr::P;
+
+/// Blend factors, along with the `BlendValue` they apply to and
+/// whether they are `OneMinus` of it
+static FACTORS: &'static [(&'static str, Option<(&'static str, bool)>)] = &[
+    ("Zero", None),
+    ("One", None),
+    ("SrcAlphaSaturate", None),
+    ("SrcColor", Some(("SourceColor", false))),
+    ("OneMinusSrcColor", Some(("SourceColor", true))),
+    ("SrcAlpha", Some(("SourceAlpha", false))),
+    ("OneMinusSrcAlpha", Some(("SourceAlpha", true))),
+    ("DstColor", Some(("DestColor", false))),
+    ("OneMinusDstColor", Some(("DestColor", true))),
+    ("DstAlpha", Some(("DestAlpha", false))),
+    ("OneMinusDstAlpha", Some(("DestAlpha", true))),
+    ("ConstColor", Some(("ConstColor", false))),
+    ("OneMinusConstColor", Some(("ConstColor", true))),
+    ("ConstAlpha", Some(("ConstAlpha", false))),
+    ("OneMinusConstAlpha", Some(("ConstAlpha", true))),
+];
+
+/// Blend equations, which are named as in `gfx::state::Equation`
+static EQUATIONS: &'static [&'static str] = &["Add", "Sub", "RevSub", "Min", "Max"];
+
+/// Parse a comma separated list of identifiers, up to a `;` or the end
+fn parse_names(parser: &mut ::syntax::parse::parser::Parser)
+               -> Vec<(token::InternedString, codemap::Span)> {
+    let mut names = Vec::new();
+    while !parser.check(&token::Eof) && !parser.check(&token::Semi) {
+        let span = parser.span;
+        names.push((token::get_ident(panictry!(parser.parse_ident())), span));
+        if !panictry!(parser.eat(&token::Comma)) {
+            break
+        }
+    }
+    names
+}
+
+/// Build a `gfx::state::Factor` expression out of its name
+fn factor_expr(cx: &mut ext::base::ExtCtxt, span: codemap::Span, name: &str,
+               path_root: ast::Ident) -> Option<P<ast::Expr>> {
+    let factor = match FACTORS.iter().find(|&&(factor, _)| factor == name) {
+        Some(&(_, factor)) => factor,
+        None => {
+            cx.span_err(span, &format!(
+                "Unknown blend factor `{}`, expected one of: {}", name,
+                FACTORS.iter().map(|&(f, _)| f).collect::<Vec<_>>().connect(", ")
+            ));
+            return None
+        },
+    };
+    Some(match factor {
+        None => {
+            let factor = cx.ident_of(if name == "SrcAlphaSaturate" {
+                "SourceAlphaSaturated"
+            } else {
+                name
+            });
+            quote_expr!(cx, $path_root::gfx::state::Factor::$factor)
+        },
+        Some((value, one_minus)) => {
+            let kind = cx.ident_of(if one_minus { "OneMinus" } else { "ZeroPlus" });
+            let value = cx.ident_of(value);
+            quote_expr!(cx, $path_root::gfx::state::Factor::$kind(
+                $path_root::gfx::state::BlendValue::$value))
+        },
+    })
+}
+
+/// Build a `gfx::state::BlendChannel` expression out of the source and the
+/// destination factor and the equation names
+fn channel_expr(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+                names: &[(token::InternedString, codemap::Span)],
+                path_root: ast::Ident) -> Option<P<ast::Expr>> {
+    if names.len() != 3 {
+        cx.span_err(span, "Expected a source factor, a destination factor and an \
+                           equation, like `blend!(SrcAlpha, OneMinusSrcAlpha, Add)`");
+        return None
+    }
+    let source = factor_expr(cx, names[0].1, &names[0].0, path_root);
+    let destination = factor_expr(cx, names[1].1, &names[1].0, path_root);
+    let (ref equation, equation_span) = names[2];
+    if !EQUATIONS.contains(&&equation[..]) {
+        cx.span_err(equation_span, &format!(
+            "Unknown blend equation `{}`, expected one of: {}", equation, EQUATIONS.connect(", ")
+        ));
+        return None
+    }
+    let equation = cx.ident_of(equation);
+    match (source, destination) {
+        (Some(source), Some(destination)) => Some(quote_expr!(cx,
+            $path_root::gfx::state::BlendChannel {
+                equation: $path_root::gfx::state::Equation::$equation,
+                source: $source,
+                destination: $destination,
+            }
+        )),
+        _ => None,
+    }
+}
+
+/// Expand `blend!` into a `gfx::state::Blend`, blending the color and the
+/// alpha channels the same way, or the alpha one as given after a `;`:
+///
+/// ```ignore
+/// let alpha = blend!(SrcAlpha, OneMinusSrcAlpha, Add);
+/// let premultiplied = blend!(One, OneMinusSrcAlpha, Add; One, One, Max);
+/// ```
+///
+/// Factors are `Zero`, `One`, `SrcAlphaSaturate` and `[OneMinus]Src`,
+/// `Dst` or `Const` followed by `Color` or `Alpha`. The equations are the
+/// ones of `gfx::state::Equation`. A leading `#[gfx_crate = "..."]` gives
+/// the path to the `gfx` crate.
+pub fn expand_blend(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+                    tts: &[ast::TokenTree]) -> Box<MacResult + 'static> {
+    let mut parser = cx.new_parser_from_tts(tts);
+    let attributes = parser.parse_outer_attributes();
+    let color_names = parse_names(&mut parser);
+    let alpha_names = if panictry!(parser.eat(&token::Semi)) {
+        Some(parse_names(&mut parser))
+    } else {
+        None
+    };
+    if !parser.check(&token::Eof) {
+        cx.span_err(parser.span, "Expected `,` or `;` between the blend factors");
+        return DummyResult::expr(span)
+    }
+    super::expand_with_root(cx, span, &attributes, |cx, path_root| {
+        let color = channel_expr(cx, span, &color_names, path_root);
+        let alpha = channel_expr(cx, span, alpha_names.as_ref().unwrap_or(&color_names), path_root);
+        match (color, alpha) {
+            (Some(color), Some(alpha)) => quote_expr!(cx, $path_root::gfx::state::Blend {
+                color: $color,
+                alpha: $alpha,
+                value: [0.0, 0.0, 0.0, 0.0],
+            }),
+            _ => cx.expr_tuple(span, vec![]),
+        }
+    })
+}
+
+/// Comparison functions, named as in `gfx::state::Comparison`
+static COMPARISONS: &'static [&'static str] = &[
+    "Never", "Less", "LessEqual", "Equal", "GreaterEqual", "Greater", "NotEqual", "Always",
+];
+
+/// Stencil operations, named as in `gfx::state::StencilOp`
+static STENCIL_OPS: &'static [&'static str] = &[
+    "Keep", "Zero", "Replace", "IncrementClamp", "IncrementWrap",
+    "DecrementClamp", "DecrementWrap", "Invert",
+];
+
+/// Check that `name` is one of `names`, the variants of `gfx::state::$kind`,
+/// and build the path to it
+fn variant_expr(cx: &mut ext::base::ExtCtxt, span: codemap::Span, kind: &str,
+                names: &[&str], name: &str, path_root: ast::Ident) -> P<ast::Expr> {
+    if !names.contains(&name) {
+        cx.span_err(span, &format!(
+            "Unknown `{}` value `{}`, expected one of: {}", kind, name, names.connect(", ")
+        ));
+    }
+    let kind = cx.ident_of(kind);
+    let name = cx.ident_of(name);
+    quote_expr!(cx, $path_root::gfx::state::$kind::$name)
+}
+
+/// Build a `u8` stencil value out of an integer attribute
+fn stencil_value(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+                 attributes: &[ast::Attribute], key: &str, default: u8) -> P<ast::Expr> {
+    let value = match super::find_int(cx, span, attributes, key) {
+        Some(value) if value > 0xFF => {
+            cx.span_err(span, &format!("`#[{}]` does not fit a stencil value", key));
+            default
+        },
+        Some(value) => value as u8,
+        None => default,
+    };
+    cx.expr_u8(span, value)
+}
+
+/// Build the `gfx::state::Depth` of a field
+fn depth_expr(cx: &mut ext::base::ExtCtxt, field: &ast::StructField,
+              path_root: ast::Ident) -> P<ast::Expr> {
+    let attributes = &field.node.attrs;
+    let fun = super::find_str(cx, field.span, attributes, "fun");
+    let fun = variant_expr(cx, field.span, "Comparison", COMPARISONS,
+                           fun.as_ref().map_or("Less", |fun| &fun[..]), path_root);
+    let write = cx.expr_bool(field.span, attr::contains_name(attributes, "write"));
+    quote_expr!(cx, $path_root::gfx::state::Depth {
+        fun: $fun,
+        write: $write,
+    })
+}
+
+/// Build the `gfx::state::Stencil` of a field, with the same settings for
+/// the front and the back faces
+fn stencil_expr(cx: &mut ext::base::ExtCtxt, field: &ast::StructField,
+                path_root: ast::Ident) -> P<ast::Expr> {
+    let span = field.span;
+    let attributes = &field.node.attrs;
+    let fun = super::find_str(cx, span, attributes, "fun");
+    let fun = variant_expr(cx, span, "Comparison", COMPARISONS,
+                           fun.as_ref().map_or("Always", |fun| &fun[..]), path_root);
+    let value = stencil_value(cx, span, attributes, "value", 0);
+    let mask_read = stencil_value(cx, span, attributes, "read_mask", 0xFF);
+    let mask_write = stencil_value(cx, span, attributes, "write_mask", 0xFF);
+    // `#[ops = "fail, depth_fail, pass"]`
+    let ops: Vec<String> = match super::find_str(cx, span, attributes, "ops") {
+        Some(ops) => ops.split(',').map(|op| op.trim().to_string()).collect(),
+        None => vec!["Keep".to_string(); 3],
+    };
+    if ops.len() != 3 {
+        cx.span_err(span, "`#[ops]` expects the fail, depth fail and pass operations, \
+                           like \"Keep, Keep, Replace\"");
+        return cx.expr_tuple(span, vec![])
+    }
+    let ops: Vec<P<ast::Expr>> = ops.iter().map(|op|
+        variant_expr(cx, span, "StencilOp", STENCIL_OPS, op, path_root)
+    ).collect();
+    let (op_fail, op_depth_fail, op_pass) = (&ops[0], &ops[1], &ops[2]);
+    quote_expr!(cx, {
+        let side = $path_root::gfx::state::StencilSide {
+            fun: $fun,
+            value: $value,
+            mask_read: $mask_read,
+            mask_write: $mask_write,
+            op_fail: $op_fail,
+            op_depth_fail: $op_depth_fail,
+            op_pass: $op_pass,
+        };
+        $path_root::gfx::state::Stencil {
+            front: side,
+            back: side,
+        }
+    })
+}
+
+#[derive(Copy, Clone)]
+pub struct DepthStencil;
+
+impl ItemDecorator for DepthStencil {
+    /// Generate the depth and stencil state configured by the field
+    /// attributes of a `#[depth_stencil]` struct:
+    ///
+    /// ```ignore
+    /// #[depth_stencil]
+    /// struct Outline {
+    ///     #[fun = "LessEqual"]
+    ///     #[write]
+    ///     depth: gfx::state::Depth,
+    ///     #[fun = "NotEqual"]
+    ///     #[value = "1"]
+    ///     #[ops = "Keep, Keep, Replace"]
+    ///     stencil: gfx::state::Stencil,
+    /// }
+    /// ```
+    ///
+    /// The configured values are built by `Default`, and applied to a draw
+    /// state with `pub fn apply(&self, state: gfx::DrawState) -> gfx::DrawState`.
+    /// The depth comparison defaults to `Less` without writes, the stencil
+    /// one to `Always` with masks of `0xFF` and `Keep` operations.
+    fn expand(&self, context: &mut ext::base::ExtCtxt, span: codemap::Span,
+              _meta_item: &ast::MetaItem, item: &ast::Item,
+              push: &mut FnMut(P<ast::Item>)) {
+        let item = &super::strip_unconfigured(context, item);
+        // Insert the `gfx` reexport module, unless a `#[gfx_crate]` path is given
+        let crate_root = super::crate_root(context, span, &item.attrs, |i| (*push)(i));
+        let path_root = crate_root.ident;
+
+        let (definition, generics) = match item.node {
+            ast::ItemStruct(ref definition, ref generics) => (definition, generics),
+            _ => {
+                context.span_err(span, "Only structs can be annotated with #[depth_stencil]");
+                return;
+            }
+        };
+
+        let mut values = Vec::new();
+        let mut applies = Vec::new();
+        for field in definition.fields.iter() {
+            let ident = match field.node.ident() {
+                Some(ident) => ident,
+                None => {
+                    context.span_err(field.span, "#[depth_stencil] requires named fields");
+                    continue
+                },
+            };
+            let kind = match field.node.ty.node {
+                ast::TyPath(_, ref path) => path.segments.last()
+                    .map(|segment| segment.identifier.name.as_str().to_string()),
+                _ => None,
+            };
+            let (value, state_field) = match kind.as_ref().map(|kind| &kind[..]) {
+                Some("Depth") => (depth_expr(context, field, path_root), "depth"),
+                Some("Stencil") => (stencil_expr(context, field, path_root), "stencil"),
+                _ => {
+                    context.span_err(field.span, "Unsupported #[depth_stencil] field, expected \
+                                                  `gfx::state::Depth` or `gfx::state::Stencil`");
+                    continue
+                },
+            };
+            values.push(context.field_imm(field.span, ident, value));
+            let state_field = context.ident_of(state_field);
+            applies.push(quote_stmt!(context, {
+                state.$state_field = Some(self.$ident);
+            }).unwrap());
+        }
+
+        let struct_ty = super::struct_type(context, span, item.ident, generics);
+
+        // `impl Default`, building the configured values
+        let default_body = context.expr_struct_ident(span, item.ident, values);
+        let decl = context.fn_decl(Vec::new(), struct_ty.clone());
+        let method = super::impl_method(context, span, "default", false, super::no_generics(),
+                                        decl, context.block_expr(default_body));
+        let default_impl = context.item(span, item.ident, Vec::new(), ast::ItemImpl(
+            ast::Unsafety::Normal,
+            ast::ImplPolarity::Positive,
+            generics.clone(),
+            Some(context.trait_ref(context.path_global(span, vec![
+                context.ident_of("std"),
+                context.ident_of("default"),
+                context.ident_of("Default"),
+            ]))),
+            struct_ty.clone(),
+            vec![P(method)]
+        ));
+        (*push)(super::fixup_extern_crate_paths(default_impl, &crate_root));
+
+        // `pub fn apply(&self, state: gfx::DrawState) -> gfx::DrawState`
+        let state = context.ident_of("state");
+        let state_ty = quote_ty!(context, $path_root::gfx::DrawState);
+        let decl = context.fn_decl(vec![
+            ast::Arg::new_self(span, ast::MutImmutable, context.ident_of("self")),
+            context.arg(span, state, state_ty.clone()),
+        ], state_ty);
+        let body = quote_expr!(context, {
+            let mut state = $state;
+            $applies
+            state
+        });
+        let mut method = super::impl_method(context, span, "apply", true, super::no_generics(),
+                                            decl, context.block_expr(body));
+        method.vis = ast::Public;
+        let attrs = vec![super::allow_dead_code(context, span)];
+        let apply_impl = context.item(span, item.ident, attrs, ast::ItemImpl(
+            ast::Unsafety::Normal,
+            ast::ImplPolarity::Positive,
+            generics.clone(),
+            None,
+            struct_ty,
+            vec![P(method)]
+        ));
+        (*push)(super::fixup_extern_crate_paths(apply_impl, &crate_root));
+    }
+}
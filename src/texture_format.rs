@@ -0,0 +1,208 @@
+// Copyright 2015 The Gfx-rs Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use syntax::{ast, attr, codemap, ext};
+use syntax::ext::base::ItemDecorator;
+use syntax::ext::build::AstBuilder;
+use syntax::ptr::P;
+
+/// The `gfx::tex::Components` of a `#[channels = "RGBA"]` attribute
+fn components(channels: &str) -> Option<&'static str> {
+    match channels {
+        "R" => Some("R"),
+        "RG" => Some("RG"),
+        "RGB" => Some("RGB"),
+        "RGBA" => Some("RGBA"),
+        _ => None,
+    }
+}
+
+/// Build the `gfx::tex::Format` expression of a variant, or report why its
+/// attributes do not describe one
+fn format_expr(cx: &mut ext::base::ExtCtxt, variant: &ast::Variant,
+               path_root: ast::Ident) -> Option<P<ast::Expr>> {
+    let span = variant.span;
+    let attrs = &variant.node.attrs;
+    let format = super::find_str(cx, span, attrs, "format");
+    let channels = super::find_str(cx, span, attrs, "channels");
+    let bits = super::find_int(cx, span, attrs, "bits");
+    let srgb = attr::contains_name(attrs, "srgb");
+    let float = attr::contains_name(attrs, "float");
+    let integer = attr::contains_name(attrs, "integer");
+    let signed = attr::contains_name(attrs, "signed");
+
+    // a `gfx::tex` constant or variant, like `"RGBA8"` or `"Format::BGRA8"`
+    if let Some(format) = format {
+        if channels.is_some() || bits.is_some() || srgb || float || integer || signed {
+            cx.span_err(span, "`#[format]` names the whole format, it can not be \
+                               combined with `#[channels]`, `#[bits]` or the kind");
+            return None
+        }
+        let mut path = vec![path_root, cx.ident_of("gfx"), cx.ident_of("tex")];
+        path.extend(format.split("::").map(|segment| cx.ident_of(segment)));
+        return Some(cx.expr_path(cx.path(span, path)))
+    }
+
+    let (channels, bits) = match (channels, bits) {
+        (Some(channels), Some(bits)) => (channels, bits),
+        _ => {
+            cx.span_err(span, "Texture formats need either a `#[format = \"RGBA8\"]` or \
+                               both `#[channels = \"RGBA\"]` and `#[bits = \"N\"]`");
+            return None
+        },
+    };
+    let components = match components(&channels) {
+        Some(components) => cx.ident_of(components),
+        None => {
+            cx.span_err(span, &format!("Unknown channels `{}`, expected one of: \
+                                        R, RG, RGB, RGBA", channels));
+            return None
+        },
+    };
+    if srgb {
+        cx.span_err(span, "`gfx::tex::Format` has no sRGB formats yet");
+        return None
+    }
+    if float && (integer || signed) {
+        cx.span_err(span, "`#[float]` and `#[integer]`/`#[signed]` formats are mutually exclusive");
+        return None
+    }
+    if float {
+        let size = match bits {
+            16 => cx.ident_of("F16"),
+            32 => cx.ident_of("F32"),
+            _ => {
+                cx.span_err(span, &format!("Float formats have 16 or 32 bits, not {}", bits));
+                return None
+            },
+        };
+        return Some(quote_expr!(cx, $path_root::gfx::tex::Format::Float(
+            $path_root::gfx::tex::Components::$components,
+            $path_root::gfx::attrib::FloatSize::$size
+        )))
+    }
+
+    // normalized by default, raw integers with `#[integer]`
+    let (sub_type, sizes) = if integer {
+        ("Raw", vec![8, 16, 32])
+    } else {
+        ("Normalized", vec![8, 16])
+    };
+    if !sizes.contains(&bits) {
+        cx.span_err(span, &format!(
+            "{} formats have {} bits, not {}",
+            if integer { "Integer" } else { "Normalized" },
+            sizes.iter().map(|size| size.to_string()).collect::<Vec<String>>().connect(", "),
+            bits
+        ));
+        return None
+    }
+    let sub_type = cx.ident_of(sub_type);
+    let kind = cx.ident_of(if signed { "Integer" } else { "Unsigned" });
+    let bits = cx.expr_u8(span, bits as u8);
+    Some(quote_expr!(cx, $path_root::gfx::tex::Format::$kind(
+        $path_root::gfx::tex::Components::$components,
+        $bits,
+        $path_root::gfx::attrib::IntSubType::$sub_type
+    )))
+}
+
+#[derive(Copy, Clone)]
+pub struct TextureFormat;
+
+impl ItemDecorator for TextureFormat {
+    /// Generate the conversion of a `#[texture_format]` enum into
+    /// `gfx::tex::Format`, describing every variant by its attributes:
+    ///
+    /// ```ignore
+    /// #[texture_format]
+    /// pub enum SurfaceFormat {
+    ///     #[format = "RGBA8"]
+    ///     Color,
+    ///     #[channels = "RGBA"]
+    ///     #[bits = "16"]
+    ///     #[float]
+    ///     Hdr,
+    ///     #[channels = "RG"]
+    ///     #[bits = "16"]
+    ///     #[integer]
+    ///     Ids,
+    /// }
+    /// ```
+    ///
+    /// `#[format]` names a `gfx::tex` format. Otherwise the format is made of
+    /// `#[channels]` and `#[bits]`, normalized unless marked `#[float]` or
+    /// `#[integer]`, and unsigned unless marked `#[signed]`. Combinations gfx
+    /// has no format for are reported as errors, as is `#[srgb]` until gfx
+    /// has sRGB formats. The conversion is `impl From<SurfaceFormat> for
+    /// gfx::tex::Format`, so the enum has to be public.
+    fn expand(&self, context: &mut ext::base::ExtCtxt, span: codemap::Span,
+              _meta_item: &ast::MetaItem, item: &ast::Item,
+              push: &mut FnMut(P<ast::Item>)) {
+        let item = &super::strip_unconfigured(context, item);
+        // Insert the `gfx` reexport module, unless a `#[gfx_crate]` path is given
+        let crate_root = super::crate_root(context, span, &item.attrs, |i| (*push)(i));
+        let path_root = crate_root.ident;
+
+        let (definition, generics) = match item.node {
+            ast::ItemEnum(ref definition, ref generics) => (definition, generics),
+            _ => {
+                context.span_err(span, "Only enums can be annotated with #[texture_format]");
+                return;
+            }
+        };
+
+        let mut arms = Vec::new();
+        for variant in definition.variants.iter() {
+            match variant.node.kind {
+                ast::TupleVariantKind(ref args) if args.is_empty() => (),
+                _ => {
+                    context.span_err(variant.span, "Texture format variants can not have fields");
+                    continue
+                },
+            }
+            let format = match format_expr(context, variant, path_root) {
+                Some(format) => format,
+                None => continue,
+            };
+            let path = context.path(variant.span, vec![item.ident, variant.node.name]);
+            let pat = context.pat(variant.span, ast::PatEnum(path, None));
+            arms.push(context.arm(variant.span, vec![pat], format));
+        }
+
+        // `impl From<Name> for gfx::tex::Format`
+        let format_ty = quote_ty!(context, $path_root::gfx::tex::Format);
+        let enum_ty = super::struct_type(context, span, item.ident, generics);
+        let value = context.ident_of("format");
+        let body = context.expr_match(span, context.expr_ident(span, value), arms);
+        let decl = context.fn_decl(vec![context.arg(span, value, enum_ty.clone())],
+                                   format_ty.clone());
+        let method = super::impl_method(context, span, "from", false, super::no_generics(),
+                                        decl, context.block_expr(body));
+        let from_path = context.path_all(span, true, vec![
+            context.ident_of("std"),
+            context.ident_of("convert"),
+            context.ident_of("From"),
+        ], Vec::new(), vec![enum_ty.clone()], Vec::new());
+        let impl_item = context.item(span, item.ident, Vec::new(), ast::ItemImpl(
+            ast::Unsafety::Normal,
+            ast::ImplPolarity::Positive,
+            generics.clone(),
+            Some(context.trait_ref(from_path)),
+            format_ty,
+            vec![P(method)]
+        ));
+        (*push)(super::fixup_extern_crate_paths(impl_item, &crate_root));
+    }
+}
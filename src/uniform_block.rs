@@ -0,0 +1,146 @@
+// Copyright 2015 The Gfx-rs Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp;
+use syntax::{ast, codemap, ext};
+use syntax::ext::base::ItemDecorator;
+use syntax::ptr::P;
+
+use layout::{self, round_up, Layout};
+
+#[derive(Copy, Clone)]
+pub struct UniformBlock;
+
+impl ItemDecorator for UniformBlock {
+    /// Check that the `struct` matches the std140 layout of a uniform block
+    fn expand(&self, context: &mut ext::base::ExtCtxt, span: codemap::Span,
+              meta_item: &ast::MetaItem, item: &ast::Item,
+              _push: &mut FnMut(P<ast::Item>)) {
+        let item = &super::strip_unconfigured(context, item);
+        let definition = match item.node {
+            ast::ItemStruct(ref definition, _) => definition,
+            _ => {
+                context.span_err(span, "Only structs can be annotated with #[uniform_block]");
+                return;
+            }
+        };
+
+        match meta_item.node {
+            ast::MetaWord(_) => (), //expected
+            _ => {
+                context.span_err(meta_item.span, "#[uniform_block] needs no param");
+            }
+        }
+
+        if !super::has_repr(&item.attrs, "C") {
+            context.span_err(span, "#[uniform_block] requires #[repr(C)] on the struct");
+        }
+
+        check_layout(context, definition, "std140", layout::std140_layout);
+    }
+}
+
+/// Compare the Rust layout of every field to the given GPU one
+fn check_layout(cx: &mut ext::base::ExtCtxt, definition: &ast::StructDef,
+                standard: &str, gpu_layout: fn(&ast::Ty) -> Option<Layout>) -> Option<Layout> {
+    let mut rust_offset = 0;
+    let mut gpu_offset = 0;
+    let mut rust_align = 1;
+    let mut gpu_align = 1;
+    let mut is_valid = true;
+    for field in definition.fields.iter() {
+        let ty = &field.node.ty;
+        match (layout::rust_layout(ty), gpu_layout(ty)) {
+            (Some(rust), Some(gpu)) => {
+                let rust_start = round_up(rust_offset, rust.align);
+                let gpu_start = round_up(gpu_offset, gpu.align);
+                if rust_start != gpu_start {
+                    cx.span_err(field.span, &format!(
+                        "Field is at offset {} in Rust but {} places it at {}",
+                        rust_start, standard, gpu_start
+                    ));
+                    is_valid = false;
+                } else if rust.size != gpu.size {
+                    cx.span_err(field.span, &format!(
+                        "Field takes {} bytes in Rust but {} requires {}",
+                        rust.size, standard, gpu.size
+                    ));
+                    is_valid = false;
+                }
+                rust_offset = rust_start + rust.size;
+                gpu_offset = gpu_start + gpu.size;
+                rust_align = cmp::max(rust_align, rust.align);
+                gpu_align = cmp::max(gpu_align, gpu.align);
+            },
+            _ => {
+                cx.span_err(field.span, &format!(
+                    "Unsupported {} member type: `{:?}`", standard, ty.node
+                ));
+                is_valid = false;
+            },
+        }
+    }
+    if is_valid {
+        Some(Layout {
+            size: round_up(rust_offset, rust_align),
+            align: gpu_align,
+        })
+    } else {
+        None
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct StorageBuffer;
+
+impl ItemDecorator for StorageBuffer {
+    /// Check that the `struct` matches the std430 layout of a storage buffer
+    /// element, including the stride of consecutive elements
+    fn expand(&self, context: &mut ext::base::ExtCtxt, span: codemap::Span,
+              meta_item: &ast::MetaItem, item: &ast::Item,
+              _push: &mut FnMut(P<ast::Item>)) {
+        let item = &super::strip_unconfigured(context, item);
+        let definition = match item.node {
+            ast::ItemStruct(ref definition, _) => definition,
+            _ => {
+                context.span_err(span, "Only structs can be annotated with #[storage_buffer]");
+                return;
+            }
+        };
+
+        match meta_item.node {
+            ast::MetaWord(_) => (), //expected
+            _ => {
+                context.span_err(meta_item.span, "#[storage_buffer] needs no param");
+            }
+        }
+
+        if !super::has_repr(&item.attrs, "C") {
+            context.span_err(span, "#[storage_buffer] requires #[repr(C)] on the struct");
+        }
+
+        // elements of a std430 array are aligned to the largest member
+        // alignment, while Rust pads them to the largest Rust one
+        if let Some(element) = check_layout(context, definition, "std430", layout::std430_layout) {
+            let stride = round_up(element.size, element.align);
+            if stride != element.size {
+                context.span_err(span, &format!(
+                    "Elements take {} bytes in Rust but std430 places them {} bytes apart, \
+                    add {} bytes of padding at the end of the struct",
+                    element.size, stride, stride - element.size
+                ));
+            }
+        }
+    }
+}
@@ -12,15 +12,23 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fmt;
 use std::str::FromStr;
 use syntax::{ast, ext};
 use syntax::ext::build::AstBuilder;
-use syntax::ext::deriving::generic;
+use syntax::owned_slice::OwnedSlice;
 use syntax::{attr, codemap};
 use syntax::parse::token;
+use syntax::print::pprust;
 use syntax::ptr::P;
-use syntax::ext::base::ItemDecorator;
+use syntax::util::small_vector::SmallVector;
+use syntax::ext::base::{DummyResult, ItemDecorator, ItemModifier, MacEager, MacResult};
+
+use glsl;
+use layout;
+use obj;
 
 /// A component modifier.
 #[derive(Copy, Clone, PartialEq)]
@@ -38,6 +46,15 @@ enum Modifier {
     ///
     /// Specifies a high-precision float.
     AsDouble,
+    /// Corresponds to the `#[as_int]` attribute.
+    ///
+    /// Exposes the component as a true integer (`ivec`/`uvec`) attribute,
+    /// which is also the default for integer components.
+    AsInt,
+    /// Corresponds to the `#[as_half]` attribute.
+    ///
+    /// Interprets `u16` components as half-precision floats.
+    AsHalf,
 }
 
 impl fmt::Debug for Modifier {
@@ -46,6 +63,8 @@ impl fmt::Debug for Modifier {
             Modifier::Normalized => write!(f, "normalized"),
             Modifier::AsFloat => write!(f, "as_float"),
             Modifier::AsDouble => write!(f, "as_double"),
+            Modifier::AsInt => write!(f, "as_int"),
+            Modifier::AsHalf => write!(f, "as_half"),
         }
     }
 }
@@ -58,6 +77,8 @@ impl FromStr for Modifier {
             "normalized" => Ok(Modifier::Normalized),
             "as_float" => Ok(Modifier::AsFloat),
             "as_double" => Ok(Modifier::AsDouble),
+            "as_int" => Ok(Modifier::AsInt),
+            "as_half" => Ok(Modifier::AsHalf),
             _ => Err(()),
         }
     }
@@ -87,20 +108,147 @@ fn find_modifier(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
     })
 }
 
+/// The part a field plays in skinning, given by `#[skin_weights]` or
+/// `#[skin_indices]`
+#[derive(Copy, Clone, PartialEq)]
+enum Skin {
+    Weights,
+    Indices,
+}
+
+impl Skin {
+    fn of(field: &ast::StructField) -> Option<Skin> {
+        if attr::contains_name(&field.node.attrs, "skin_weights") {
+            Some(Skin::Weights)
+        } else if attr::contains_name(&field.node.attrs, "skin_indices") {
+            Some(Skin::Indices)
+        } else {
+            None
+        }
+    }
+
+    /// The conventional attribute name, used unless given with `#[name]`
+    fn name(&self) -> &'static str {
+        match *self {
+            Skin::Weights => "a_BoneWeights",
+            Skin::Indices => "a_BoneIndices",
+        }
+    }
+
+    /// The modifier of the attribute unless given, indices being integers
+    fn modifier(&self) -> Option<Modifier> {
+        match *self {
+            Skin::Weights => None,
+            Skin::Indices => Some(Modifier::AsInt),
+        }
+    }
+}
+
+/// Check the `#[skin_weights]` and `#[skin_indices]` pair of a skinned
+/// format: both are given, once, as vectors of the same length, with float
+/// or normalized weights and integer indices.
+fn check_skin(cx: &mut ext::base::ExtCtxt, definition: &ast::StructDef) {
+    let mut fields: Vec<(Skin, &ast::StructField)> = Vec::new();
+    for field in definition.fields.iter() {
+        let skin = match Skin::of(field) {
+            Some(skin) => skin,
+            None => continue,
+        };
+        if fields.iter().any(|&(other, _)| other == skin) {
+            cx.span_err(field.span, &format!("Only one field can be the skin {}",
+                                             if skin == Skin::Weights { "weights" } else { "indices" }));
+            continue
+        }
+        let count = match components(&field.node.ty) {
+            Some((ref component, Some(count))) if count <= 4 => {
+                // read quietly, the attribute itself reports extra modifiers
+                let modifier = field.node.attrs.iter().filter_map(|attribute| {
+                    match attribute.node.value.node {
+                        ast::MetaWord(ref word) => word.parse().ok(),
+                        _ => None,
+                    }
+                }).next();
+                let is_integer = ["u8", "u16", "u32", "i8", "i16", "i32"].contains(&&component[..]);
+                match (skin, modifier) {
+                    (Skin::Weights, None) if &component[..] == "f32" => (),
+                    (Skin::Weights, Some(Modifier::Normalized)) if is_integer => (),
+                    (Skin::Indices, None) | (Skin::Indices, Some(Modifier::AsInt)) if is_integer => (),
+                    (Skin::Weights, _) => cx.span_err(field.span, "Skin weights are `f32` or \
+                                                                   `#[normalized]` integers"),
+                    (Skin::Indices, _) => cx.span_err(field.span, "Skin indices are integers, \
+                                                                   used as integer attributes"),
+                }
+                count
+            },
+            _ => {
+                cx.span_err(field.span, "Skin weights and indices are vectors of up to 4 \
+                                         components, like `[f32; 4]` and `[u8; 4]`");
+                continue
+            },
+        };
+        if let Some(&(_, other)) = fields.first() {
+            if components(&other.node.ty).and_then(|(_, other_count)| other_count) != Some(count) {
+                cx.span_err(field.span, "Skin weights and indices need the same number of \
+                                         components, one weight per bone index");
+            }
+        }
+        fields.push((skin, field));
+    }
+    if fields.len() == 1 {
+        let (skin, field) = fields[0];
+        cx.span_err(field.span, &format!("Skin {} are given without the {}",
+            if skin == Skin::Weights { "weights" } else { "indices" },
+            if skin == Skin::Weights { "`#[skin_indices]`" } else { "`#[skin_weights]`" }
+        ));
+    }
+}
+
+/// Report `#[packed(...)]` fields. `gfx::attrib::Type` has no packed
+/// formats like `int_2_10_10_10` yet, so these can not be described.
+fn reject_packed(cx: &mut ext::base::ExtCtxt, attributes: &[ast::Attribute]) -> bool {
+    let mut found = false;
+    for attribute in attributes.iter() {
+        match attribute.node.value.node {
+            ast::MetaList(ref name, ref formats) if &name[..] == "packed" => {
+                attr::mark_used(attribute);
+                found = true;
+                match formats.first().map(|format| &format.node) {
+                    Some(&ast::MetaWord(ref format)) if &format[..] == "int_2_10_10_10" =>
+                        cx.span_err(attribute.span, "Packed `int_2_10_10_10` components are not \
+                                                     supported by `gfx::attrib::Type` yet, use \
+                                                     `#[normalized]` `[i16; 4]` instead"),
+                    _ => cx.span_err(attribute.span, "Unknown packed format, expected \
+                                                      `#[packed(int_2_10_10_10)]`"),
+                }
+            },
+            _ => (),
+        }
+    }
+    found
+}
+
+/// The note attached to errors about unsupported attribute types
+const COMPONENT_TYPES: &'static str = "Attributes are scalars, `[T; N]` vectors or `[[T; N]; M]` \
+                                       matrices of `f32`, `f64`, `u8`, `u16`, `u32`, `i8`, `i16` \
+                                       or `i32` components, or `#[vertex_format]` structs \
+                                       declared before";
+
 /// Find a `gfx::attrib::Type` that describes the given type identifier.
 fn decode_type(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
                ty_ident: &ast::Ident, modifier: Option<Modifier>,
                path_root: ast::Ident) -> P<ast::Expr> {
     let ty_str = ty_ident.name.as_str();
     match ty_str {
-        "f32" | "f64" => {
+        "f16" | "f32" | "f64" => {
             let kind = cx.ident_of(match modifier {
                 None | Some(Modifier::AsFloat) => "Default",
                 Some(Modifier::AsDouble) => "Precision",
-                Some(Modifier::Normalized) => {
+                Some(Modifier::Normalized) | Some(Modifier::AsInt) |
+                Some(Modifier::AsHalf) => {
                     cx.span_warn(span, &format!(
-                        "Incompatible float modifier attribute: `#[{:?}]`", modifier
+                        "Incompatible float modifier attribute: `#[{:?}]`", modifier.unwrap()
                     ));
+                    cx.span_note(span, "Float components support `#[as_float]` and `#[as_double]`");
                     ""
                 }
             });
@@ -108,19 +256,25 @@ fn decode_type(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
             quote_expr!(cx, $path_root::gfx::attrib::Type::Float($path_root::gfx::attrib::FloatSubType::$kind,
                                                                  $path_root::gfx::attrib::FloatSize::$size))
         },
+        "u16" if modifier == Some(Modifier::AsHalf) => {
+            quote_expr!(cx, $path_root::gfx::attrib::Type::Float($path_root::gfx::attrib::FloatSubType::Default,
+                                                                 $path_root::gfx::attrib::FloatSize::F16))
+        },
         "u8" | "u16" | "u32" | "u64" |
         "i8" | "i16" | "i32" | "i64" => {
             let sign = cx.ident_of({
                 if ty_str.starts_with("i") { "Signed" } else { "Unsigned" }
             });
             let kind = cx.ident_of(match modifier {
-                None => "Raw",
+                None | Some(Modifier::AsInt) => "Raw",
                 Some(Modifier::Normalized) => "Normalized",
                 Some(Modifier::AsFloat) => "AsFloat",
-                Some(Modifier::AsDouble) => {
+                Some(Modifier::AsDouble) | Some(Modifier::AsHalf) => {
                     cx.span_warn(span, &format!(
-                        "Incompatible int modifier attribute: `#[{:?}]`", modifier
+                        "Incompatible int modifier attribute: `#[{:?}]`", modifier.unwrap()
                     ));
+                    cx.span_note(span, "Integer components support `#[normalized]`, `#[as_float]`, \
+                                        `#[as_int]` and `#[as_half]` for `u16`");
                     ""
                 }
             });
@@ -130,18 +284,17 @@ fn decode_type(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
                                                                $path_root::gfx::attrib::SignFlag::$sign))
         },
         ty_str => {
-            cx.span_err(span, &format!("Unrecognized component type: `{:?}`",
-                                      ty_str));
+            cx.span_err(span, &format!("Unrecognized component type: `{}`", ty_str));
+            cx.span_note(span, COMPONENT_TYPES);
             cx.expr_tuple(span, vec![])
         },
     }
 }
 
 fn decode_count_and_type(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
-                         field: &ast::StructField,
+                         ty: &ast::Ty, modifier: Option<Modifier>,
                          path_root: ast::Ident) -> (P<ast::Expr>, P<ast::Expr>) {
-    let modifier = find_modifier(cx, span, &field.node.attrs);
-    match field.node.ty.node {
+    match ty.node {
         ast::TyPath(_,ref p) => (
             cx.expr_lit(span, ast::LitInt(1, ast::UnsuffixedIntLit(ast::Plus))),
             decode_type(cx, span, &p.segments[0].identifier, modifier, path_root),
@@ -151,71 +304,1664 @@ fn decode_count_and_type(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
                 decode_type(cx, span, &p.segments[0].identifier, modifier, path_root)
             },
             _ => {
-                cx.span_err(span, &format!("Unsupported fixed vector sub-type: \
-                                          `{:?}`",pty.node));
+                cx.span_err(span, &format!("Unsupported fixed vector sub-type: `{}`",
+                                           pprust::ty_to_string(pty)));
+                cx.span_note(span, COMPONENT_TYPES);
                 cx.expr_tuple(span, vec![])
             },
         }),
         _ => {
-            cx.span_err(span, &format!("Unsupported attribute type: `{:?}`",
-                                      field.node.ty.node));
+            cx.span_err(span, &format!("Unsupported attribute type: `{}`",
+                                       pprust::ty_to_string(ty)));
+            cx.span_note(span, COMPONENT_TYPES);
             (cx.expr_tuple(span, vec![]), cx.expr_tuple(span, vec![]))
         },
     }
 }
 
-/// Generates the the method body for `gfx::VertexFormat::generate`.
+/// Check if the type is a component type, like `f32`
+fn is_component(ty: &ast::Ty) -> bool {
+    match ty.node {
+        ast::TyPath(_, ref p) if p.segments.len() == 1 => {
+            match p.segments[0].identifier.name.as_str() {
+                "f16" | "f32" | "f64" |
+                "u8" | "u16" | "u32" | "u64" |
+                "i8" | "i16" | "i32" | "i64" => true,
+                _ => false,
+            }
+        },
+        _ => false,
+    }
+}
+
+/// Look up the other vertex format a field type refers to, whose attributes
+/// are flattened into the outer format. Fails with the formats an ambiguous
+/// path may refer to. Other types are left to `decode_count_and_type`,
+/// which reports unknown ones.
+fn nested_format(cx: &ext::base::ExtCtxt, ty: &ast::Ty)
+                 -> Option<Result<Vec<glsl::Var>, Vec<String>>> {
+    match ty.node {
+        ast::TyPath(None, ref path) if !is_component(ty) => {
+            match with_formats(|formats| super::find_entry(cx, &formats.borrow(), path)) {
+                Err(ref candidates) if candidates.is_empty() => None,
+                found => Some(found),
+            }
+        },
+        _ => None,
+    }
+}
+
+/// Extract the column count and the column type of a matrix field
+/// (`[[T; N]; M]`). Matrices are bound as `M` consecutive vector attributes.
+fn decode_matrix<'a>(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+                     ty: &'a ast::Ty) -> Option<(usize, &'a ast::Ty)> {
+    match ty.node {
+        ast::TyFixedLengthVec(ref column, ref expr) => match column.node {
+            ast::TyFixedLengthVec(..) => match expr.node {
+                ast::ExprLit(ref lit) => match lit.node {
+                    ast::LitInt(count, _) => Some((count as usize, &**column)),
+                    _ => None,
+                },
+                _ => {
+                    cx.span_err(span, "Matrix column count must be an integer literal");
+                    None
+                },
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The GLSL type a component type (`f32`, `[u8; 4]`) is exposed to the
+/// shader as, given its modifier.
+fn glsl_type(ty: &ast::Ty, modifier: Option<Modifier>) -> Option<String> {
+    let (component, count) = match ty.node {
+        ast::TyPath(_, ref p) => (p.segments[0].identifier, 1),
+        ast::TyFixedLengthVec(ref pty, ref expr) => match (&pty.node, layout::array_len(expr)) {
+            (&ast::TyPath(_, ref p), Some(count)) => (p.segments[0].identifier, count),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let base = match (&component.name.as_str()[..], modifier) {
+        ("f64", _) | (_, Some(Modifier::AsDouble)) => "double",
+        ("f16", _) | ("f32", _) | (_, Some(Modifier::Normalized)) |
+        (_, Some(Modifier::AsFloat)) | (_, Some(Modifier::AsHalf)) => "float",
+        (name, _) if name.starts_with("i") => "int",
+        _ => "uint",
+    };
+    Some(glsl::vector_type(base, count))
+}
+
+/// Access the attributes of the vertex formats expanded so far, by
+/// `item_key`, so that they can be checked against shader sources
+fn with_formats<T, F>(f: F) -> T where F: FnOnce(&RefCell<HashMap<String, Vec<glsl::Var>>>) -> T {
+    // declared in a function, where `gfx_macros_codegen` leaves the macro to
+    // the compiler, as it only expands the item macros it knows
+    thread_local!(
+        static FORMATS: RefCell<HashMap<String, Vec<glsl::Var>>> =
+            RefCell::new(HashMap::new())
+    );
+    FORMATS.with(f)
+}
+
+/// Forget the vertex formats expanded so far
+pub fn clear_registry() {
+    with_formats(|formats| formats.borrow_mut().clear());
+    with_channels(|channels| channels.borrow_mut().clear());
+}
+
+/// The attributes of the previously expanded `#[vertex_format]` struct a
+/// path refers to, see `find_entry`
+pub fn signature(cx: &ext::base::ExtCtxt, path: &ast::Path) -> Result<Vec<glsl::Var>, Vec<String>> {
+    with_formats(|formats| super::find_entry(cx, &formats.borrow(), path))
+}
+
+/// Order the attribute pushes so that the attribute of `#[location = "N"]` ends
+/// up at index `N` of the generated vector. A matrix pinned at `N` occupies
+/// the locations `N` to `N+M-1`, and the remaining attributes fill the free
+/// locations in declaration order.
+fn order_by_location<T>(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+                        field_pushes: Vec<(Option<(usize, codemap::Span)>, Vec<T>)>,
+                        has_nested: bool) -> Vec<T> {
+    if field_pushes.iter().all(|&(location, _)| location.is_none()) {
+        return field_pushes.into_iter().flat_map(|(_, pushes)| pushes.into_iter()).collect()
+    }
+    if has_nested {
+        cx.span_err(span, "`#[location]` can not be combined with nested vertex formats, \
+                           their attribute count is unknown");
+    }
+    let total = field_pushes.iter().fold(0, |total, &(_, ref pushes)| total + pushes.len());
+    let mut slots: Vec<Option<(T, codemap::Span)>> = (0..total).map(|_| None).collect();
+    let mut unpinned = Vec::new();
+    for (location, pushes) in field_pushes.into_iter() {
+        let (location, field_span) = match location {
+            Some(location) => location,
+            None => {
+                unpinned.extend(pushes.into_iter());
+                continue
+            },
+        };
+        for (i, push) in pushes.into_iter().enumerate() {
+            let slot = location + i;
+            if slot >= total {
+                cx.span_err(field_span, &format!(
+                    "Location {} is out of range, the format only has {} attributes",
+                    slot, total
+                ));
+            } else if let Some((_, first)) = slots[slot] {
+                cx.span_err(field_span, &format!("Location {} is assigned twice", slot));
+                cx.span_note(first, "first assigned here");
+            } else {
+                slots[slot] = Some((push, field_span));
+            }
+        }
+    }
+    let mut unpinned = unpinned.into_iter();
+    slots.into_iter().filter_map(|slot|
+        slot.map(|(push, _)| push).or_else(|| unpinned.next())
+    ).collect()
+}
+
+/// Generates the runtime offset of a field in the struct.
+fn field_offset(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+                struct_ty: &P<ast::Ty>, access: super::FieldAccess) -> P<ast::Expr> {
+    let x = cx.expr_ident(span, cx.ident_of("x"));
+    let field = access.expr(cx, span, x);
+    quote_expr!(cx, unsafe {
+        let x: $struct_ty = ::std::mem::uninitialized();
+        let offset = (&$field as *const _ as usize) -
+            (&x as *const _ as usize);
+        ::std::mem::forget(x);
+        offset
+    })
+}
+
+/// Generates the inherent `offset_<field>()` functions, which are named
+/// `offset_0`, `offset_1`, etc for tuple structs. The offsets are
+/// computed at expansion time for `#[repr(C)]` and `#[repr(packed)]` structs
+/// of plain fields, and at runtime otherwise.
+fn offset_methods(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+                  item: &ast::Item, definition: &ast::StructDef,
+                  struct_ty: &P<ast::Ty>) -> Vec<P<ast::ImplItem>> {
+    let packed = super::has_repr(&item.attrs, "packed");
+    let offsets = if packed || super::has_repr(&item.attrs, "C") {
+        let types: Vec<&ast::Ty> = definition.fields.iter().map(|f| &*f.node.ty).collect();
+        layout::struct_layout(&types, packed).map(|(offsets, _)| offsets)
+    } else {
+        None
+    };
+    definition.fields.iter().enumerate().filter_map(|(i, def)| {
+        if super::is_skipped(def) {
+            return None
+        }
+        let access = super::FieldAccess::of(def, i);
+        let offset = match offsets {
+            Some(ref offsets) => cx.expr_usize(def.span, offsets[i]),
+            None => field_offset(cx, def.span, struct_ty, access),
+        };
+        let name = format!("offset_{}", access.as_string());
+        let decl = cx.fn_decl(Vec::new(), quote_ty!(cx, usize));
+        let mut method = super::impl_method(cx, span, &name, false, super::no_generics(),
+                                            decl, cx.block_expr(offset));
+        method.vis = ast::Public;
+        Some(P(method))
+    }).collect()
+}
+
+/// Check that any bit pattern is a valid value of the type, which excludes
+/// `bool` components
+fn is_plain_data(ty: &ast::Ty) -> bool {
+    match ty.node {
+        ast::TyPath(_, ref p) => p.segments.last().map_or(false, |segment|
+            segment.identifier.name.as_str() != "bool"
+        ),
+        ast::TyFixedLengthVec(ref elem, _) => is_plain_data(elem),
+        _ => false,
+    }
+}
+
+/// Generates `pub fn as_bytes(&[Self]) -> &[u8]` and
+/// `pub fn from_bytes(&[u8]) -> Option<&[Self]>`, viewing vertex slices as
+/// raw bytes. They are only generated when the layout is known at expansion
+/// time to have no implicit padding and only plain components, so that both
+/// views are safe. `from_bytes` returns `None` if the length is not a
+/// multiple of the vertex size, or the bytes are not aligned for the vertex.
+fn byte_methods(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+                item: &ast::Item, definition: &ast::StructDef,
+                struct_ty: &P<ast::Ty>) -> Vec<P<ast::ImplItem>> {
+    let packed = super::has_repr(&item.attrs, "packed");
+    if !packed && !super::has_repr(&item.attrs, "C") {
+        return Vec::new()
+    }
+    let types: Vec<&ast::Ty> = definition.fields.iter().map(|f| &*f.node.ty).collect();
+    if !types.iter().all(|ty| is_plain_data(ty)) {
+        return Vec::new()
+    }
+    let (offsets, struct_layout) = match layout::struct_layout(&types, packed) {
+        Some(result) => result,
+        None => return Vec::new(),
+    };
+    let mut end = 0;
+    for (ty, &offset) in types.iter().zip(offsets.iter()) {
+        if offset != end {
+            return Vec::new()
+        }
+        end = offset + layout::rust_layout(ty).map_or(0, |l| l.size);
+    }
+    if end == 0 || struct_layout.size != end {
+        return Vec::new()
+    }
+    let size = cx.expr_usize(span, struct_layout.size);
+    let align = cx.expr_usize(span, if packed { 1 } else { struct_layout.align });
+
+    let vertices = cx.ident_of("vertices");
+    let decl_as = cx.fn_decl(
+        vec![cx.arg(span, vertices, quote_ty!(cx, &[$struct_ty]))],
+        quote_ty!(cx, &[u8])
+    );
+    let body_as = quote_expr!(cx, unsafe {
+        ::std::slice::from_raw_parts($vertices.as_ptr() as *const u8, $vertices.len() * $size)
+    });
+    let mut method_as = super::impl_method(cx, span, "as_bytes", false, super::no_generics(),
+                                           decl_as, cx.block_expr(body_as));
+    method_as.vis = ast::Public;
+
+    let bytes = cx.ident_of("bytes");
+    let decl_from = cx.fn_decl(
+        vec![cx.arg(span, bytes, quote_ty!(cx, &[u8]))],
+        quote_ty!(cx, Option<&[$struct_ty]>)
+    );
+    let body_from = quote_expr!(cx, {
+        if $bytes.is_empty() {
+            return Some(&[][..])
+        }
+        if $bytes.len() % $size != 0 || $bytes.as_ptr() as usize % $align != 0 {
+            return None
+        }
+        Some(unsafe {
+            ::std::slice::from_raw_parts($bytes.as_ptr() as *const $struct_ty, $bytes.len() / $size)
+        })
+    });
+    let mut method_from = super::impl_method(cx, span, "from_bytes", false, super::no_generics(),
+                                             decl_from, cx.block_expr(body_from));
+    method_from.vis = ast::Public;
+    vec![P(method_as), P(method_from)]
+}
+
+/// Split an HLSL semantic into its name and index, `TEXCOORD3` into
+/// `("TEXCOORD", 3)`. Semantics without a trailing number have index 0.
+fn split_semantic(semantic: &str) -> (&str, usize) {
+    let digits = semantic.len() - semantic.chars().rev()
+        .take_while(|c| c.is_digit(10)).count();
+    match semantic[digits..].parse() {
+        Ok(index) => (&semantic[..digits], index),
+        Err(_) => (semantic, 0),
+    }
+}
+
+/// Generates `pub fn attribute_semantics() -> Vec<(String, String)>`, pairing
+/// the name of every attribute of `attribute_formats()` with its HLSL
+/// semantic for D3D backends. Fields take theirs from
+/// `#[semantic = "TEXCOORD0"]`, with the columns of a matrix using
+/// consecutive indices, and default to the attribute name otherwise.
+fn semantics_method(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+                    definition: &ast::StructDef, naming: &super::Naming,
+                    struct_ty: &P<ast::Ty>) -> P<ast::ImplItem> {
+    let mut semantics: Vec<(String, String)> = Vec::new();
+    let mut nested = Vec::new();
+    let channels = channels(cx, definition, naming).fields;
+    for (def, (_, channel)) in definition.fields.iter().zip(channels.into_iter()) {
+        let semantic = super::find_str(cx, def.span, &def.node.attrs, "semantic");
+        let (name, ty) = match channel {
+            Channel::Attribute(name, ty, _) => (name, ty),
+            Channel::Nested => {
+                if semantic.is_some() {
+                    cx.span_err(def.span, "`#[semantic]` is not supported on nested vertex \
+                                           formats, the nested fields carry their own");
+                }
+                nested.push(def.node.ty.clone());
+                continue
+            },
+            Channel::Skipped(_) => continue,
+        };
+        let semantic = match semantic {
+            Some(semantic) => semantic,
+            None => continue,
+        };
+        let valid = semantic.chars().next().map_or(false, |c| c.is_alphabetic() || c == '_') &&
+                    semantic.chars().all(|c| c.is_alphanumeric() || c == '_');
+        if !valid {
+            cx.span_err(def.span, &format!("Invalid HLSL semantic `{}`", semantic));
+            continue
+        }
+        let columns = match ty.node {
+            ast::TyFixedLengthVec(ref column, ref count) => match column.node {
+                ast::TyFixedLengthVec(..) => layout::array_len(count),
+                _ => None,
+            },
+            _ => None,
+        };
+        let (base, index) = split_semantic(&semantic);
+        let fields: Vec<(String, String)> = match columns {
+            Some(columns) => (0..columns).map(|i|
+                (format!("{}_{}", name, i), format!("{}{}", base, index + i))
+            ).collect(),
+            None => vec![(name, semantic.to_string())],
+        };
+        for (name, semantic) in fields {
+            if semantics.iter().any(|&(_, ref other)| split_semantic(other) == split_semantic(&semantic)) {
+                cx.span_err(def.span, &format!("HLSL semantic `{}` is used twice", semantic));
+            }
+            semantics.push((name, semantic));
+        }
+    }
+
+    // the explicit semantics, followed by the ones of the nested formats
+    let mut pushes: Vec<P<ast::Stmt>> = semantics.iter().map(|&(ref name, ref semantic)| {
+        let (name, semantic) = (&name[..], &semantic[..]);
+        quote_stmt!(cx, { table.push(($name.to_string(), $semantic.to_string())); }).unwrap()
+    }).collect();
+    pushes.extend(nested.into_iter().map(|ty|
+        quote_stmt!(cx, { table.extend(<$ty>::attribute_semantics().into_iter()); }).unwrap()
+    ));
+    // only filled from the explicit and nested semantics, if any
+    let table = if pushes.is_empty() {
+        quote_stmt!(cx, let table: Vec<(String, String)> = Vec::new();).unwrap()
+    } else {
+        quote_stmt!(cx, let mut table: Vec<(String, String)> = Vec::new();).unwrap()
+    };
+    let body = quote_expr!(cx, {
+        $table;
+        $pushes
+        let formats = <$struct_ty>::attribute_formats();
+        formats.iter().map(|&(ref name, _)| {
+            let name = name.to_string();
+            let semantic = table.iter().find(|&&(ref other, _)| *other == name)
+                                .map(|&(_, ref semantic)| semantic.clone())
+                                .unwrap_or_else(|| name.clone());
+            (name, semantic)
+        }).collect()
+    });
+    let decl = cx.fn_decl(Vec::new(), quote_ty!(cx, Vec<(String, String)>));
+    let mut method = super::impl_method(cx, span, "attribute_semantics", false,
+                                        super::no_generics(), decl, cx.block_expr(body));
+    method.vis = ast::Public;
+    P(method)
+}
+
+/// Generates `pub fn linearize(&mut self)` for formats with `#[srgb]` color
+/// fields, converting their red, green and blue components from sRGB to
+/// linear in place. `gfx::attrib::Format` can not mark attributes for the
+/// conversion, and vertex fetches never decode sRGB, so colors authored in
+/// sRGB are converted once when building the vertices. The fields are
+/// `[f32; N]` or `#[normalized]` `[u8; N]` colors of 3 or 4 components.
+fn srgb_method(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+               definition: &ast::StructDef) -> Option<P<ast::ImplItem>> {
+    let mut stmts = Vec::new();
+    for (index, field) in definition.fields.iter().enumerate() {
+        if !attr::contains_name(&field.node.attrs, "srgb") {
+            continue
+        }
+        let component = match field.node.ty.node {
+            ast::TyFixedLengthVec(ref elem, ref count) => match layout::array_len(count) {
+                Some(3) | Some(4) => pprust::ty_to_string(elem),
+                _ => String::new(),
+            },
+            _ => String::new(),
+        };
+        let normalized = attr::contains_name(&field.node.attrs, "normalized");
+        // integer components are rounded to the closest value
+        let (scale, round) = match &component[..] {
+            "f32" => (1.0, 0.0),
+            "u8" if normalized => (255.0, 0.5),
+            _ => {
+                cx.span_err(field.span, "`#[srgb]` colors are `[f32; N]` or `#[normalized]` \
+                                         `[u8; N]` with 3 or 4 components");
+                continue
+            },
+        };
+        let scale = float_expr(cx, span, scale, "f32");
+        let round = float_expr(cx, span, round, "f32");
+        let ty = cx.ty_ident(span, cx.ident_of(&component));
+        let access = super::FieldAccess::of(field, index).expr(cx, field.span, cx.expr_self(span));
+        // the alpha channel is linear already
+        stmts.push(quote_stmt!(cx, {
+            for i in 0..3 {
+                let c = $access[i] as f32 / $scale;
+                let l = if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) };
+                $access[i] = (l * $scale + $round) as $ty;
+            }
+        }).unwrap());
+    }
+    if stmts.is_empty() {
+        return None
+    }
+    let decl = cx.fn_decl(vec![
+        ast::Arg::new_self(span, ast::MutImmutable, cx.ident_of("self")),
+    ], quote_ty!(cx, ()));
+    let mut method = super::mut_self(super::impl_method(cx, span, "linearize", true,
+                                                        super::no_generics(), decl,
+                                                        cx.block(span, stmts, None)));
+    method.vis = ast::Public;
+    Some(P(method))
+}
+
+/// The `(kind, sub type, bits, sign)` code of a component type, as hashed by
+/// `format_hash()`, following `decode_type`
+fn type_code(ty: &ast::Ty, modifier: Option<Modifier>) -> Option<[u8; 4]> {
+    let ty_str = match ty.node {
+        ast::TyPath(_, ref p) => p.segments[0].identifier.name.as_str(),
+        ast::TyFixedLengthVec(ref pty, _) => match pty.node {
+            ast::TyPath(_, ref p) => p.segments[0].identifier.name.as_str(),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let bits = match ty_str[1..].parse() {
+        Ok(bits) => bits,
+        Err(_) => return None,
+    };
+    match ty_str {
+        "f16" | "f32" | "f64" =>
+            Some([2, if modifier == Some(Modifier::AsDouble) { 1 } else { 0 }, bits, 0]),
+        "u16" if modifier == Some(Modifier::AsHalf) => Some([2, 0, 16, 0]),
+        "u8" | "u16" | "u32" | "i8" | "i16" | "i32" => Some([1, match modifier {
+            Some(Modifier::Normalized) => 1,
+            Some(Modifier::AsFloat) => 2,
+            _ => 0,
+        }, bits, if ty_str.starts_with("i") { 1 } else { 0 }]),
+        _ => None,
+    }
+}
+
+/// The bytes of an attribute hashed by `format_hash()`: the name and a NUL,
+/// the type code, the element count, the offset in little endian, the stride
+/// and the instance rate
+fn hash_bytes(name: &str, code: [u8; 4], count: u8, offset: u32, stride: u8, rate: u8) -> Vec<u8> {
+    let mut bytes: Vec<u8> = name.bytes().collect();
+    bytes.push(0);
+    bytes.extend(code.iter().cloned());
+    bytes.extend([count, offset as u8, (offset >> 8) as u8, (offset >> 16) as u8,
+                  (offset >> 24) as u8, stride, rate].iter().cloned());
+    bytes
+}
+
+/// The 64-bit FNV-1a hash of the bytes
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte|
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    )
+}
+
+/// Generates `pub fn format_hash() -> u64`, a 64-bit FNV-1a hash of the
+/// names, types, offsets, strides and instance rates of `attribute_formats()`,
+/// for assets to record the layout they were baked with. The types are
+/// hashed by explicit codes (see `type_code`) rather than their `Debug`
+/// output, so that the hash does not change along with gfx. It is computed
+/// on call, as the offsets of nested formats are only known to the compiler.
+fn hash_method(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+               struct_ty: &P<ast::Ty>, path_root: ast::Ident) -> P<ast::ImplItem> {
+    let body = quote_expr!(cx, {
+        use self::$path_root::gfx::attrib::{Type, IntSubType, IntSize, SignFlag,
+                                            FloatSubType, FloatSize};
+        let mut hash = 0xcbf29ce484222325u64;
+        for &(ref name, ref format) in <$struct_ty>::attribute_formats().iter() {
+            let code: [u8; 4] = match format.elem_type {
+                Type::Int(ref sub, ref size, ref sign) => [1, match *sub {
+                    IntSubType::Raw => 0,
+                    IntSubType::Normalized => 1,
+                    IntSubType::AsFloat => 2,
+                }, match *size {
+                    IntSize::U8 => 8,
+                    IntSize::U16 => 16,
+                    IntSize::U32 => 32,
+                }, match *sign {
+                    SignFlag::Unsigned => 0,
+                    SignFlag::Signed => 1,
+                }],
+                Type::Float(ref sub, ref size) => [2, match *sub {
+                    FloatSubType::Default => 0,
+                    FloatSubType::Precision => 1,
+                }, match *size {
+                    FloatSize::F16 => 16,
+                    FloatSize::F32 => 32,
+                    FloatSize::F64 => 64,
+                }, 0],
+                Type::Special => [3, 0, 0, 0],
+            };
+            let offset = format.offset as u32;
+            let mut bytes: Vec<u8> = name.bytes().collect();
+            bytes.push(0);
+            bytes.extend(code.iter().cloned());
+            bytes.extend([format.elem_count as u8, offset as u8, (offset >> 8) as u8,
+                          (offset >> 16) as u8, (offset >> 24) as u8, format.stride as u8,
+                          format.instance_rate as u8].iter().cloned());
+            for &byte in bytes.iter() {
+                hash = (hash ^ byte as u64).wrapping_mul(0x100000001b3);
+            }
+        }
+        hash
+    });
+    let decl = cx.fn_decl(Vec::new(), quote_ty!(cx, u64));
+    let mut method = super::impl_method(cx, span, "format_hash", false,
+                                        super::no_generics(), decl, cx.block_expr(body));
+    method.vis = ast::Public;
+    P(method)
+}
+
+/// The name of the `format_hash()` constant of a format, `MY_VERTEX_FORMAT_HASH`
+/// for `MyVertex`
+fn hash_const_name(ident: ast::Ident) -> String {
+    let mut name = String::new();
+    for (i, c) in ident.as_str().chars().enumerate() {
+        if c.is_uppercase() && i != 0 {
+            name.push('_');
+        }
+        name.extend(c.to_uppercase());
+    }
+    name.push_str("_FORMAT_HASH");
+    name
+}
+
+/// Generates the `#[test]` function of a `#[gen_tests]` format, asserting
+/// that the size, alignment and field offsets the compiler picks match the
+/// ones computed at expansion time, and that `attribute_formats()` describes
+/// every attribute within the vertex.
+fn test_item(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+             item: &ast::Item, definition: &ast::StructDef, generics: &ast::Generics,
+             struct_ty: &P<ast::Ty>, signature: &[glsl::Var]) -> Option<P<ast::Item>> {
+    if generics.is_parameterized() {
+        cx.span_err(span, "`#[gen_tests]` needs a struct without generics, \
+                           to know the layout to test");
+        return None
+    }
+    let mut stmts = Vec::new();
+
+    // the layout, unless a field is of a type unknown to the expansion
+    let types: Vec<&ast::Ty> = definition.fields.iter().map(|f| &*f.node.ty).collect();
+    let packed = super::has_repr(&item.attrs, "packed");
+    if let Some((offsets, layout)) = layout::struct_layout(&types, packed) {
+        let size = cx.expr_usize(span, layout.size);
+        let align = cx.expr_usize(span, layout.align);
+        stmts.push(quote_stmt!(cx,
+            assert_eq!(::std::mem::size_of::<$struct_ty>(), $size);
+        ).unwrap());
+        stmts.push(quote_stmt!(cx,
+            assert_eq!(::std::mem::min_align_of::<$struct_ty>(), $align);
+        ).unwrap());
+        for (i, (def, &offset)) in definition.fields.iter().zip(offsets.iter()).enumerate() {
+            let actual = field_offset(cx, def.span, struct_ty, super::FieldAccess::of(def, i));
+            let offset = cx.expr_usize(def.span, offset);
+            stmts.push(quote_stmt!(cx, assert_eq!($actual, $offset);).unwrap());
+        }
+    }
+
+    // the names of the reflection table, in any `#[location]` order
+    let mut names: Vec<String> = signature.iter().map(|var| var.name.clone()).collect();
+    names.sort();
+    let names = names.into_iter().map(|name|
+        cx.expr_str(span, token::intern_and_get_ident(&name))
+    ).collect();
+    let names = cx.expr_vec(span, names);
+    stmts.push(quote_stmt!(cx, {
+        let expected: Vec<&str> = $names.to_vec();
+        let mut names: Vec<String> = <$struct_ty>::attribute_formats().iter()
+            .map(|&(ref name, _)| name.to_string()).collect();
+        names.sort();
+        assert_eq!(names.iter().map(|name| &name[..]).collect::<Vec<&str>>(), expected);
+    };).unwrap());
+
+    // interleaved attributes step over whole vertices
+    if !definition.fields.iter().any(|def| attr::contains_name(&def.node.attrs, "buffer")) {
+        stmts.push(quote_stmt!(cx, {
+            let size = ::std::mem::size_of::<$struct_ty>();
+            for &(ref name, ref format) in <$struct_ty>::attribute_formats().iter() {
+                assert!((format.offset as usize) < size, "`{}` starts past the vertex", name);
+                assert_eq!(format.stride as usize, size);
+            }
+        };).unwrap());
+    }
+
+    let name = cx.ident_of(&format!("gen_tests_{}", item.ident.as_str()));
+    let body = cx.block(span, stmts, None);
+    Some(cx.item_fn(span, name, Vec::new(), quote_ty!(cx, ()), body).map(|mut test| {
+        test.attrs.push(cx.attribute(span, cx.meta_word(span, token::InternedString::new("test"))));
+        test.attrs.push(cx.attribute(span, cx.meta_list(span, token::InternedString::new("cfg"),
+            vec![cx.meta_word(span, token::InternedString::new("test"))])));
+        test.attrs.push(cx.attribute(span, cx.meta_list(span, token::InternedString::new("allow"),
+            vec![cx.meta_word(span, token::InternedString::new("non_snake_case"))])));
+        test
+    }))
+}
+
+/// Lay out the fields of a format spread over several buffers with
+/// `#[buffer = "N"]`, each buffer holding its fields tightly packed in
+/// declaration order. Returns the buffer and offset of every field along with
+/// the stride of every buffer, or `None` for interleaved formats.
+fn buffer_layout(cx: &mut ext::base::ExtCtxt, definition: &ast::StructDef)
+                 -> Option<(Vec<Option<(usize, usize)>>, Vec<usize>)> {
+    if !definition.fields.iter().any(|def| attr::contains_name(&def.node.attrs, "buffer")) {
+        return None
+    }
+    let mut fields = Vec::with_capacity(definition.fields.len());
+    let mut strides = Vec::new();
+    for def in definition.fields.iter() {
+        if super::is_skipped(def) {
+            fields.push(None);
+            continue
+        }
+        let slot = super::find_int(cx, def.span, &def.node.attrs, "buffer").unwrap_or(0) as usize;
+        let size = match layout::rust_layout(&def.node.ty) {
+            Some(layout) => layout.size,
+            None => {
+                cx.span_err(def.span, "Fields of multi-buffer vertex formats need to be \
+                                       components, vectors or matrices");
+                fields.push(None);
+                continue
+            },
+        };
+        while strides.len() <= slot {
+            strides.push(0);
+        }
+        fields.push(Some((slot, strides[slot])));
+        strides[slot] += size;
+    }
+    Some((fields, strides))
+}
+
+/// The field offsets and the size of a `#[repr(C)]` or `#[repr(packed)]`
+/// format, when every field is a component type whose layout is known at
+/// expansion time. Nested formats and generic fields are only laid out by
+/// the compiler.
+fn known_layout(item: &ast::Item, definition: &ast::StructDef) -> Option<(Vec<usize>, usize)> {
+    let packed = super::has_repr(&item.attrs, "packed");
+    if !packed && !super::has_repr(&item.attrs, "C") {
+        return None
+    }
+    let types: Vec<&ast::Ty> = definition.fields.iter().map(|f| &*f.node.ty).collect();
+    layout::struct_layout(&types, packed).map(|(offsets, layout)| (offsets, layout.size))
+}
+
+/// An unsuffixed integer literal, which takes the type of a `static` field
+fn int_lit(cx: &mut ext::base::ExtCtxt, span: codemap::Span, value: usize) -> P<ast::Expr> {
+    cx.expr_lit(span, ast::LitInt(value as u64, ast::UnsuffixedIntLit(ast::Plus)))
+}
+
+/// Generates the the method body for the inherent `attribute_formats`, which
+/// describes every attribute by its name and `gfx::attrib::Format`. Formats
+/// spread over several buffers also get the buffer of every attribute. The
+/// GLSL declarations of the attributes are collected into `signature`, after
+/// checking that no two attributes share a name.
+///
+/// With a `known` layout, the formats are a `static` table with literal
+/// offsets and strides, returned as a `&'static [(&'static str, Format)]`
+/// slice without allocating, and their `format_hash()` is returned as well.
+/// Otherwise they are built into a `Vec<(String, Format)>` on every call, as
+/// the offsets of nested and generic fields come from `size_of` calls, which
+/// are not constant. Both are iterated the same way by the generated code.
+/// Returns the body along with its return type.
 fn method_body(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
-                   substr: &generic::Substructure,
-                   path_root: ast::Ident) -> P<ast::Expr> {
-    match *substr.fields {
-        generic::StaticStruct(ref definition, generic::Named(ref fields)) => {
-            let attribute_pushes = definition.fields.iter().zip(fields.iter())
-                .map(|(def, &(ident, _))| {
-                    let struct_ident = substr.type_ident;
-                    let buffer_expr = &substr.nonself_args[0];
-                    let (count_expr, type_expr) = decode_count_and_type(cx, span, def, path_root);
-                    let ident_str = match super::find_name(cx, span, &def.node.attrs) {
-                        Some(name) => name,
-                        None => token::get_ident(ident),
+               definition: &ast::StructDef, naming: &super::Naming, struct_ty: &P<ast::Ty>,
+               known: Option<&(Vec<usize>, usize)>, path_root: ast::Ident,
+               signature: &mut Vec<glsl::Var>)
+               -> (P<ast::Expr>, P<ast::Ty>, Option<Vec<usize>>, Option<u64>) {
+    let buffers = buffer_layout(cx, definition);
+    // attribute pushes of every field along with their buffer,
+    // with its `#[location]` if pinned
+    let mut field_pushes = Vec::with_capacity(definition.fields.len());
+    let mut has_nested = false;
+    // attribute names along with their field, to report collisions
+    let mut names = Vec::new();
+    for (index, def) in definition.fields.iter().enumerate() {
+        if super::is_skipped(def) {
+            continue
+        }
+        if reject_packed(cx, &def.node.attrs) {
+            continue
+        }
+        // report the problems of a field at the field
+        let span = def.span;
+        let access = super::FieldAccess::of(def, index);
+        let skin = Skin::of(def);
+        let modifier = find_modifier(cx, span, &def.node.attrs)
+            .or(skin.and_then(|skin| skin.modifier()));
+        let ident_str = match (super::find_name(cx, span, &def.node.attrs), access) {
+            (Some(name), _) => name,
+            (None, _) if skin.is_some() => token::InternedString::new(skin.unwrap().name()),
+            (None, super::FieldAccess::Named(ident)) => naming.name(ident),
+            (None, super::FieldAccess::Indexed(_)) => {
+                cx.span_err(def.span, "Tuple struct fields need a `#[name = \"...\"]` \
+                                       to be used as vertex attributes");
+                continue
+            },
+        };
+        let instance_rate = match super::find_int(cx, span, &def.node.attrs, "instance_rate") {
+            Some(rate) if rate > 255 => {
+                cx.span_err(span, &format!(
+                    "Instance rate {} does not fit `gfx::attrib::InstanceRate`", rate
+                ));
+                0
+            },
+            Some(rate) => rate as u8,
+            None => 0,
+        };
+        let location = super::find_int(cx, span, &def.node.attrs, "location");
+        // along with the literal offset and stride for the static table
+        let (slot, field_offset, stride, literal) = match buffers {
+            Some((ref fields, ref strides)) => match fields[index] {
+                Some((slot, offset)) =>
+                    (slot, cx.expr_usize(span, offset), cx.expr_usize(span, strides[slot]),
+                     Some((offset, strides[slot]))),
+                None => continue,
+            },
+            None => (0, field_offset(cx, span, struct_ty, access),
+                     quote_expr!(cx, ::std::mem::size_of::<$struct_ty>()),
+                     known.map(|&(ref offsets, size)| (offsets[index], size))),
+        };
+        if let Some(nested) = nested_format(cx, &def.node.ty) {
+            if modifier.is_some() {
+                cx.span_warn(span, "Modifier attributes are ignored on nested vertex formats");
+            }
+            if location.is_some() {
+                cx.span_err(span, "`#[location]` is not supported on nested vertex formats");
+            }
+            has_nested = true;
+            let nested = nested.unwrap_or_else(|candidates| {
+                let path = match def.node.ty.node {
+                    ast::TyPath(_, ref path) => path,
+                    _ => unreachable!(),
+                };
+                cx.span_err(def.span, &super::entry_error(path, "`#[vertex_format]` struct",
+                                                          "this format", &candidates));
+                Vec::new()
+            });
+            names.extend(nested.iter().map(|var| (var.name.clone(), def.span)));
+            signature.extend(nested.into_iter());
+            let field_ty = &def.node.ty;
+            let rate_fix = match super::find_int(cx, span, &def.node.attrs, "instance_rate") {
+                Some(_) => {
+                    let rate = cx.expr_u8(span, instance_rate);
+                    quote_expr!(cx, format.instance_rate = $rate)
+                },
+                None => quote_expr!(cx, ()),
+            };
+            field_pushes.push((None, vec![(quote_expr!(cx, {
+                for &(ref name, ref format) in <$field_ty>::attribute_formats().iter() {
+                    let mut format = format.clone();
+                    format.offset += $field_offset as $path_root::gfx::attrib::Offset;
+                    format.stride = { use std::mem;
+                        mem::size_of::<$struct_ty>() as $path_root::gfx::attrib::Stride
                     };
-                    let ident_str = &ident_str[..];
-                    let instance_expr = cx.expr_u8(span, 0); // not supposed to be set by the macro
-                    quote_expr!(cx, {
-                        attributes.push($path_root::gfx::Attribute {
-                            name: $ident_str.to_string(),
-                            buffer: $buffer_expr.clone(),
-                            format: $path_root::gfx::attrib::Format {
-                                elem_count: $count_expr,
-                                elem_type: $type_expr,
-                                offset: unsafe {
-                                    let x: $struct_ident = ::std::mem::uninitialized();
-                                    let offset = (&x.$ident as *const _ as usize) -
-                                        (&x as *const _ as usize);
-                                    ::std::mem::forget(x);
-                                    offset as $path_root::gfx::attrib::Offset
-                                },
-                                stride: { use std::mem;
-                                    mem::size_of::<$struct_ident>() as
-                                        $path_root::gfx::attrib::Stride
-                                },
-                                instance_rate: $instance_expr,
-                            }
-                        });
-                    })
-                }).collect::<Vec<P<ast::Expr>>>();
-            let capacity = fields.len();
-            quote_expr!(cx, {
-                let mut attributes = Vec::with_capacity($capacity);
-                $attribute_pushes;
-                attributes
-            })
+                    $rate_fix;
+                    formats.push((name.to_string(), format));
+                }
+            }), 0, None)]));
+            continue
+        }
+        // a matrix is split into one attribute per column,
+        // named `<name>_0` to `<name>_<M-1>`
+        let columns = match decode_matrix(cx, span, &def.node.ty) {
+            Some((count, column_ty)) => (0..count).map(|i| {
+                (format!("{}_{}", ident_str, i), column_ty, Some((i, column_ty)))
+            }).collect(),
+            None => vec![(ident_str.to_string(), &*def.node.ty, None)],
+        };
+        let mut pushes = Vec::with_capacity(columns.len());
+        for (name, ty, column) in columns {
+            names.push((name.clone(), def.span));
+            signature.push(glsl::Var {
+                kind: glsl::Kind::Attribute,
+                name: name.clone(),
+                ty: glsl_type(ty, modifier),
+                count: None,
+            });
+            let name = &name[..];
+            let (count_expr, type_expr) = decode_count_and_type(cx, span, ty, modifier, path_root);
+            let offset_expr = match column {
+                Some((i, column_ty)) => {
+                    let i = cx.expr_usize(span, i);
+                    quote_expr!(cx, $field_offset + $i * ::std::mem::size_of::<$column_ty>())
+                },
+                None => field_offset.clone(),
+            };
+            let instance_expr = cx.expr_u8(span, instance_rate);
+            let literal_offset = match (literal, column) {
+                (Some((offset, stride)), Some((i, column_ty))) =>
+                    layout::rust_layout(column_ty).map(|l| (offset + i * l.size, stride)),
+                (literal, _) => literal,
+            };
+            let entry = literal_offset.map(|(offset, stride)| {
+                let count = match ty.node {
+                    ast::TyFixedLengthVec(_, ref count) => layout::array_len(count),
+                    _ => Some(1),
+                };
+                let bytes = match (type_code(ty, modifier), count) {
+                    (Some(code), Some(count)) => Some(hash_bytes(name, code, count as u8,
+                                                                 offset as u32, stride as u8,
+                                                                 instance_rate)),
+                    _ => None,
+                };
+                let offset = int_lit(cx, span, offset);
+                let stride = int_lit(cx, span, stride);
+                let instance = int_lit(cx, span, instance_rate as usize);
+                (quote_expr!(cx, ($name, $path_root::gfx::attrib::Format {
+                    elem_count: $count_expr,
+                    elem_type: $type_expr,
+                    offset: $offset,
+                    stride: $stride,
+                    instance_rate: $instance,
+                })), bytes)
+            });
+            pushes.push((quote_expr!(cx, {
+                formats.push(($name.to_string(), $path_root::gfx::attrib::Format {
+                    elem_count: $count_expr,
+                    elem_type: $type_expr,
+                    offset: $offset_expr as $path_root::gfx::attrib::Offset,
+                    stride: $stride as $path_root::gfx::attrib::Stride,
+                    instance_rate: $instance_expr,
+                }));
+            }), slot, entry));
+        }
+        field_pushes.push((location.map(|loc| (loc as usize, def.span)), pushes));
+    }
+    glsl::check_names(cx, &names);
+    let ordered = order_by_location(cx, span, field_pushes, has_nested);
+    let slots: Vec<usize> = ordered.iter().map(|&(_, slot, _)| slot).collect();
+    let (body, ty, hash) = if !has_nested && ordered.iter().all(|&(_, _, ref entry)| entry.is_some()) {
+        let count = cx.expr_usize(span, ordered.len());
+        let (entries, bytes): (Vec<_>, Vec<_>) = ordered.into_iter()
+            .filter_map(|(_, _, entry)| entry).unzip();
+        let hash = if bytes.iter().all(|bytes| bytes.is_some()) {
+            Some(fnv1a(&bytes.into_iter().flat_map(|bytes| bytes.unwrap().into_iter())
+                             .collect::<Vec<u8>>()))
+        } else {
+            None
+        };
+        let entries = cx.expr_vec(span, entries);
+        (quote_expr!(cx, {
+            static FORMATS: [(&'static str, $path_root::gfx::attrib::Format); $count] = $entries;
+            &FORMATS
+        }), quote_ty!(cx, &'static [(&'static str, $path_root::gfx::attrib::Format)]), hash)
+    } else {
+        let attribute_pushes: Vec<P<ast::Expr>> =
+            ordered.into_iter().map(|(push, _, _)| push).collect();
+        let capacity = attribute_pushes.len();
+        (quote_expr!(cx, {
+            let mut formats = Vec::with_capacity($capacity);
+            $attribute_pushes;
+            formats
+        }), quote_ty!(cx, Vec<(String, $path_root::gfx::attrib::Format)>), None)
+    };
+    (body, ty, buffers.map(|_| slots), hash)
+}
+
+/// Check that the struct has a `#[repr(C)]` or `#[repr(packed)]` layout,
+/// which the attribute offsets and strides are meant to describe.
+fn check_repr(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+              attributes: &[ast::Attribute]) {
+    let mut found = false;
+    for attribute in attributes.iter() {
+        match attribute.node.value.node {
+            ast::MetaList(ref name, ref hints) if &name[..] == "repr" => {
+                found = true;
+                for hint in hints.iter() {
+                    match hint.node {
+                        ast::MetaWord(ref word) if &word[..] == "C" || &word[..] == "packed" => (),
+                        _ => cx.span_err(hint.span, "Vertex formats require `#[repr(C)]` or \
+                                                     `#[repr(packed)]`, other representations \
+                                                     do not match the attribute layout"),
+                    }
+                }
+            },
+            _ => (),
+        }
+    }
+    if !found {
+        cx.span_err(span, "Vertex formats require `#[repr(C)]` or `#[repr(packed)]` on the struct");
+    }
+}
+
+/// Check that a `#[repr(C)]` struct of plain fields has no implicit padding,
+/// which would be interleaved with the vertex data. Padding can be declared
+/// explicitly with `#[skip]` fields.
+fn check_padding(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+                 item: &ast::Item, definition: &ast::StructDef) {
+    if super::has_repr(&item.attrs, "packed") {
+        return
+    }
+    let types: Vec<&ast::Ty> = definition.fields.iter().map(|f| &*f.node.ty).collect();
+    let (offsets, struct_layout) = match layout::struct_layout(&types, false) {
+        Some(result) => result,
+        None => return,
+    };
+    let mut end = 0;
+    for (field, &offset) in definition.fields.iter().zip(offsets.iter()) {
+        if offset != end {
+            cx.span_err(field.span, &format!(
+                "Field is aligned to offset {} leaving {} bytes of implicit padding, \
+                declare the padding with a `#[skip]` field", offset, offset - end
+            ));
+        }
+        end = offset + layout::rust_layout(&field.node.ty).map_or(0, |l| l.size);
+    }
+    if struct_layout.size != end {
+        cx.span_err(span, &format!(
+            "Struct size {} leaves {} bytes of implicit trailing padding, \
+            declare the padding with a `#[skip]` field", struct_layout.size, struct_layout.size - end
+        ));
+    }
+}
+
+/// The channel name of a field in the `#[soa]` companion, `positions` for a
+/// `position` field. Names already ending in `s` are kept as they are.
+fn channel_name(ident: ast::Ident) -> String {
+    let name = ident.as_str();
+    if name.ends_with("s") {
+        name.to_string()
+    } else {
+        format!("{}s", name)
+    }
+}
+
+/// Generates the structure-of-arrays companion `NameSoA` of a `#[soa]`
+/// format, holding a `Vec` per field, with `new()`, `push()`, `len()` and
+/// `interleave()` copying the channels into a slice of vertices.
+fn soa_items(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+             item: &ast::Item, definition: &ast::StructDef,
+             generics: &ast::Generics, struct_ty: &P<ast::Ty>) -> Vec<P<ast::Item>> {
+    if definition.fields.is_empty() {
+        cx.span_err(span, "#[soa] needs at least one field");
+        return Vec::new()
+    }
+    if definition.fields.iter().any(|def| def.node.ident().is_none()) {
+        cx.span_err(span, "#[soa] is not supported on tuple structs");
+        return Vec::new()
+    }
+    let soa_ident = cx.ident_of(&format!("{}SoA", item.ident.as_str()));
+    let soa_ty = super::struct_type(cx, span, soa_ident, generics);
+    let channels: Vec<(ast::Ident, ast::Ident)> = definition.fields.iter().map(|def| {
+        let ident = def.node.ident().unwrap();
+        (ident, cx.ident_of(&channel_name(ident)))
+    }).collect();
+    let soa_def = ast::StructDef {
+        fields: definition.fields.iter().zip(channels.iter()).map(|(def, &(_, channel))| {
+            let ty = &def.node.ty;
+            codemap::Spanned {
+                node: ast::StructField_ {
+                    kind: ast::NamedField(channel, ast::Public),
+                    id: ast::DUMMY_NODE_ID,
+                    ty: quote_ty!(cx, Vec<$ty>),
+                    attrs: Vec::new(),
+                },
+                span: def.span,
+            }
+        }).collect(),
+        ctor_id: None,
+    };
+    let soa_item = cx.item_struct_poly(span, soa_ident, soa_def, generics.clone())
+                     .map(|mut sitem| {
+        sitem.vis = item.vis;
+        sitem
+    });
+
+    // `pub fn new() -> NameSoA`
+    let empty = channels.iter().map(|&(_, channel)|
+        cx.field_imm(span, channel, quote_expr!(cx, Vec::new()))
+    ).collect();
+    let body_new = cx.block_expr(cx.expr_struct_ident(span, soa_ident, empty));
+    let decl_new = cx.fn_decl(Vec::new(), soa_ty.clone());
+    let method_new = super::impl_method(cx, span, "new", false, super::no_generics(),
+                                        decl_new, body_new);
+
+    // `pub fn push(&mut self, vertex: Name)`
+    let vertex = cx.ident_of("vertex");
+    let pushes = channels.iter().map(|&(field, channel)| {
+        let this = cx.expr_field_access(span, cx.expr_self(span), channel);
+        let value = cx.expr_field_access(span, cx.expr_ident(span, vertex), field);
+        quote_stmt!(cx, $this.push($value);).unwrap()
+    }).collect();
+    let decl_push = cx.fn_decl(vec![
+        ast::Arg::new_self(span, ast::MutImmutable, cx.ident_of("self")),
+        cx.arg(span, vertex, struct_ty.clone()),
+    ], quote_ty!(cx, ()));
+    let method_push = super::mut_self(super::impl_method(cx, span, "push", true,
+                                                         super::no_generics(), decl_push,
+                                                         cx.block(span, pushes, None)));
+
+    // `pub fn len(&self) -> usize`, the length of the first channel
+    let first = cx.expr_field_access(span, cx.expr_self(span), channels[0].1);
+    let decl_len = cx.fn_decl(vec![
+        ast::Arg::new_self(span, ast::MutImmutable, cx.ident_of("self")),
+    ], quote_ty!(cx, usize));
+    let method_len = super::impl_method(cx, span, "len", true, super::no_generics(),
+                                        decl_len, cx.block_expr(quote_expr!(cx, $first.len())));
+
+    // `pub fn interleave(&self, out: &mut [Name])`, panicking unless every
+    // channel has one value per vertex
+    let out = cx.ident_of("out");
+    let mut copies: Vec<P<ast::Stmt>> = channels.iter().map(|&(_, channel)| {
+        let this = cx.expr_field_access(span, cx.expr_self(span), channel);
+        let message = cx.expr_str(span, token::intern_and_get_ident(&format!(
+            "`{}` channel length does not match the output", channel.as_str())));
+        quote_stmt!(cx, assert!($this.len() == $out.len(), $message);).unwrap()
+    }).collect();
+    let assignments: Vec<P<ast::Stmt>> = channels.iter().map(|&(field, channel)| {
+        let this = cx.expr_field_access(span, cx.expr_self(span), channel);
+        let target = cx.expr_field_access(span, cx.expr_ident(span, vertex), field);
+        quote_stmt!(cx, { $target = $this[i].clone(); }).unwrap()
+    }).collect();
+    copies.push(quote_stmt!(cx,
+        for (i, $vertex) in $out.iter_mut().enumerate() {
+            $assignments
+        }
+    ).unwrap());
+    let decl_interleave = cx.fn_decl(vec![
+        ast::Arg::new_self(span, ast::MutImmutable, cx.ident_of("self")),
+        cx.arg(span, out, quote_ty!(cx, &mut [$struct_ty])),
+    ], quote_ty!(cx, ()));
+    let method_interleave = super::impl_method(cx, span, "interleave", true,
+                                               super::no_generics(), decl_interleave,
+                                               cx.block(span, copies, None));
+
+    let methods = vec![method_new, method_push, method_len, method_interleave];
+    let soa_impl = cx.item(span, soa_ident, vec![super::allow_dead_code(cx, span)], ast::ItemImpl(
+        ast::Unsafety::Normal,
+        ast::ImplPolarity::Positive,
+        generics.clone(),
+        None,
+        soa_ty,
+        methods.into_iter().map(|mut method| {
+            method.vis = ast::Public;
+            P(method)
+        }).collect()
+    ));
+    vec![soa_item, soa_impl]
+}
+
+/// How a vertex format field takes part in `convert_vertex!`.
+#[derive(Clone)]
+enum Channel {
+    /// An attribute by name, with its type and modifier
+    Attribute(String, P<ast::Ty>, Option<Modifier>),
+    /// A nested vertex format
+    Nested,
+    /// A `#[skip]` or `PhantomData` field, with its type
+    Skipped(P<ast::Ty>),
+}
+
+#[derive(Clone)]
+struct Channels {
+    fields: Vec<(super::FieldAccess, Channel)>,
+    is_tuple: bool,
+}
+
+/// Access the fields of the vertex formats expanded so far, by `item_key`,
+/// so that `convert_vertex!` can match them by attribute name
+fn with_channels<T, F>(f: F) -> T where F: FnOnce(&RefCell<HashMap<String, Channels>>) -> T {
+    thread_local!(
+        static CHANNELS: RefCell<HashMap<String, Channels>> =
+            RefCell::new(HashMap::new())
+    );
+    CHANNELS.with(f)
+}
+
+/// Describe the fields of a format for `convert_vertex!`. Diagnostics of
+/// the attributes are left to the format expansion itself.
+fn channels(cx: &ext::base::ExtCtxt, definition: &ast::StructDef,
+            naming: &super::Naming) -> Channels {
+    Channels {
+        fields: definition.fields.iter().enumerate().map(|(index, def)| {
+            let access = super::FieldAccess::of(def, index);
+            let channel = if super::is_skipped(def) {
+                Channel::Skipped(def.node.ty.clone())
+            } else if nested_format(cx, &def.node.ty).is_some() {
+                Channel::Nested
+            } else {
+                let skin = Skin::of(def);
+                let name = attr::first_attr_value_str_by_name(&def.node.attrs, "name")
+                    .map(|name| name.to_string())
+                    .unwrap_or_else(|| match (skin, access) {
+                        (Some(skin), _) => skin.name().to_string(),
+                        (None, super::FieldAccess::Named(ident)) => naming.name(ident).to_string(),
+                        (None, super::FieldAccess::Indexed(_)) => access.as_string(),
+                    });
+                let modifier = def.node.attrs.iter().filter_map(|attribute| {
+                    match attribute.node.value.node {
+                        ast::MetaWord(ref word) => word.parse().ok(),
+                        _ => None,
+                    }
+                }).next().or(skin.and_then(|skin| skin.modifier()));
+                Channel::Attribute(name, def.node.ty.clone(), modifier)
+            };
+            (access, channel)
+        }).collect(),
+        is_tuple: definition.fields.iter().any(|def| def.node.ident().is_none()),
+    }
+}
+
+/// The component type name and the component count of an attribute type,
+/// `None` for scalars
+fn components(ty: &ast::Ty) -> Option<(String, Option<usize>)> {
+    match ty.node {
+        ast::TyPath(_, ref p) if is_component(ty) =>
+            Some((p.segments[0].identifier.as_str().to_string(), None)),
+        ast::TyFixedLengthVec(ref pty, ref expr) => match (&pty.node, layout::array_len(expr)) {
+            (&ast::TyPath(_, ref p), Some(count)) if is_component(pty) =>
+                Some((p.segments[0].identifier.as_str().to_string(), Some(count))),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Convert one component between types. Normalized integers are scaled
+/// from and to the `[0, 1]` or `[-1, 1]` range, anything else is cast.
+fn convert_component(cx: &mut ext::base::ExtCtxt, value: P<ast::Expr>,
+                     src: &str, src_modifier: Option<Modifier>,
+                     dst: &str, dst_modifier: Option<Modifier>) -> P<ast::Expr> {
+    let is_int = |ty: &str| ty.starts_with("i") || ty.starts_with("u");
+    let src_normalized = is_int(src) && src_modifier == Some(Modifier::Normalized);
+    let dst_normalized = is_int(dst) && dst_modifier == Some(Modifier::Normalized);
+    let dst_ty = cx.ident_of(dst);
+    if !src_normalized && !dst_normalized {
+        return quote_expr!(cx, $value as $dst_ty)
+    }
+    let src_ty = cx.ident_of(src);
+    let float = if src_normalized {
+        let min = if src.starts_with("i") { quote_expr!(cx, -1.0) } else { quote_expr!(cx, 0.0) };
+        quote_expr!(cx, ($value as f64 / ::std::$src_ty::MAX as f64).max($min))
+    } else {
+        quote_expr!(cx, ($value as f64))
+    };
+    if dst_normalized {
+        let min = if dst.starts_with("i") { quote_expr!(cx, -1.0) } else { quote_expr!(cx, 0.0) };
+        quote_expr!(cx, ($float.max($min).min(1.0) * ::std::$dst_ty::MAX as f64).round() as $dst_ty)
+    } else {
+        quote_expr!(cx, $float as $dst_ty)
+    }
+}
+
+/// Convert the value of a source attribute to the destination type, or
+/// explain why it can't be
+fn convert_attribute(cx: &mut ext::base::ExtCtxt, value: P<ast::Expr>,
+                     src: (&ast::Ty, Option<Modifier>),
+                     dst: (&ast::Ty, Option<Modifier>)) -> Result<P<ast::Expr>, String> {
+    let (src_ty, src_modifier) = src;
+    let (dst_ty, dst_modifier) = dst;
+    if pprust::ty_to_string(src_ty) == pprust::ty_to_string(dst_ty) &&
+       src_modifier == dst_modifier {
+        return Ok(value)
+    }
+    let (src_component, src_count) = try!(components(src_ty).ok_or(format!(
+        "`{}` can only be converted to the same type", pprust::ty_to_string(src_ty))));
+    let (dst_component, dst_count) = try!(components(dst_ty).ok_or(format!(
+        "`{}` can only be converted from the same type", pprust::ty_to_string(dst_ty))));
+    if src_count != dst_count {
+        return Err(format!("can not convert {} components to {}",
+                           src_count.unwrap_or(1), dst_count.unwrap_or(1)))
+    }
+    if src_modifier == Some(Modifier::AsHalf) || dst_modifier == Some(Modifier::AsHalf) {
+        return Err("half-precision components can not be converted".to_string())
+    }
+    Ok(match src_count {
+        Some(count) => {
+            let elements = (0..count).map(|i| {
+                let index = cx.expr_usize(value.span, i);
+                let element = cx.expr(value.span, ast::ExprIndex(value.clone(), index));
+                convert_component(cx, element, &src_component, src_modifier,
+                                  &dst_component, dst_modifier)
+            }).collect();
+            cx.expr_vec(value.span, elements)
+        },
+        None => convert_component(cx, value, &src_component, src_modifier,
+                                  &dst_component, dst_modifier),
+    })
+}
+
+/// The fields of a previously expanded `#[vertex_format]` struct, reporting
+/// unknown types
+fn find_channels(cx: &mut ext::base::ExtCtxt, ty: &ast::Ty, user: &str) -> Option<Channels> {
+    match ty.node {
+        ast::TyPath(None, ref path) => {
+            match with_channels(|channels| super::find_entry(cx, &channels.borrow(), path)) {
+                Ok(channels) => Some(channels),
+                Err(candidates) => {
+                    cx.span_err(ty.span, &super::entry_error(path, "`#[vertex_format]` struct",
+                                                             &format!("`{}`", user), &candidates));
+                    None
+                },
+            }
         },
         _ => {
-            cx.span_err(span, "Unable to implement `gfx::VertexFormat::generate` \
-                              on a non-structure");
-            cx.expr_tuple(span, vec![])
+            cx.span_err(ty.span, &format!("Expected a `#[vertex_format]` struct declared \
+                                           before `{}`", user));
+            None
+        },
+    }
+}
+
+/// Build a value of the format from the values of all its fields
+fn construct(cx: &mut ext::base::ExtCtxt, span: codemap::Span, ty: &ast::Ty,
+             channels: &Channels, values: Vec<P<ast::Expr>>) -> P<ast::Expr> {
+    let path = match ty.node {
+        ast::TyPath(_, ref path) => path.clone(),
+        _ => unreachable!(),
+    };
+    if channels.is_tuple {
+        cx.expr_call(span, cx.expr_path(path), values)
+    } else {
+        let fields = channels.fields.iter().zip(values.into_iter()).map(|(&(access, _), value)|
+            match access {
+                super::FieldAccess::Named(ident) => cx.field_imm(span, ident, value),
+                super::FieldAccess::Indexed(_) => unreachable!(),
+            }
+        ).collect();
+        cx.expr_struct(span, path, fields)
+    }
+}
+
+/// Expand `convert_vertex!(Src => Dst)` into a `From<Src>` implementation for
+/// `Dst`, both previously declared `#[vertex_format]` structs. Attributes are
+/// matched by name, skipped destination fields get their default value and
+/// source attributes missing from the destination are dropped.
+pub fn expand_convert(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+                      tts: &[ast::TokenTree]) -> Box<MacResult + 'static> {
+    let mut parser = cx.new_parser_from_tts(tts);
+    let src_ty = parser.parse_ty();
+    panictry!(parser.expect(&token::FatArrow));
+    let dst_ty = parser.parse_ty();
+    panictry!(parser.expect(&token::Eof));
+
+    let (src, dst) = match (find_channels(cx, &src_ty, "convert_vertex!"),
+                            find_channels(cx, &dst_ty, "convert_vertex!")) {
+        (Some(src), Some(dst)) => (src, dst),
+        _ => return DummyResult::any(span),
+    };
+
+    let source = cx.ident_of("source");
+    let values: Vec<P<ast::Expr>> = dst.fields.iter().map(|&(dst_access, ref dst_channel)| {
+        let (name, ty, modifier) = match *dst_channel {
+            Channel::Attribute(ref name, ref ty, modifier) => (name, ty, modifier),
+            Channel::Skipped(_) => return quote_expr!(cx, ::std::default::Default::default()),
+            Channel::Nested => {
+                cx.span_err(span, &format!("Nested format `{}` is not supported by \
+                                            `convert_vertex!`", dst_access.as_string()));
+                return cx.expr_tuple(span, Vec::new())
+            },
+        };
+        let found = src.fields.iter().filter_map(|&(src_access, ref src_channel)| {
+            match *src_channel {
+                Channel::Attribute(ref src_name, ref src_ty, src_modifier) if src_name == name =>
+                    Some((src_access, src_ty.clone(), src_modifier)),
+                _ => None,
+            }
+        }).next();
+        let (src_access, src_ty, src_modifier) = match found {
+            Some(found) => found,
+            None => {
+                cx.span_err(span, &format!("Attribute `{}` is missing from the source format",
+                                           name));
+                return cx.expr_tuple(span, Vec::new())
+            },
+        };
+        let value = src_access.expr(cx, span, cx.expr_ident(span, source));
+        match convert_attribute(cx, value, (&*src_ty, src_modifier), (&**ty, modifier)) {
+            Ok(value) => value,
+            Err(reason) => {
+                cx.span_err(span, &format!("Unable to convert attribute `{}`: {}", name, reason));
+                cx.expr_tuple(span, Vec::new())
+            },
+        }
+    }).collect();
+
+    let body = construct(cx, span, &dst_ty, &dst, values);
+    let item = quote_item!(cx,
+        impl ::std::convert::From<$src_ty> for $dst_ty {
+            fn from($source: $src_ty) -> $dst_ty {
+                $body
+            }
         }
+    ).unwrap();
+    MacEager::items(SmallVector::one(item))
+}
+
+/// The OBJ data an attribute is filled with by `mesh_data!`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum MeshData {
+    Position,
+    Normal,
+    TexCoord,
+}
+
+/// How `mesh_data!` fills a field of the vertices.
+enum MeshField {
+    /// With OBJ data, converted to the component type and count
+    Data(MeshData, String, usize),
+    /// With a constant value
+    Constant(P<ast::Expr>),
+}
+
+/// Recognize the conventional attribute names (`pos`, `a_Position`,
+/// `normal`, `a_TexCoord`, `uv`, etc) of the OBJ data
+fn mesh_data_of(name: &str) -> Option<MeshData> {
+    use std::ascii::AsciiExt;
+    let name = name.to_ascii_lowercase();
+    let name = if name.starts_with("a_") { &name[2..] } else { &name[..] };
+    match &name.replace("_", "")[..] {
+        "pos" | "position" | "vertex" => Some(MeshData::Position),
+        "norm" | "normal" => Some(MeshData::Normal),
+        "tex" | "texcoord" | "texcoords" | "uv" => Some(MeshData::TexCoord),
+        _ => None,
+    }
+}
+
+/// A float literal of the component type
+fn float_expr(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+              value: f32, component: &str) -> P<ast::Expr> {
+    let text = format!("{}", value.abs());
+    let text = if text.contains(".") || text.contains("e") { text } else { format!("{}.0", text) };
+    let ty = if component == "f64" { ast::TyF64 } else { ast::TyF32 };
+    let literal = cx.expr_lit(span, ast::LitFloat(token::intern_and_get_ident(&text), ty));
+    if value.is_sign_negative() {
+        cx.expr_unary(span, ast::UnNeg, literal)
+    } else {
+        literal
+    }
+}
+
+/// The value of a `#[skip]` field of an embedded vertex, zero for
+/// components and arrays of them
+fn zero_expr(cx: &mut ext::base::ExtCtxt, span: codemap::Span, ty: &ast::Ty) -> Option<P<ast::Expr>> {
+    let is_phantom = match ty.node {
+        ast::TyPath(_, ref p) => p.segments.last().map_or(false, |segment|
+            segment.identifier.name.as_str() == "PhantomData"
+        ),
+        _ => false,
+    };
+    if is_phantom {
+        return Some(quote_expr!(cx, ::std::marker::PhantomData))
+    }
+    components(ty).map(|(component, count)| {
+        let zero = if component == "bool" {
+            quote_expr!(cx, false)
+        } else {
+            let component = cx.ident_of(&component);
+            quote_expr!(cx, 0 as $component)
+        };
+        match count {
+            Some(count) => {
+                let count = cx.expr_usize(span, count);
+                quote_expr!(cx, [$zero; $count])
+            },
+            None => zero,
+        }
+    })
+}
+
+/// Expand `mesh_data!("assets/cube.obj" as Vertex)` into a pair of
+/// `&'static [Vertex]` and `&'static [u32]`, the distinct vertices of an OBJ
+/// file and the indices of its triangles. The fields of the previously
+/// declared `#[vertex_format]` struct are filled by attribute name: position
+/// (`pos`, `position`), normal (`normal`) and texture coordinate attributes
+/// (`tex_coord`, `uv`), optionally prefixed by `a_`, take float arrays.
+/// `#[skip]` fields are zeroed. The path is relative to the crate root.
+pub fn expand_mesh(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+                   tts: &[ast::TokenTree]) -> Box<MacResult + 'static> {
+    let mut parser = cx.new_parser_from_tts(tts);
+    let (file, _) = panictry!(parser.parse_str());
+    panictry!(parser.expect_keyword(token::keywords::As));
+    let vertex_ty = parser.parse_ty();
+    panictry!(parser.expect(&token::Eof));
+
+    let channels = match find_channels(cx, &vertex_ty, "mesh_data!") {
+        Some(channels) => channels,
+        None => return DummyResult::expr(span),
+    };
+    let path = super::resolve_path(cx, span, &file);
+    let text = match super::read_file(cx, span, &path).map(String::from_utf8) {
+        Some(Ok(text)) => text,
+        Some(Err(_)) => {
+            cx.span_err(span, &format!("`{}` is not valid UTF-8", path.display()));
+            return DummyResult::expr(span)
+        },
+        None => return DummyResult::expr(span),
+    };
+    let mesh = match obj::parse(&text) {
+        Ok(mesh) => mesh,
+        Err((line, message)) => {
+            cx.span_err(span, &format!("{}:{}: {}", path.display(), line, message));
+            return DummyResult::expr(span)
+        },
+    };
+    if mesh.triangles.is_empty() {
+        cx.span_err(span, &format!("`{}` has no faces", path.display()));
+        return DummyResult::expr(span)
+    }
+    let (corners, indices) = mesh.indexed();
+
+    // check the fields once, before filling every vertex
+    let mut fields = Vec::with_capacity(channels.fields.len());
+    for &(access, ref channel) in channels.fields.iter() {
+        let field = match *channel {
+            Channel::Attribute(ref name, ref ty, _) => {
+                let data = match mesh_data_of(name) {
+                    Some(data) => data,
+                    None => {
+                        cx.span_err(span, &format!("Attribute `{}` is not a position, normal \
+                                                    or texture coordinate", name));
+                        continue
+                    },
+                };
+                let (component, count) = match components(ty) {
+                    Some((ref component, Some(count)))
+                        if &component[..] == "f32" || &component[..] == "f64" =>
+                        (component.clone(), count),
+                    _ => {
+                        cx.span_err(span, &format!("Attribute `{}` needs to be an array of \
+                                                    `f32` or `f64`", name));
+                        continue
+                    },
+                };
+                let (available, min) = match data {
+                    MeshData::Position => (4, 2),
+                    MeshData::Normal => (3, 3),
+                    MeshData::TexCoord => (3, 1),
+                };
+                if count < min || count > available {
+                    cx.span_err(span, &format!("Attribute `{}` of {:?} data needs {} to {} \
+                                                components", name, data, min, available));
+                    continue
+                }
+                let missing = corners.iter().any(|corner| match data {
+                    MeshData::Position => false,
+                    MeshData::Normal => corner.normal.is_none(),
+                    MeshData::TexCoord => corner.tex_coord.is_none(),
+                });
+                if missing {
+                    cx.span_err(span, &format!("Attribute `{}` needs {:?} data for every face \
+                                                corner of `{}`", name, data, path.display()));
+                    continue
+                }
+                MeshField::Data(data, component, count)
+            },
+            Channel::Skipped(ref ty) => match zero_expr(cx, span, ty) {
+                Some(zero) => MeshField::Constant(zero),
+                None => {
+                    cx.span_err(span, &format!("Skipped field `{}` can not be zeroed by \
+                                                `mesh_data!`", access.as_string()));
+                    continue
+                },
+            },
+            Channel::Nested => {
+                cx.span_err(span, &format!("Nested format `{}` is not supported by \
+                                            `mesh_data!`", access.as_string()));
+                continue
+            },
+        };
+        fields.push(field);
+    }
+    if fields.len() != channels.fields.len() {
+        return DummyResult::expr(span)
+    }
+
+    let vertices = corners.iter().map(|corner| {
+        let values = fields.iter().map(|field| match *field {
+            MeshField::Data(data, ref component, count) => {
+                let source = match data {
+                    MeshData::Position => &mesh.positions[corner.position][..],
+                    MeshData::Normal => &mesh.normals[corner.normal.unwrap()][..],
+                    MeshData::TexCoord => &mesh.tex_coords[corner.tex_coord.unwrap()][..],
+                };
+                let elements = source[..count].iter().map(|&value|
+                    float_expr(cx, span, value, component)
+                ).collect();
+                cx.expr_vec(span, elements)
+            },
+            MeshField::Constant(ref value) => value.clone(),
+        }).collect();
+        construct(cx, span, &vertex_ty, &channels, values)
+    }).collect();
+    let vertices = cx.expr_vec(span, vertices);
+    let indices: Vec<P<ast::Expr>> = indices.into_iter().map(|index| cx.expr_u32(span, index)).collect();
+    let vertex_count = cx.expr_usize(span, corners.len());
+    let index_count = cx.expr_usize(span, indices.len());
+    let indices = cx.expr_vec(span, indices);
+    MacEager::expr(quote_expr!(cx, {
+        static VERTICES: [$vertex_ty; $vertex_count] = $vertices;
+        static INDICES: [u32; $index_count] = $indices;
+        (&VERTICES[..], &INDICES[..])
+    }))
+}
+
+/// A `#[skip]` field of `size` bytes, named `name` in structs with named fields
+fn padding_field(cx: &mut ext::base::ExtCtxt, span: codemap::Span, name: Option<String>,
+                 vis: ast::Visibility, size: usize) -> ast::StructField {
+    let size = cx.expr_usize(span, size);
+    let skip = cx.attribute(span, cx.meta_word(span, token::InternedString::new("skip")));
+    codemap::Spanned {
+        node: ast::StructField_ {
+            kind: match name {
+                Some(name) => ast::NamedField(cx.ident_of(&name), vis),
+                None => ast::UnnamedField(vis),
+            },
+            id: ast::DUMMY_NODE_ID,
+            ty: quote_ty!(cx, [u8; $size]),
+            attrs: vec![skip],
+        },
+        span: span,
+    }
+}
+
+/// Declare the padding of a `#[stride = "N"]` struct and of its
+/// `#[pad_after = "N"]` fields as `#[skip]` byte arrays, so the layout matches
+/// the one of an existing binary mesh format
+fn insert_padding(cx: &mut ext::base::ExtCtxt, span: codemap::Span,
+                  item: ast::Item) -> ast::Item {
+    let stride = super::find_int(cx, span, &item.attrs, "stride");
+    let padded = match item.node {
+        ast::ItemStruct(ref definition, _) => definition.fields.iter().any(|field|
+            attr::contains_name(&field.node.attrs, "pad_after")
+        ),
+        _ => false,
+    };
+    if stride.is_none() && !padded {
+        return item
+    }
+    let item = super::strip_unconfigured(cx, &item);
+    let (definition, generics) = match item.node {
+        ast::ItemStruct(ref definition, ref generics) => (definition, generics),
+        _ => return item.clone(),
+    };
+
+    let mut fields = Vec::with_capacity(definition.fields.len());
+    for field in definition.fields.iter() {
+        fields.push(field.clone());
+        if let Some(size) = super::find_int(cx, field.span, &field.node.attrs, "pad_after") {
+            let (name, vis) = match field.node.kind {
+                ast::NamedField(ident, vis) => (Some(format!("_pad_after_{}", ident.as_str())), vis),
+                ast::UnnamedField(vis) => (None, vis),
+            };
+            fields.push(padding_field(cx, field.span, name, vis, size as usize));
+        }
+    }
+
+    if let Some(stride) = stride {
+        let stride = stride as usize;
+        let packed = super::has_repr(&item.attrs, "packed");
+        let (end, layout) = {
+            let types: Vec<&ast::Ty> = fields.iter().map(|f| &*f.node.ty).collect();
+            let (offsets, layout) = match layout::struct_layout(&types, packed) {
+                Some(result) => result,
+                None => {
+                    cx.span_err(span, "`#[stride]` needs every field to be a scalar or \
+                                       an array of scalars, to compute the size of the vertex");
+                    return item.clone()
+                },
+            };
+            (offsets.last().map_or(0, |&offset|
+                offset + layout::rust_layout(types[types.len() - 1]).map_or(0, |l| l.size)
+            ), layout)
+        };
+        if stride < end {
+            cx.span_err(span, &format!(
+                "`#[stride = \"{}\"]` is smaller than the {} bytes of the attributes, \
+                which would overlap the ones of the next vertex", stride, end
+            ));
+            return item.clone()
+        }
+        if stride % layout.align != 0 {
+            cx.span_err(span, &format!(
+                "`#[stride = \"{}\"]` is not a multiple of the {} byte alignment of the \
+                fields, use `#[repr(packed)]` to match it", stride, layout.align
+            ));
+            return item.clone()
+        }
+        if stride > end {
+            let (name, vis) = match definition.fields.last().map(|field| field.node.kind) {
+                Some(ast::UnnamedField(vis)) => (None, vis),
+                Some(ast::NamedField(_, vis)) => (Some("_pad_stride".to_string()), vis),
+                None => (Some("_pad_stride".to_string()), ast::Inherited),
+            };
+            fields.push(padding_field(cx, span, name, vis, stride - end));
+        }
+    }
+
+    ast::Item {
+        node: ast::ItemStruct(P(ast::StructDef {
+            fields: fields,
+            ctor_id: definition.ctor_id,
+        }), generics.clone()),
+        .. item.clone()
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct VertexFormatLayout;
+
+impl ItemModifier for VertexFormatLayout {
+    /// Add `#[repr(C)]` to the `struct` unless it has a representation
+    /// already, declare the padding of `#[stride = "N"]` and `#[pad_after = "N"]`
+    /// and pass it on to the `VertexFormat` decorator
+    fn expand(&self, context: &mut ext::base::ExtCtxt, span: codemap::Span,
+              _meta_item: &ast::MetaItem, item: P<ast::Item>) -> P<ast::Item> {
+        item.map(|item| {
+            let mut item = insert_padding(context, span, item);
+            if !item.attrs.iter().any(|a| match a.node.value.node {
+                ast::MetaList(ref name, _) => &name[..] == "repr",
+                _ => false,
+            }) {
+                item.attrs.push(context.attribute(span, context.meta_list(span,
+                    token::InternedString::new("repr"),
+                    vec![context.meta_word(span, token::InternedString::new("C"))]
+                )));
+            }
+            item.attrs.push(context.attribute(span,
+                context.meta_word(span, token::InternedString::new("vertex_format_impl"))
+            ));
+            item
+        })
     }
 }
 
@@ -225,70 +1971,203 @@ pub struct VertexFormat;
 impl ItemDecorator for VertexFormat {
     /// Derive a `gfx::VertexFormat` implementation for the `struct`
     fn expand(&self, context: &mut ext::base::ExtCtxt, span: codemap::Span,
-              meta_item: &ast::MetaItem, item: &ast::Item,
+              _meta_item: &ast::MetaItem, item: &ast::Item,
               push: &mut FnMut(P<ast::Item>)) {
-        // Insert the `gfx` reexport module
-        let path_root = super::extern_crate_hack(context, span, |i| (*push)(i));
-        let fixup = |item| {
-            (*push)(super::fixup_extern_crate_paths(item, path_root))
+        let item = &super::strip_unconfigured(context, item);
+        // Insert the `gfx` reexport module, unless a `#[gfx_crate]` path is given
+        let crate_root = super::crate_root(context, span, &item.attrs, |i| (*push)(i));
+        let path_root = crate_root.ident;
+
+        let (definition, generics) = match item.node {
+            ast::ItemStruct(ref definition, ref generics) => (definition, generics),
+            _ => {
+                context.span_err(span, "Unable to implement `gfx::VertexFormat::generate` \
+                                       on a non-structure");
+                return;
+            }
         };
+        // the padding is only inserted by the `#[vertex_format]` modifier,
+        // a derive can not change the struct
+        if !attr::contains_name(&item.attrs, "vertex_format_impl") {
+            if attr::contains_name(&item.attrs, "stride") {
+                context.span_err(span, "`#[stride]` needs `#[vertex_format]` to insert the \
+                                        padding, `#[derive(VertexFormat)]` can not add fields");
+            }
+            for field in definition.fields.iter() {
+                if attr::contains_name(&field.node.attrs, "pad_after") {
+                    context.span_err(field.span, "`#[pad_after]` needs `#[vertex_format]` to \
+                                                  insert the padding, `#[derive(VertexFormat)]` \
+                                                  can not add fields");
+                }
+            }
+        }
+        check_repr(context, span, &item.attrs);
+        check_padding(context, span, item, definition);
+        check_skin(context, definition);
+
+        // methods generic over a resource parameter name that can't clash
+        // with the ones of the struct
+        let resource = context.ident_of("__R");
+        let resources_bound = context.typarambound(context.path(span, vec![
+            path_root,
+            context.ident_of("gfx"),
+            context.ident_of("Resources"),
+        ]));
+        let mut method_generics = super::no_generics();
+        method_generics.ty_params = OwnedSlice::from_vec(vec![
+            context.typaram(span, resource, OwnedSlice::from_vec(vec![resources_bound]), None)
+        ]);
+
+        // `pub fn attribute_formats() -> &'static [(&'static str, gfx::attrib::Format)]`,
+        // or `Vec<(String, gfx::attrib::Format)>` for layouts only known to the
+        // compiler, describing the layout without a buffer for tools to introspect
+        let struct_ty = super::struct_type(context, span, item.ident, generics);
+        let mut signature = Vec::new();
+        let naming = super::Naming::of(context, span, &item.attrs);
+        let known = known_layout(item, definition);
+        let (formats_body, formats_ty, slots, hash) = method_body(context, span, definition,
+                                                                  &naming, &struct_ty,
+                                                                  known.as_ref(), path_root,
+                                                                  &mut signature);
+        let key = super::item_key(context, item.ident);
+        with_formats(|formats| formats.borrow_mut().insert(key.clone(), signature.clone()));
+        with_channels(|formats| formats.borrow_mut().insert(
+            key, channels(context, definition, &naming)
+        ));
+        let formats_decl = context.fn_decl(Vec::new(), formats_ty);
+        let mut formats_method = super::impl_method(context, span, "attribute_formats", false,
+                                                    super::no_generics(), formats_decl,
+                                                    context.block_expr(formats_body));
+        formats_method.vis = ast::Public;
 
-        // `impl<R: gfx::Resources> gfx::VertexFormat for $item`
-        generic::TraitDef {
-            span: span,
-            attributes: Vec::new(),
-            path: generic::ty::Path::new(
-                vec![super::EXTERN_CRATE_HACK, "gfx", "VertexFormat"],
+        // `pub fn generate_buffers<R: gfx::Resources>(buffers: &[gfx::RawBufferHandle<R>])
+        // -> Vec<gfx::Attribute<R>>`, binding the attributes of every `#[buffer = "N"]`
+        // to the `N`-th buffer. Multi-buffer formats are only bound through it,
+        // as `generate` uses the same buffer for all attributes.
+        let buffers = context.ident_of("buffers");
+        let multi_buffer = slots.is_some();
+        let buffers_body = match slots {
+            Some(slots) => {
+                let slots = slots.into_iter().map(|slot| context.expr_usize(span, slot)).collect();
+                let slots = context.expr_vec_slice(span, slots);
+                quote_expr!(context, {
+                    let slots: &[usize] = $slots;
+                    <$struct_ty>::attribute_formats().iter().zip(slots.iter())
+                        .map(|(&(ref name, ref format), &slot)| {
+                            $path_root::gfx::Attribute {
+                                name: name.to_string(),
+                                buffer: $buffers[slot].clone(),
+                                format: format.clone(),
+                            }
+                        }).collect()
+                })
+            },
+            None => quote_expr!(context,
+                <$struct_ty as $path_root::gfx::VertexFormat>::generate($buffers[0].clone())
             ),
-            additional_bounds: Vec::new(),
-            generics: generic::ty::LifetimeBounds::empty(),
-            methods: vec![
-                // `fn generate(gfx::RawBufferHandle) -> Vec<gfx::Attribute>`
-                generic::MethodDef {
-                    name: "generate",
-                    generics: generic::ty::LifetimeBounds {
-                        lifetimes: Vec::new(),
-                        bounds: vec![
-                            ("R", vec![
-                                generic::ty::Path::new(vec![
-                                    super::EXTERN_CRATE_HACK, "gfx", "Resources"
-                                ]),
-                            ]),
-                        ],
-                    },
-                    explicit_self: None,
-                    args: vec![
-                        generic::ty::Literal(generic::ty::Path {
-                            path: vec![super::EXTERN_CRATE_HACK, "gfx", "RawBufferHandle"],
-                            lifetime: None,
-                            params: vec![box generic::ty::Literal(generic::ty::Path::new_local("R"))],
-                            global: false,
-                        }),
-                    ],
-                    ret_ty: generic::ty::Literal(
-                        generic::ty::Path {
-                            path: vec!["Vec"],
-                            lifetime: None,
-                            params: vec![
-                                box generic::ty::Literal(generic::ty::Path {
-                                    path: vec![super::EXTERN_CRATE_HACK, "gfx", "Attribute"],
-                                    lifetime: None,
-                                    params: vec![box generic::ty::Literal(
-                                        generic::ty::Path::new_local("R")
-                                    )],
-                                    global: false,
-                                }),
-                            ],
-                            global: false,
-                        },
-                    ),
-                    attributes: Vec::new(),
-                    // generate the method body
-                    combine_substructure: generic::combine_substructure(
-                        box |c, s, ss| method_body(c, s, ss, path_root)),
-                },
-            ],
-            associated_types: Vec::new(),
-        }.expand(context, meta_item, item, fixup);
+        };
+        let buffers_decl = context.fn_decl(
+            vec![context.arg(span, buffers,
+                quote_ty!(context, &[$path_root::gfx::RawBufferHandle<$resource>]))],
+            quote_ty!(context, Vec<$path_root::gfx::Attribute<$resource>>)
+        );
+        let mut buffers_method = super::impl_method(context, span, "generate_buffers", false,
+                                                    method_generics.clone(), buffers_decl,
+                                                    context.block_expr(buffers_body));
+        buffers_method.vis = ast::Public;
+
+        // along with `pub fn offset_<field>() -> usize` for interop code, the
+        // byte views of plain formats, the HLSL semantics, the layout hash and
+        // the GLSL declarations
+        let mut inherent_methods = vec![P(formats_method), P(buffers_method)];
+        inherent_methods.extend(offset_methods(context, span, item, definition, &struct_ty).into_iter());
+        inherent_methods.extend(byte_methods(context, span, item, definition, &struct_ty).into_iter());
+        inherent_methods.push(semantics_method(context, span, definition, &naming, &struct_ty));
+        inherent_methods.push(hash_method(context, span, &struct_ty, path_root));
+        inherent_methods.extend(srgb_method(context, span, definition).into_iter());
+        if attr::contains_name(&item.attrs, "emit_glsl_decl") {
+            inherent_methods.push(glsl::decl_method(context, span, "glsl_decl", &signature));
+        }
+        let attrs = vec![super::allow_dead_code(context, span)];
+        let formats_impl = context.item(span, item.ident, attrs, ast::ItemImpl(
+            ast::Unsafety::Normal,
+            ast::ImplPolarity::Positive,
+            generics.clone(),
+            None,
+            struct_ty.clone(),
+            inherent_methods
+        ));
+        (*push)(super::fixup_extern_crate_paths(formats_impl, &crate_root));
+
+        // `pub const NAME_FORMAT_HASH: u64`, the `format_hash()` of formats
+        // laid out at expansion time, for `match` arms and static asset tables
+        if let Some(hash) = hash {
+            let hash = context.expr_lit(span, ast::LitInt(hash, ast::UnsignedIntLit(ast::TyU64)));
+            let name = context.ident_of(&hash_const_name(item.ident));
+            (*push)(context.item_const(span, name, quote_ty!(context, u64), hash).map(|mut hash_item| {
+                hash_item.vis = item.vis;
+                hash_item.attrs.push(super::allow_dead_code(context, span));
+                hash_item
+            }));
+        }
+
+        // `#[test] fn gen_tests_Name()` checking the layout for `#[gen_tests]`
+        if attr::contains_name(&item.attrs, "gen_tests") {
+            if let Some(test) = test_item(context, span, item, definition, generics,
+                                          &struct_ty, &signature) {
+                (*push)(test);
+            }
+        }
+
+        // `NameSoA` companion for `#[soa]`
+        if attr::contains_name(&item.attrs, "soa") {
+            for soa_item in soa_items(context, span, item, definition, generics, &struct_ty) {
+                (*push)(soa_item);
+            }
+        }
+
+        // Formats spread over several buffers can not be bound to a single
+        // one, so they only get `generate_buffers`, and using them where a
+        // `gfx::VertexFormat` is expected fails to compile.
+        if multi_buffer {
+            return;
+        }
+
+        // `fn generate<R: gfx::Resources>(buffer: gfx::RawBufferHandle<R>) -> Vec<gfx::Attribute<R>>`,
+        // allocating as the trait returns a `Vec` of attributes owning their
+        // name and buffer.
+        let buffer = context.ident_of("buffer");
+        let body = quote_expr!(context,
+            <$struct_ty>::attribute_formats().iter().map(|&(ref name, ref format)| {
+                $path_root::gfx::Attribute {
+                    name: name.to_string(),
+                    buffer: $buffer.clone(),
+                    format: format.clone(),
+                }
+            }).collect()
+        );
+        let decl = context.fn_decl(
+            vec![context.arg(span, buffer,
+                quote_ty!(context, $path_root::gfx::RawBufferHandle<$resource>))],
+            quote_ty!(context, Vec<$path_root::gfx::Attribute<$resource>>)
+        );
+        let method = super::impl_method(context, span, "generate", false,
+                                        method_generics, decl, context.block_expr(body));
+
+        // `impl<...> gfx::VertexFormat for $item<...>`, keeping the generics
+        // and where clauses of the struct
+        let impl_item = context.item(span, item.ident, Vec::new(), ast::ItemImpl(
+            ast::Unsafety::Normal,
+            ast::ImplPolarity::Positive,
+            generics.clone(),
+            Some(context.trait_ref(context.path(span, vec![
+                path_root,
+                context.ident_of("gfx"),
+                context.ident_of("VertexFormat"),
+            ]))),
+            struct_ty,
+            vec![P(method)]
+        ));
+        (*push)(super::fixup_extern_crate_paths(impl_item, &crate_root));
     }
 }
@@ -0,0 +1,108 @@
+// Copyright 2015 The Gfx-rs Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![feature(plugin, custom_attribute)]
+#![plugin(gfx_macros)]
+
+mod secret_lib;
+
+use secret_lib::{gfx, Res};
+
+#[vertex_format]
+#[derive(Copy, Clone)]
+#[allow(dead_code)]
+struct Vertex {
+    #[name = "a_Pos"]
+    pos: [f32; 3],
+}
+
+#[shader_param]
+#[derive(Clone)]
+#[allow(dead_code)]
+struct Params<R: gfx::Resources> {
+    #[name = "t_Color"]
+    color: gfx::shade::TextureParam<R>,
+}
+
+#[render_target]
+#[allow(dead_code)]
+struct Targets<R: gfx::Resources> {
+    #[name = "o_Color"]
+    color: gfx::Plane<R>,
+}
+
+// Test declaring a full pipeline
+pipeline!(BlendedPipeline<R: gfx::Resources> {
+    vertex: Vertex,
+    params: Params<R>,
+    targets: Targets<R>,
+    blend: gfx::BlendPreset::Alpha,
+    depth: (gfx::state::Comparison::LessEqual, true),
+});
+
+// Test the default state
+pipeline!(PlainPipeline<R: gfx::Resources> {
+    vertex: Vertex,
+    params: Params<R>,
+});
+
+#[test]
+fn test_draw_state() {
+    let expected = gfx::DrawState::new()
+        .blend(gfx::BlendPreset::Alpha)
+        .depth(gfx::state::Comparison::LessEqual, true);
+    assert!(BlendedPipeline::<Res>::draw_state() == expected);
+    assert!(PlainPipeline::<Res>::draw_state() == gfx::DrawState::new());
+    assert_eq!(BlendedPipeline::<Res>::render_targets().len(), 1);
+}
+
+#[test]
+fn test_init() {
+    fn _init(program: gfx::ProgramHandle<Res>, params: Params<Res>) -> BlendedPipeline<Res> {
+        BlendedPipeline::init(program, params)
+    }
+}
+
+// Test declaring an effect with embedded shaders
+effect!(TexturedEffect<R: gfx::Resources> {
+    vertex_shader: {
+        glsl_150: b"#version 150 core
+in vec3 a_Pos;
+void main() { gl_Position = vec4(a_Pos, 1.0); }
+"
+    },
+    fragment_shader: {
+        glsl_150: b"#version 150 core
+uniform sampler2D t_Color;
+out vec4 o_Color;
+void main() { o_Color = texture(t_Color, vec2(0.5)); }
+"
+    },
+    vertex: Vertex,
+    params: Params<R>,
+    blend: gfx::BlendPreset::Alpha,
+});
+
+#[test]
+fn test_effect() {
+    fn _params(effect: &mut TexturedEffect<Res>) -> &mut Params<Res> {
+        effect.params_mut()
+    }
+    fn _bind(effect: &TexturedEffect<Res>, mesh: gfx::Mesh<Res>)
+             -> Result<gfx::batch::OwnedBatch<Params<Res>>, gfx::batch::Error> {
+        effect.bind(mesh)
+    }
+    assert!(TexturedEffect::<Res>::draw_state() ==
+            gfx::DrawState::new().blend(gfx::BlendPreset::Alpha));
+}
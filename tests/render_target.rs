@@ -0,0 +1,102 @@
+// Copyright 2015 The Gfx-rs Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![feature(plugin, custom_attribute)]
+#![plugin(gfx_macros)]
+
+mod secret_lib;
+
+use secret_lib::{gfx, Res};
+
+// Test declaring the color targets of a G-buffer
+#[render_target]
+#[allow(dead_code)]
+struct GBuffer<R: gfx::Resources> {
+    #[name = "o_Albedo"]
+    #[format = "RGBA8"]
+    albedo: gfx::Plane<R>,
+    #[name = "o_Normal"]
+    #[index = "2"]
+    #[mask = "RGB"]
+    normal: gfx::Plane<R>,
+    #[name = "o_Depth"]
+    #[index = "1"]
+    #[mask = "R"]
+    depth: gfx::Plane<R>,
+}
+
+#[test]
+fn test_render_targets() {
+    let targets = GBuffer::<Res>::render_targets();
+    let names: Vec<(&str, usize)> = targets.iter().map(|&(ref name, index, _, _)| (&name[..], index)).collect();
+    assert_eq!(names, vec![("o_Albedo", 0), ("o_Depth", 1), ("o_Normal", 2)]);
+    assert!(targets[0].2 == Some(gfx::tex::RGBA8));
+    assert!(targets[1].2 == None);
+    assert!(targets[0].3 == gfx::state::MASK_ALL);
+    assert!(targets[1].3 == gfx::state::RED);
+    assert!(targets[2].3 == gfx::state::RED | gfx::state::GREEN | gfx::state::BLUE);
+}
+
+// Test dual-source blending outputs
+#[render_target]
+#[allow(dead_code)]
+struct DualSourceTarget<R: gfx::Resources> {
+    #[name = "o_Color"]
+    color: gfx::Plane<R>,
+    #[name = "o_Coverage"]
+    #[blend_src = "1"]
+    coverage: gfx::Plane<R>,
+}
+
+#[test]
+fn test_blend_src() {
+    assert_eq!(DualSourceTarget::<Res>::render_targets().len(), 1);
+    let outputs = DualSourceTarget::<Res>::output_indices();
+    let outputs: Vec<(&str, usize, usize)> = outputs.iter()
+        .map(|&(ref name, index, source)| (&name[..], index, source)).collect();
+    assert_eq!(outputs, vec![("o_Color", 0, 0), ("o_Coverage", 0, 1)]);
+    assert_eq!(GBuffer::<Res>::output_indices().len(), 3);
+}
+
+// Test mapping engine formats to gfx ones
+#[texture_format]
+#[derive(Copy, Clone)]
+#[allow(dead_code)]
+pub enum SurfaceFormat {
+    #[format = "RGBA8"]
+    Color,
+    #[channels = "RGBA"]
+    #[bits = "16"]
+    #[float]
+    Hdr,
+    #[channels = "RGB"]
+    #[bits = "8"]
+    Albedo,
+    #[channels = "RG"]
+    #[bits = "16"]
+    #[integer]
+    Ids,
+}
+
+#[test]
+fn test_texture_format() {
+    use secret_lib::gfx::tex::{Components, Format};
+    use secret_lib::gfx::attrib::{FloatSize, IntSubType};
+
+    assert!(Format::from(SurfaceFormat::Color) == gfx::tex::RGBA8);
+    assert!(Format::from(SurfaceFormat::Hdr) == Format::Float(Components::RGBA, FloatSize::F16));
+    assert!(Format::from(SurfaceFormat::Albedo) ==
+            Format::Unsigned(Components::RGB, 8, IntSubType::Normalized));
+    assert!(Format::from(SurfaceFormat::Ids) == Format::Unsigned(Components::RG, 16, IntSubType::Raw));
+}
@@ -12,13 +12,73 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#![feature(plugin, custom_attribute)]
+#![feature(plugin, custom_attribute, custom_derive)]
 #![plugin(gfx_macros)]
 
 mod secret_lib;
 
+use std::default::Default;
+use std::marker::PhantomData;
 use secret_lib::{gfx, Res};
 
+/// Program metadata with variables of the given names, in order
+fn program_info(uniforms: &[&str], blocks: &[&str], textures: &[&str]) -> gfx::ProgramInfo {
+    use secret_lib::gfx::device::shade::{BaseType, BlockVar, ContainerType, SamplerVar,
+                                         SamplerType, UniformVar};
+    use secret_lib::gfx::device::shade::{IsArray, IsMultiSample, IsRect, IsShadow};
+
+    gfx::ProgramInfo {
+        attributes: Vec::new(),
+        uniforms: uniforms.iter().enumerate().map(|(i, name)| UniformVar {
+            name: name.to_string(),
+            location: i,
+            count: 1,
+            base_type: BaseType::F32,
+            container: ContainerType::Single,
+        }).collect(),
+        blocks: blocks.iter().map(|name| BlockVar {
+            name: name.to_string(),
+            size: 0,
+            usage: 0,
+        }).collect(),
+        textures: textures.iter().enumerate().map(|(i, name)| SamplerVar {
+            name: name.to_string(),
+            location: i,
+            base_type: BaseType::F32,
+            sampler_type: SamplerType::Sampler2D(IsArray::NoArray, IsShadow::NoShadow,
+                                                 IsMultiSample::NoMultiSample, IsRect::NoRect),
+        }).collect(),
+    }
+}
+
+/// A texture bound without a sampler
+fn texture() -> gfx::shade::TextureParam<Res> {
+    use secret_lib::gfx::device::handle::{Manager, Producer};
+
+    (Manager::new().make_texture((), Default::default()), None)
+}
+
+/// A buffer to bind as a block, which gfx has no buffer role of
+fn buffer() -> gfx::RawBufferHandle<Res> {
+    use secret_lib::gfx::device::{BufferRole, BufferInfo, BufferUsage};
+    use secret_lib::gfx::device::handle::{Manager, Producer};
+
+    Manager::new().make_buffer((), BufferInfo {
+        role: BufferRole::Vertex,
+        usage: BufferUsage::Static,
+        size: 0,
+    })
+}
+
+/// Parameter storage with an unset slot for every variable of the program
+fn param_storage(info: &gfx::ProgramInfo) -> gfx::ParamStorage<Res> {
+    gfx::ParamStorage {
+        uniforms: info.uniforms.iter().map(|_| None).collect(),
+        blocks: info.blocks.iter().map(|_| None).collect(),
+        textures: info.textures.iter().map(|_| None).collect(),
+    }
+}
+
 // Test all features
 #[shader_param]
 #[allow(dead_code)]
@@ -48,3 +108,594 @@ fn test_shader_param() {
     // testing if OwnedBatch can be constructed
     let _owned: gfx::batch::OwnedBatch<TestParam<Res>>;
 }
+
+// Test multiple texture maps in one material
+#[shader_param]
+#[allow(dead_code)]
+struct MaterialParam<R: gfx::Resources> {
+    #[name = "t_Diffuse"]
+    diffuse: gfx::shade::TextureParam<R>,
+    #[name = "t_Normal"]
+    normal: gfx::shade::TextureParam<R>,
+    shininess: f32,
+}
+
+#[test]
+fn test_textures() {
+    use secret_lib::gfx::shade::{ParameterError, ShaderParam};
+
+    let _ref: gfx::batch::RefBatch<MaterialParam<Res>>;
+    let info = program_info(&["shininess"], &[], &["t_Diffuse", "t_Normal"]);
+    let link = MaterialParam::<Res>::create_link(None, &info).unwrap();
+    assert_eq!((link.diffuse, link.normal, link.shininess), (Some(0), Some(1), Some(0)));
+    assert_eq!(MaterialParam::<Res>::create_link(None, &program_info(&[], &[], &["t_Specular"]))
+                                    .err(),
+               Some(ParameterError::MissingTexture("t_Specular".to_string())));
+}
+
+// Test std140 uniform blocks bound through typed buffers
+#[uniform_block]
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[allow(dead_code)]
+struct Light {
+    color: [f32; 4],
+    direction: [f32; 3],
+    intensity: f32,
+    transform: [[f32; 4]; 4],
+}
+
+#[shader_param]
+#[allow(dead_code)]
+struct LightParam<R: gfx::Resources> {
+    #[name = "b_Light"]
+    light: gfx::BufferHandle<R, Light>,
+    #[uniform_block]
+    raw_light: gfx::RawBufferHandle<R>,
+}
+
+#[test]
+fn test_uniform_block() {
+    use secret_lib::gfx::shade::ShaderParam;
+
+    let _ref: gfx::batch::RefBatch<LightParam<Res>>;
+    let info = program_info(&[], &["b_Light", "raw_light"], &[]);
+    let link = LightParam::<Res>::create_link(None, &info).unwrap();
+    assert_eq!((link.light, link.raw_light), (Some(0), Some(1)));
+    let params = LightParam {
+        light: gfx::BufferHandle::from_raw(buffer()),
+        raw_light: buffer(),
+    };
+    let mut out = param_storage(&info);
+    params.fill_params(&link, &mut out);
+    assert!(out.blocks.iter().all(|block| block.is_some()));
+}
+
+// Test std430 layout checks of storage buffer elements
+#[storage_buffer]
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[allow(dead_code)]
+struct Particle {
+    position: [f32; 4],
+    velocity: [f32; 3],
+    life: f32,
+}
+
+#[test]
+fn test_storage_buffer() {
+    let _particles: [Particle; 2];
+}
+
+// Test the `f64` rules, where `dvec3` and `dvec4` are aligned to 32 bytes
+#[uniform_block]
+#[repr(C)]
+#[allow(dead_code)]
+struct Timing {
+    origin: [f64; 3],
+    time: f64,
+    transform: [[f64; 4]; 4],
+}
+
+#[storage_buffer]
+#[repr(C)]
+#[allow(dead_code)]
+struct Body {
+    position: [f64; 3],
+    mass: f64,
+}
+
+#[test]
+fn test_double_blocks() {
+    let _timing: Timing;
+    let _bodies: [Body; 2];
+}
+
+// Test optional parameters
+#[shader_param]
+#[allow(dead_code)]
+struct OptionalParam<R: gfx::Resources> {
+    color: [f32; 4],
+    #[name = "u_Fog"]
+    fog: Option<f32>,
+    detail: Option<gfx::shade::TextureParam<R>>,
+}
+
+#[test]
+fn test_optional() {
+    use secret_lib::gfx::shade::ShaderParam;
+
+    let _ref: gfx::batch::RefBatch<OptionalParam<Res>>;
+    // programs without the uniform link, like for any other parameter
+    let link = OptionalParam::<Res>::create_link(None, &program_info(&["color"], &[], &[]))
+                                    .unwrap();
+    assert_eq!(link.fog, None);
+
+    // `None` is not uploaded, leaving the slot unset
+    let info = program_info(&["color", "u_Fog"], &[], &["detail"]);
+    let link = OptionalParam::<Res>::create_link(None, &info).unwrap();
+    let params = OptionalParam { color: [1.0; 4], fog: None, detail: None };
+    let mut out = param_storage(&info);
+    params.fill_params(&link, &mut out);
+    assert!(out.uniforms[0].is_some());
+    assert!(out.uniforms[1].is_none());
+    assert!(out.textures[0].is_none());
+
+    let params = OptionalParam { fog: Some(0.5), ..params };
+    let mut out = param_storage(&info);
+    params.fill_params(&link, &mut out);
+    match out.uniforms[1] {
+        Some(gfx::UniformValue::F32(fog)) => assert_eq!(fog, 0.5),
+        ref other => panic!("unexpected `u_Fog` value {:?}", other),
+    }
+}
+
+// Test the derive form
+#[derive(ShaderParam)]
+#[allow(dead_code)]
+struct DerivedParam<R: gfx::Resources> {
+    color: [f32; 4],
+    _r: PhantomData<R>,
+}
+
+#[test]
+fn test_derive() {
+    let _ref: gfx::batch::RefBatch<DerivedParam<Res>>;
+}
+
+// Test reaching `gfx` through a reexport
+#[shader_param]
+#[gfx_crate = "secret_lib::gfx"]
+#[allow(dead_code)]
+struct ReexportParam<R: gfx::Resources> {
+    color: [f32; 4],
+    _r: PhantomData<R>,
+}
+
+#[test]
+fn test_gfx_crate() {
+    let _ref: gfx::batch::RefBatch<ReexportParam<Res>>;
+}
+
+// Test generics with a where clause
+#[shader_param]
+#[allow(dead_code)]
+struct WhereParam<'a, R> where R: gfx::Resources + 'a {
+    color: [f32; 4],
+    _r: PhantomData<&'a R>,
+}
+
+#[test]
+fn test_where_clause() {
+    let _ref: gfx::batch::RefBatch<WhereParam<'static, Res>>;
+}
+
+// Test nested parameter structs with prefixed names
+#[shader_param]
+#[allow(dead_code)]
+struct ModelParam<R: gfx::Resources> {
+    #[name = "u_Transform"]
+    transform: [[f32; 4]; 4],
+    #[prefix = "material_"]
+    material: MaterialParam<R>,
+}
+
+#[test]
+fn test_nested() {
+    use secret_lib::gfx::shade::{ParameterError, ShaderParam};
+
+    let _ref: gfx::batch::RefBatch<ModelParam<Res>>;
+    let info = program_info(&["u_Transform", "material_shininess"], &[],
+                            &["material_t_Diffuse", "material_t_Normal"]);
+    let link = ModelParam::<Res>::create_link(None, &info).unwrap();
+    assert_eq!((link.transform, link.material_shininess), (Some(0), Some(1)));
+    assert_eq!((link.material_diffuse, link.material_normal), (Some(0), Some(1)));
+    // the nested names are prefixed
+    assert_eq!(ModelParam::<Res>::create_link(None, &program_info(&[], &[], &["t_Diffuse"]))
+                                 .err(),
+               Some(ParameterError::MissingTexture("t_Diffuse".to_string())));
+}
+
+// Test nesting parameter structs of the same name from different modules
+mod sky {
+    use std::marker::PhantomData;
+    use secret_lib::gfx;
+
+    #[shader_param]
+    #[allow(dead_code)]
+    pub struct Params<R: gfx::Resources> {
+        pub color: [f32; 4],
+        pub _r: PhantomData<R>,
+    }
+}
+
+mod fog {
+    use std::marker::PhantomData;
+    use secret_lib::gfx;
+
+    #[shader_param]
+    #[allow(dead_code)]
+    pub struct Params<R: gfx::Resources> {
+        pub density: f32,
+        pub _r: PhantomData<R>,
+    }
+}
+
+#[shader_param]
+#[emit_glsl_decl]
+#[allow(dead_code)]
+struct WorldParam<R: gfx::Resources> {
+    #[prefix = "sky_"]
+    sky: sky::Params<R>,
+    #[prefix = "fog_"]
+    fog: self::fog::Params<R>,
+}
+
+#[test]
+fn test_nested_paths() {
+    assert_eq!(WorldParam::<Res>::glsl_decl(),
+               "uniform vec4 sky_color;\nuniform float fog_density;\n");
+}
+
+// Test skipping CPU-side bookkeeping fields
+#[shader_param]
+#[allow(dead_code)]
+struct SkipParam<R: gfx::Resources> {
+    color: [f32; 4],
+    #[skip]
+    dirty: bool,
+    #[skip]
+    timestamp: u64,
+    _r: PhantomData<R>,
+}
+
+#[test]
+fn test_skip() {
+    use secret_lib::gfx::shade::{ParameterError, ShaderParam};
+
+    let _ref: gfx::batch::RefBatch<SkipParam<Res>>;
+    let link = SkipParam::<Res>::create_link(None, &program_info(&["color"], &[], &[])).unwrap();
+    assert_eq!(link.color, Some(0));
+    assert_eq!(SkipParam::<Res>::create_link(None, &program_info(&["dirty"], &[], &[])).err(),
+               Some(ParameterError::MissingUniform("dirty".to_string())));
+}
+
+// Test tuple structs with named elements
+#[shader_param]
+#[allow(dead_code)]
+struct TupleParam<R: gfx::Resources>(#[name = "u_Color"] [f32; 4], PhantomData<R>);
+
+#[test]
+fn test_tuple_struct() {
+    use secret_lib::gfx::shade::ShaderParam;
+
+    let _ref: gfx::batch::RefBatch<TupleParam<Res>>;
+    let info = program_info(&["u_Color"], &[], &[]);
+    let link = TupleParam::<Res>::create_link(None, &info).unwrap();
+    assert_eq!(link._0, Some(0));
+    let mut out = param_storage(&info);
+    TupleParam([1.0; 4], PhantomData).fill_params(&link, &mut out);
+    assert!(out.uniforms[0].is_some());
+}
+
+// Test the declarative form
+gfx_parameters!(DeclaredParam<R: gfx::Resources> {
+    color: [f32; 4] = "u_Color",
+    diffuse: gfx::shade::TextureParam<R> = "t_Diffuse",
+    shininess: f32,
+});
+
+#[test]
+fn test_gfx_parameters() {
+    let _ref: gfx::batch::RefBatch<DeclaredParam<Res>>;
+}
+
+// Test default values
+#[shader_param]
+#[allow(dead_code)]
+struct DefaultParam<R: gfx::Resources> {
+    #[default = "[1.0, 1.0, 1.0, 1.0]"]
+    color: [f32; 4],
+    #[default = "32.0"]
+    shininess: f32,
+    samples: i32,
+    _r: PhantomData<R>,
+}
+
+#[test]
+fn test_default() {
+    let params: DefaultParam<Res> = Default::default();
+    assert_eq!(params.color, [1.0; 4]);
+    assert_eq!(params.shininess, 32.0);
+    assert_eq!(params.samples, 0);
+}
+
+// Test equality and hashing for caching parameter sets
+#[shader_param]
+#[param_cache]
+#[allow(dead_code)]
+struct CachedParam<R: gfx::Resources> {
+    color: [f32; 4],
+    tint: Option<[f32; 3]>,
+    #[skip]
+    frame: u32,
+    _r: PhantomData<R>,
+}
+
+#[test]
+fn test_param_cache() {
+    use std::collections::HashSet;
+
+    let a: CachedParam<Res> = CachedParam { color: [1.0; 4], tint: None, frame: 0, _r: PhantomData };
+    let b: CachedParam<Res> = CachedParam { color: [1.0; 4], tint: None, frame: 1, _r: PhantomData };
+    let c: CachedParam<Res> = CachedParam { color: [1.0; 4], tint: Some([0.5; 3]), frame: 0, _r: PhantomData };
+    assert!(a == b);
+    assert!(a != c);
+    let mut set = HashSet::new();
+    set.insert(a);
+    set.insert(b);
+    set.insert(c);
+    assert_eq!(set.len(), 2);
+}
+
+// Test splitting per-frame and per-object parameters
+#[shader_param]
+#[allow(dead_code)]
+struct RateParam<R: gfx::Resources> {
+    #[rate(frame)]
+    #[name = "u_ViewProj"]
+    view_proj: [[f32; 4]; 4],
+    #[rate(object)]
+    #[name = "u_Model"]
+    model: [[f32; 4]; 4],
+    #[name = "t_Diffuse"]
+    diffuse: gfx::shade::TextureParam<R>,
+}
+
+#[test]
+fn test_rate() {
+    fn _fill(params: &RateParam<Res>, link: &_RateParamLink, out: &mut gfx::ParamStorage<Res>) {
+        params.fill_frame_params(link, out);
+        params.fill_object_params(link, out);
+    }
+}
+
+// Test enums of mutually exclusive parameter sets
+#[shader_param]
+#[allow(dead_code)]
+enum ModeParam<R: gfx::Resources> {
+    Flat {
+        #[name = "u_Color"]
+        color: [f32; 4],
+    },
+    Textured {
+        #[name = "u_Color"]
+        tint: [f32; 4],
+        #[name = "t_Diffuse"]
+        diffuse: gfx::shade::TextureParam<R>,
+    },
+    Debug,
+}
+
+#[test]
+fn test_enum() {
+    use secret_lib::gfx::shade::ShaderParam;
+
+    let _ref: gfx::batch::RefBatch<ModeParam<Res>>;
+    // the variants share the link of `u_Color`
+    let info = program_info(&["u_Color"], &[], &["t_Diffuse"]);
+    let link = ModeParam::<Res>::create_link(None, &info).unwrap();
+    assert_eq!((link.flat_color, link.textured_diffuse), (Some(0), Some(0)));
+
+    // only the values of the active variant are uploaded
+    let fill = |params: ModeParam<Res>| {
+        let mut out = param_storage(&info);
+        params.fill_params(&link, &mut out);
+        (out.uniforms[0].is_some(), out.textures[0].is_some())
+    };
+    assert_eq!(fill(ModeParam::Flat { color: [1.0; 4] }), (true, false));
+    assert_eq!(fill(ModeParam::Textured { tint: [1.0; 4], diffuse: texture() }), (true, true));
+    assert_eq!(fill(ModeParam::Debug), (false, false));
+}
+
+// Test builders checking required fields
+#[shader_param]
+#[builder]
+#[allow(dead_code)]
+struct BuiltParam<R: gfx::Resources> {
+    #[name = "u_Color"]
+    color: [f32; 4],
+    #[default = "32.0"]
+    shininess: f32,
+    #[name = "u_Fog"]
+    fog: Option<f32>,
+    _r: PhantomData<R>,
+}
+
+#[test]
+fn test_builder() {
+    let params: BuiltParam<Res> = BuiltParam::builder()
+        .color([1.0; 4])
+        .build()
+        .unwrap();
+    assert_eq!(params.color, [1.0; 4]);
+    assert_eq!(params.shininess, 32.0);
+    assert_eq!(params.fog, None);
+    let missing: Result<BuiltParam<Res>, _> = BuiltParamBuilder::new().fog(Some(0.5)).build();
+    assert_eq!(missing.err(), Some("color"));
+}
+
+// Test GLSL declarations of the parameters
+#[shader_param]
+#[emit_glsl_decl]
+#[allow(dead_code)]
+struct DeclParam<R: gfx::Resources> {
+    #[name = "u_Transform"]
+    transform: [[f32; 4]; 4],
+    #[name = "u_Weights"]
+    weights: [f32; 3],
+    #[name = "t_Diffuse"]
+    diffuse: gfx::shade::TextureParam<R>,
+}
+
+#[test]
+fn test_glsl_decl() {
+    assert_eq!(DeclParam::<Res>::glsl_decl(),
+               "uniform mat4 u_Transform;\nuniform vec3 u_Weights;\nuniform sampler2D t_Diffuse;\n");
+}
+
+// Test struct level naming of the parameters
+#[shader_param]
+#[emit_glsl_decl]
+#[name_convention = "camelCase"]
+#[name_prefix = "u_"]
+#[allow(dead_code)]
+struct NamedParam<R: gfx::Resources> {
+    light_color: [f32; 4],
+    #[name = "t_Diffuse"]
+    diffuse: gfx::shade::TextureParam<R>,
+}
+
+#[test]
+fn test_name_convention() {
+    assert_eq!(NamedParam::<Res>::glsl_decl(),
+               "uniform vec4 u_lightColor;\nuniform sampler2D t_Diffuse;\n");
+}
+
+// Test structs used both as vertex data and as parameters
+#[vertex_format]
+#[shader_param]
+#[emit_glsl_decl]
+#[derive(Copy, Clone)]
+#[allow(dead_code)]
+struct SpriteParam<R: gfx::Resources> {
+    #[name = "a_Pos"]
+    pos: [f32; 2],
+    #[name = "u_Size"]
+    size: f32,
+    _r: PhantomData<R>,
+}
+
+#[test]
+fn test_vertex_format() {
+    let _ref: gfx::batch::RefBatch<SpriteParam<Res>>;
+    assert_eq!(SpriteParam::<Res>::attribute_formats().len(), 2);
+    assert_eq!(SpriteParam::<Res>::glsl_decl(), "attribute vec2 a_Pos;\nattribute float u_Size;\n");
+    assert_eq!(SpriteParam::<Res>::glsl_uniform_decl(), "uniform vec2 a_Pos;\nuniform float u_Size;\n");
+}
+
+// Test matrices of every square size
+#[shader_param]
+#[emit_glsl_decl]
+#[allow(dead_code)]
+struct MatrixParam<R: gfx::Resources> {
+    #[name = "u_Rotation"]
+    rotation: [[f32; 2]; 2],
+    #[name = "u_Normal"]
+    normal: [[f32; 3]; 3],
+    #[name = "u_Transform"]
+    transform: [[f32; 4]; 4],
+    _r: PhantomData<R>,
+}
+
+#[test]
+fn test_matrices() {
+    let _ref: gfx::batch::RefBatch<MatrixParam<Res>>;
+    assert_eq!(MatrixParam::<Res>::glsl_decl(),
+               "uniform mat2 u_Rotation;\nuniform mat3 u_Normal;\nuniform mat4 u_Transform;\n");
+}
+
+// Test relinking against a reloaded program
+#[shader_param]
+#[relink]
+#[allow(dead_code)]
+struct ReloadParam<R: gfx::Resources> {
+    #[name = "u_Color"]
+    color: [f32; 4],
+    #[name = "t_Diffuse"]
+    diffuse: gfx::shade::TextureParam<R>,
+}
+
+#[test]
+fn test_relink() {
+    fn _reload(link: &mut _ReloadParamLink, program: &gfx::ProgramHandle<Res>)
+               -> Result<(), gfx::shade::ParameterError> {
+        link.relink(program)
+    }
+}
+
+// Test showing the parameters by their shader names
+#[shader_param]
+#[debug_params]
+#[allow(dead_code)]
+struct DebugParam<R: gfx::Resources> {
+    #[name = "u_Color"]
+    color: [f32; 4],
+    #[name = "u_Fog"]
+    fog: Option<f32>,
+    #[skip]
+    frame: u32,
+    _r: PhantomData<R>,
+}
+
+#[test]
+fn test_debug_params() {
+    let params: DebugParam<Res> = DebugParam { color: [1.0, 0.0, 0.0, 1.0], fog: None, frame: 3, _r: PhantomData };
+    assert_eq!(format!("{:?}", params), "DebugParam { u_Color: [1, 0, 0, 1], u_Fog: None }");
+}
+
+// Test uploading one value under a renamed uniform as well
+#[shader_param]
+#[emit_glsl_decl]
+#[allow(dead_code)]
+struct AliasParam<R: gfx::Resources> {
+    #[name = "u_Model"]
+    #[alias = "u_World"]
+    model: [[f32; 4]; 4],
+    _r: PhantomData<R>,
+}
+
+#[test]
+fn test_alias() {
+    let _ref: gfx::batch::RefBatch<AliasParam<Res>>;
+    fn _links(link: &_AliasParamLink) -> (Option<gfx::shade::VarUniform>, Option<gfx::shade::VarUniform>) {
+        (link.model, link.model_alias0)
+    }
+    assert_eq!(AliasParam::<Res>::glsl_decl(), "uniform mat4 u_Model;\nuniform mat4 u_World;\n");
+}
+
+// Test a fully baked shader without any parameters
+#[shader_param]
+#[allow(dead_code)]
+struct BakedParam<R: gfx::Resources> {
+    _r: PhantomData<R>,
+}
+
+#[test]
+fn test_marker_only() {
+    use secret_lib::gfx::shade::ShaderParam;
+
+    let _ref: gfx::batch::RefBatch<BakedParam<Res>>;
+    let _link: _BakedParamLink = _BakedParamLink;
+    assert!(BakedParam::<Res>::create_link(None, &program_info(&[], &[], &[])).is_ok());
+}
@@ -0,0 +1,307 @@
+// Copyright 2015 The Gfx-rs Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![feature(plugin, custom_attribute)]
+#![plugin(gfx_macros)]
+
+mod secret_lib;
+
+use std::marker::PhantomData;
+use secret_lib::gfx;
+
+static VERTEX_SRC: gfx::ShaderSource<'static> = shaders! {
+    #[validate]
+    glsl_120: b"#version 120\nattribute vec2 a_Pos;\nvoid main() {}\n"
+    glsl_150: file("tests/shaders/simple_150.vert")
+};
+
+#[test]
+fn test_shaders() {
+    assert_eq!(VERTEX_SRC.glsl_120,
+               Some(&b"#version 120\nattribute vec2 a_Pos;\nvoid main() {}\n"[..]));
+    assert_eq!(VERTEX_SRC.glsl_130, None);
+    assert_eq!(VERTEX_SRC.glsl_140, None);
+    assert_eq!(VERTEX_SRC.glsl_150, Some(&include_bytes!("shaders/simple_150.vert")[..]));
+}
+
+static LIT_SRC: gfx::ShaderSource<'static> = shaders! {
+    #[validate]
+    glsl_150: file("tests/shaders/lit_150.frag")
+};
+
+#[test]
+fn test_include() {
+    let expected = "#version 150 core
+float lambert(vec3 normal, vec3 light) {
+    return max(dot(normal, light), 0.0);
+}
+in vec3 v_Normal;
+out vec4 o_Color;
+void main() {
+    o_Color = vec4(lambert(v_Normal, vec3(0.0, 0.0, 1.0)));
+}
+";
+    assert_eq!(LIT_SRC.glsl_150, Some(expected.as_bytes()));
+}
+
+static GEOMETRY_SRC: gfx::ShaderSource<'static> = shaders! {
+    #[validate]
+    #[stage = "geometry"]
+    glsl_150: b"#version 150 core
+layout(points) in;
+layout(triangle_strip, max_vertices = 4) out;
+void main() {
+    EmitVertex();
+    EndPrimitive();
+}
+"
+};
+
+#[test]
+fn test_geometry() {
+    assert_eq!(GEOMETRY_SRC.glsl_120, None);
+    assert!(GEOMETRY_SRC.glsl_150.is_some());
+}
+
+static REEXPORT_SRC: gfx::ShaderSource<'static> = shaders! {
+    #[gfx_crate = "secret_lib::gfx"]
+    glsl_120: b"void main() {}"
+};
+
+#[test]
+fn test_gfx_crate() {
+    assert_eq!(REEXPORT_SRC.glsl_120, Some(&b"void main() {}"[..]));
+}
+
+static VARIANTS: &'static [(&'static str, gfx::ShaderSource<'static>)] = shader_variants! {
+    #[flags = "SKINNED, SHADOWS"]
+    glsl_150: b"#version 150 core\nvoid main() {}\n"
+};
+
+static SELECTED_VARIANTS: &'static [(&'static str, gfx::ShaderSource<'static>)] = shader_variants! {
+    #[flags = "SKINNED, SHADOWS"]
+    #[variant = "SHADOWS"]
+    glsl_120: b"void main() {}"
+};
+
+#[test]
+fn test_variants() {
+    let names: Vec<&str> = VARIANTS.iter().map(|&(name, _)| name).collect();
+    assert_eq!(names, vec!["", "SKINNED", "SHADOWS", "SKINNED+SHADOWS"]);
+    assert_eq!(VARIANTS[3].1.glsl_150, Some(&b"#version 150 core\n\
+        #define SKINNED\n#define SHADOWS\nvoid main() {}\n"[..]));
+    assert_eq!(SELECTED_VARIANTS.len(), 1);
+    assert_eq!(SELECTED_VARIANTS[0].0, "SHADOWS");
+    assert_eq!(SELECTED_VARIANTS[0].1.glsl_120, Some(&b"#define SHADOWS\nvoid main() {}\n"[..]));
+}
+
+#[shader_constant]
+const MAX_LIGHTS: usize = 8;
+
+static CONSTANT_SRC: gfx::ShaderSource<'static> = shaders! {
+    glsl_150: b"#version 150 core\nuniform vec4 u_Lights[$MAX_LIGHTS];\n"
+};
+
+#[test]
+fn test_constants() {
+    assert_eq!(MAX_LIGHTS, 8);
+    assert_eq!(CONSTANT_SRC.glsl_150,
+               Some(&b"#version 150 core\nuniform vec4 u_Lights[8];\n"[..]));
+}
+
+mod forward {
+    use secret_lib::gfx;
+
+    #[shader_constant]
+    pub const MAX_LIGHTS: usize = 4;
+
+    pub static SRC: gfx::ShaderSource<'static> = shaders! {
+        glsl_150: b"#version 150 core\nuniform vec4 u_Lights[$MAX_LIGHTS];\n"
+    };
+}
+
+#[test]
+fn test_constants_by_module() {
+    assert_eq!(forward::MAX_LIGHTS, 4);
+    assert_eq!(forward::SRC.glsl_150,
+               Some(&b"#version 150 core\nuniform vec4 u_Lights[4];\n"[..]));
+    assert_eq!(CONSTANT_SRC.glsl_150,
+               Some(&b"#version 150 core\nuniform vec4 u_Lights[8];\n"[..]));
+}
+
+static STRIPPED_SRC: gfx::ShaderSource<'static> = shaders! {
+    #[strip]
+    glsl_150: b"#version 150 core
+// a comment
+in vec3   v_Normal;
+
+void main() { /* nothing */ }
+"
+};
+
+static STRIPPED_LINES_SRC: gfx::ShaderSource<'static> = shaders! {
+    #[strip = "keep_lines"]
+    glsl_150: b"#version 150 core
+// a comment
+in vec3   v_Normal;
+"
+};
+
+#[test]
+fn test_strip() {
+    assert_eq!(STRIPPED_SRC.glsl_150,
+               Some(&b"#version 150 core\nin vec3 v_Normal;\nvoid main() { }\n"[..]));
+    assert_eq!(STRIPPED_LINES_SRC.glsl_150,
+               Some(&b"#version 150 core\n\nin vec3 v_Normal;\n\n"[..]));
+}
+
+static HASHED_SRC: (gfx::ShaderSource<'static>, u64) = shaders! {
+    #[hash]
+    glsl_150: b"#version 150 core\nvoid main() {}\n"
+};
+
+static OTHER_HASHED_SRC: (gfx::ShaderSource<'static>, u64) = shaders! {
+    #[hash]
+    glsl_140: b"#version 150 core\nvoid main() {}\n"
+};
+
+#[test]
+fn test_hash() {
+    assert_eq!(HASHED_SRC.0.glsl_150, Some(&b"#version 150 core\nvoid main() {}\n"[..]));
+    assert_eq!(HASHED_SRC.1, 0xc136c4f4fe7c8944);
+    assert!(HASHED_SRC.1 != OTHER_HASHED_SRC.1);
+}
+
+static DOWNGRADED_VERT_SRC: gfx::ShaderSource<'static> = shaders! {
+    #[stage = "vertex"]
+    #[downgrade]
+    glsl_150: b"#version 150 core
+in vec3 a_Pos;
+out vec2 v_TexCoord;
+void main() {
+    v_TexCoord = a_Pos.xy;
+}
+"
+};
+
+static DOWNGRADED_FRAG_SRC: gfx::ShaderSource<'static> = shaders! {
+    #[stage = "fragment"]
+    #[downgrade]
+    glsl_150: b"#version 150 core
+in vec2 v_TexCoord;
+uniform sampler2D t_Color;
+uniform samplerCube t_Env;
+out vec4 o_Color;
+void main() {
+    o_Color = texture(t_Color, v_TexCoord) + texture(t_Env, vec3(v_TexCoord, 1.0));
+}
+"
+};
+
+static DOWNGRADED_LOOKUPS_SRC: gfx::ShaderSource<'static> = shaders! {
+    #[stage = "fragment"]
+    #[downgrade]
+    glsl_150: b"#version 150 core
+in vec3 v_TexCoord;
+uniform sampler1D t_Ramp;
+uniform sampler3D t_Volume;
+uniform sampler2DShadow t_Shadow;
+out vec4 o_Color;
+void main() {
+    float lit = texture(t_Shadow, v_TexCoord);
+    o_Color = lit * texture(t_Ramp, textureProj(t_Volume, vec4(v_TexCoord, 2.0)).x);
+}
+"
+};
+
+#[test]
+fn test_downgrade() {
+    assert_eq!(DOWNGRADED_VERT_SRC.glsl_120, Some(&b"#version 120
+attribute vec3 a_Pos;
+varying vec2 v_TexCoord;
+void main() {
+    v_TexCoord = a_Pos.xy;
+}
+"[..]));
+    assert_eq!(DOWNGRADED_FRAG_SRC.glsl_120, Some(&b"#version 120
+varying vec2 v_TexCoord;
+uniform sampler2D t_Color;
+uniform samplerCube t_Env;
+void main() {
+    gl_FragColor = texture2D(t_Color, v_TexCoord) + textureCube(t_Env, vec3(v_TexCoord, 1.0));
+}
+"[..]));
+    assert_eq!(DOWNGRADED_LOOKUPS_SRC.glsl_120, Some(&b"#version 120
+varying vec3 v_TexCoord;
+uniform sampler1D t_Ramp;
+uniform sampler3D t_Volume;
+uniform sampler2DShadow t_Shadow;
+void main() {
+    float lit = shadow2D(t_Shadow, v_TexCoord).r;
+    gl_FragColor = lit * texture1D(t_Ramp, texture3DProj(t_Volume, vec4(v_TexCoord, 2.0)).x);
+}
+"[..]));
+}
+
+// Test checking structs against the shader stages they are linked with
+#[vertex_format]
+#[derive(Copy, Clone)]
+#[allow(dead_code)]
+struct LinkedVertex {
+    #[name = "a_Pos"]
+    pos: [f32; 3],
+    #[name = "a_Color"]
+    #[normalized]
+    color: [u8; 4],
+}
+
+#[shader_param]
+#[allow(dead_code)]
+struct LinkedParam<R: gfx::Resources> {
+    #[name = "u_Transform"]
+    transform: [[f32; 4]; 4],
+    #[name = "u_Light"]
+    light: [f32; 4],
+    #[name = "t_Diffuse"]
+    diffuse: gfx::shade::TextureParam<R>,
+    _r: PhantomData<R>,
+}
+
+link_check!(LinkedVertex, LinkedParam, {
+    glsl_150: b"#version 150 core
+in vec3 a_Pos;
+layout(location = 1) in vec4 a_Color;
+uniform mat4 u_Transform;
+out vec4 v_Color;
+void main() {
+    v_Color = a_Color;
+    gl_Position = u_Transform * vec4(a_Pos, 1.0);
+}
+"
+}, {
+    glsl_150: b"#version 150 core
+in vec4 v_Color;
+uniform vec4 u_Light;
+uniform sampler2D t_Diffuse;
+out vec4 o_Color;
+void main() {
+    o_Color = v_Color * texture(t_Diffuse, vec2(0.5)) * u_Light;
+}
+"
+});
+
+#[test]
+fn test_link_check() {
+    let _ref: gfx::batch::RefBatch<LinkedParam<secret_lib::Res>>;
+}
@@ -0,0 +1,4 @@
+#pragma once
+float lambert(vec3 normal, vec3 light) {
+    return max(dot(normal, light), 0.0);
+}
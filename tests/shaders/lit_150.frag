@@ -0,0 +1,8 @@
+#version 150 core
+#include "common.glsl"
+#include "common.glsl"
+in vec3 v_Normal;
+out vec4 o_Color;
+void main() {
+    o_Color = vec4(lambert(v_Normal, vec3(0.0, 0.0, 1.0)));
+}
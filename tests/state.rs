@@ -0,0 +1,74 @@
+// Copyright 2015 The Gfx-rs Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![feature(plugin, custom_attribute)]
+#![plugin(gfx_macros)]
+
+mod secret_lib;
+
+use std::default::Default;
+use secret_lib::gfx;
+use secret_lib::gfx::state::{Blend, BlendChannel, BlendValue, Comparison, Equation, Factor,
+                             StencilOp};
+
+#[test]
+fn test_blend() {
+    let alpha = BlendChannel {
+        equation: Equation::Add,
+        source: Factor::ZeroPlus(BlendValue::SourceAlpha),
+        destination: Factor::OneMinus(BlendValue::SourceAlpha),
+    };
+    let expected = Blend {
+        color: alpha,
+        alpha: alpha,
+        value: [0.0; 4],
+    };
+    assert!(blend!(SrcAlpha, OneMinusSrcAlpha, Add) == expected);
+}
+
+#[test]
+fn test_blend_separate_alpha() {
+    let blend = blend!(One, OneMinusSrcAlpha, Add; One, One, Max);
+    assert!(blend.color.source == Factor::One);
+    assert!(blend.color.destination == Factor::OneMinus(BlendValue::SourceAlpha));
+    assert!(blend.alpha.equation == Equation::Max);
+    assert!(blend.alpha.destination == Factor::One);
+}
+
+// Test configuring depth and stencil tests
+#[depth_stencil]
+#[allow(dead_code)]
+struct Outline {
+    #[fun = "LessEqual"]
+    #[write]
+    depth: gfx::state::Depth,
+    #[fun = "NotEqual"]
+    #[value = "1"]
+    #[ops = "Keep, Keep, Replace"]
+    stencil: gfx::state::Stencil,
+}
+
+#[test]
+fn test_depth_stencil() {
+    let outline: Outline = Default::default();
+    assert!(outline.depth.fun == Comparison::LessEqual);
+    assert!(outline.depth.write);
+    assert!(outline.stencil.front.fun == Comparison::NotEqual);
+    assert_eq!(outline.stencil.front.value, 1);
+    assert_eq!(outline.stencil.back.mask_write, 0xFF);
+    assert!(outline.stencil.back.op_pass == StencilOp::Replace);
+    let state = outline.apply(gfx::DrawState::new());
+    assert!(state.depth == Some(outline.depth));
+    assert!(state.stencil == Some(outline.stencil));
+}
@@ -12,11 +12,23 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#![feature(plugin, custom_attribute)]
+#![feature(plugin, custom_attribute, custom_derive)]
 #![plugin(gfx_macros)]
 
 mod secret_lib;
 
+/// An empty vertex buffer to generate the attributes of
+fn vertex_buffer() -> secret_lib::gfx::RawBufferHandle<secret_lib::Res> {
+    use secret_lib::gfx::device::{BufferRole, BufferInfo, BufferUsage};
+    use secret_lib::gfx::device::handle::{Manager, Producer};
+
+    Manager::new().make_buffer((), BufferInfo {
+        role: BufferRole::Vertex,
+        usage: BufferUsage::Static,
+        size: 0,
+    })
+}
+
 // Test all features
 #[repr(packed)]
 #[vertex_format]
@@ -132,3 +144,727 @@ fn test_vertex_format() {
         },
     ]);
 }
+
+// Test normalized unsigned and signed integer components
+#[repr(packed)]
+#[vertex_format]
+#[derive(Copy, Clone)]
+struct MyColorVertex {
+    #[normalized]
+    color: [u8; 4],
+    #[normalized]
+    normal: [i16; 3],
+}
+
+#[test]
+fn test_normalized() {
+    use secret_lib::gfx::attrib::{Type, IntSubType, IntSize, SignFlag};
+    use secret_lib::{gfx, Res};
+    use secret_lib::gfx::VertexFormat;
+
+    let handle = vertex_buffer();
+
+    let attributes: Vec<gfx::Attribute<Res>> = MyColorVertex::generate(handle);
+    assert_eq!(attributes.len(), 2);
+    assert_eq!(attributes[0].format.elem_count, 4);
+    assert_eq!(attributes[0].format.elem_type,
+               Type::Int(IntSubType::Normalized, IntSize::U8, SignFlag::Unsigned));
+    assert_eq!(attributes[1].format.elem_count, 3);
+    assert_eq!(attributes[1].format.elem_type,
+               Type::Int(IntSubType::Normalized, IntSize::U16, SignFlag::Signed));
+    assert_eq!(attributes[1].format.offset, 4);
+}
+
+// Test per-field instance rates
+#[repr(packed)]
+#[vertex_format]
+#[derive(Copy, Clone)]
+struct MyInstanceRate {
+    a0: [f32; 2],
+    #[instance_rate = "1"]
+    offset: [f32; 2],
+    #[instance_rate = "4"]
+    #[name = "a_color"]
+    color: [f32; 4],
+}
+
+#[test]
+fn test_instance_rate() {
+    use secret_lib::{gfx, Res};
+    use secret_lib::gfx::VertexFormat;
+
+    let handle = vertex_buffer();
+
+    let attributes: Vec<gfx::Attribute<Res>> = MyInstanceRate::generate(handle);
+    let rates: Vec<_> = attributes.iter().map(|a| a.format.instance_rate).collect();
+    assert_eq!(rates, vec![0, 1, 4]);
+    assert_eq!(attributes[2].name, "a_color".to_string());
+}
+
+// Test matrices being split into column attributes
+#[repr(packed)]
+#[vertex_format]
+#[derive(Copy, Clone)]
+struct MyMatrixInstance {
+    a0: f32,
+    #[name = "model"]
+    transform: [[f32; 4]; 4],
+}
+
+#[test]
+fn test_matrix() {
+    use secret_lib::gfx::attrib::{Type, FloatSubType, FloatSize};
+    use secret_lib::{gfx, Res};
+    use secret_lib::gfx::VertexFormat;
+
+    let handle = vertex_buffer();
+
+    let attributes: Vec<gfx::Attribute<Res>> = MyMatrixInstance::generate(handle);
+    let names: Vec<_> = attributes.iter().map(|a| &a.name[..]).collect();
+    assert_eq!(names, vec!["a0", "model_0", "model_1", "model_2", "model_3"]);
+    for (i, a) in attributes[1..].iter().enumerate() {
+        assert_eq!(a.format.elem_count, 4);
+        assert_eq!(a.format.elem_type, Type::Float(FloatSubType::Default, FloatSize::F32));
+        assert_eq!(a.format.offset, 4 + 16 * i as gfx::attrib::Offset);
+        assert_eq!(a.format.stride, 68);
+    }
+}
+
+// Test the derive form
+#[repr(packed)]
+#[derive(Copy, Clone, VertexFormat)]
+struct MyDerivedVertex {
+    pos: [f32; 3],
+}
+
+#[test]
+fn test_derive() {
+    use secret_lib::{gfx, Res};
+    use secret_lib::gfx::VertexFormat;
+
+    let handle = vertex_buffer();
+
+    let attributes: Vec<gfx::Attribute<Res>> = MyDerivedVertex::generate(handle);
+    assert_eq!(attributes.len(), 1);
+    assert_eq!(attributes[0].name, "pos".to_string());
+    assert_eq!(attributes[0].format.stride, 12);
+}
+
+// Test reaching `gfx` through a reexport
+#[repr(packed)]
+#[vertex_format]
+#[gfx_crate = "secret_lib::gfx"]
+#[derive(Copy, Clone)]
+struct MyReexportVertex {
+    pos: [f32; 2],
+}
+
+#[test]
+fn test_gfx_crate() {
+    use secret_lib::{gfx, Res};
+    use secret_lib::gfx::VertexFormat;
+
+    let handle = vertex_buffer();
+
+    let attributes: Vec<gfx::Attribute<Res>> = MyReexportVertex::generate(handle);
+    assert_eq!(attributes.len(), 1);
+}
+
+// Test flattening of nested vertex formats
+#[repr(packed)]
+#[vertex_format]
+#[derive(Copy, Clone)]
+struct PosNormal {
+    pos: [f32; 3],
+    normal: [f32; 3],
+}
+
+#[repr(packed)]
+#[vertex_format]
+#[derive(Copy, Clone)]
+struct MySkinnedVertex {
+    base: PosNormal,
+    #[as_float]
+    weights: [u8; 4],
+}
+
+#[test]
+fn test_nested() {
+    use secret_lib::{gfx, Res};
+    use secret_lib::gfx::VertexFormat;
+
+    let handle = vertex_buffer();
+
+    let attributes: Vec<gfx::Attribute<Res>> = MySkinnedVertex::generate(handle);
+    let layout: Vec<_> = attributes.iter().map(|a|
+        (&a.name[..], a.format.offset, a.format.stride)
+    ).collect();
+    assert_eq!(layout, vec![("pos", 0, 28), ("normal", 12, 28), ("weights", 24, 28)]);
+}
+
+// Test nesting formats of the same name from different modules by path
+mod skinning {
+    #[repr(packed)]
+    #[vertex_format]
+    #[derive(Copy, Clone)]
+    pub struct Vertex {
+        #[as_float]
+        pub weights: [u8; 4],
+    }
+}
+
+mod morphing {
+    #[repr(packed)]
+    #[vertex_format]
+    #[derive(Copy, Clone)]
+    pub struct Vertex {
+        pub delta: [f32; 3],
+    }
+}
+
+#[repr(packed)]
+#[vertex_format]
+#[emit_glsl_decl]
+#[derive(Copy, Clone)]
+struct MyMorphedVertex {
+    skin: skinning::Vertex,
+    morph: self::morphing::Vertex,
+}
+
+#[test]
+fn test_nested_paths() {
+    assert_eq!(MyMorphedVertex::glsl_decl(), "attribute vec4 weights;\nattribute vec3 delta;\n");
+}
+
+// Test skipping CPU-side bookkeeping fields
+#[repr(packed)]
+#[vertex_format]
+#[derive(Copy, Clone)]
+struct MySkipVertex {
+    pos: [f32; 3],
+    #[skip]
+    dirty: bool,
+}
+
+#[test]
+fn test_skip() {
+    use secret_lib::{gfx, Res};
+    use secret_lib::gfx::VertexFormat;
+
+    let handle = vertex_buffer();
+
+    let attributes: Vec<gfx::Attribute<Res>> = MySkipVertex::generate(handle);
+    assert_eq!(attributes.len(), 1);
+    assert_eq!(attributes[0].name, "pos".to_string());
+    assert_eq!(attributes[0].format.stride, 13);
+}
+
+// Test that `PhantomData` markers are skipped
+#[repr(packed)]
+#[vertex_format]
+#[derive(Copy, Clone)]
+struct MyTaggedVertex<T> {
+    pos: [f32; 3],
+    _tag: ::std::marker::PhantomData<T>,
+}
+
+#[test]
+fn test_phantom() {
+    use secret_lib::{gfx, Res};
+    use secret_lib::gfx::VertexFormat;
+
+    let handle = vertex_buffer();
+
+    let attributes: Vec<gfx::Attribute<Res>> = MyTaggedVertex::<u8>::generate(handle);
+    assert_eq!(attributes.len(), 1);
+    assert_eq!(attributes[0].name, "pos".to_string());
+    assert_eq!(attributes[0].format.stride, 12);
+}
+
+// Test pinning attributes with `#[location]`
+#[vertex_format]
+#[derive(Copy, Clone)]
+struct MyLocatedVertex {
+    #[location = "2"]
+    pos: [f32; 3],
+    normal: [f32; 3],
+    #[location = "0"]
+    color: [f32; 4],
+}
+
+#[test]
+fn test_location() {
+    use secret_lib::{gfx, Res};
+    use secret_lib::gfx::VertexFormat;
+
+    let handle = vertex_buffer();
+
+    let attributes: Vec<gfx::Attribute<Res>> = MyLocatedVertex::generate(handle);
+    let names: Vec<&str> = attributes.iter().map(|a| &a.name[..]).collect();
+    assert_eq!(names, vec!["color", "normal", "pos"]);
+    assert_eq!(attributes[2].format.offset, 0);
+}
+
+// Test integer components exposed as integer attributes
+#[repr(packed)]
+#[vertex_format]
+#[derive(Copy, Clone)]
+struct MyBoneVertex {
+    #[as_int]
+    bones: [u8; 4],
+    #[as_int]
+    mask: u32,
+}
+
+#[test]
+fn test_as_int() {
+    use secret_lib::gfx::attrib::{Type, IntSubType, IntSize, SignFlag};
+    use secret_lib::{gfx, Res};
+    use secret_lib::gfx::VertexFormat;
+
+    let handle = vertex_buffer();
+
+    let attributes: Vec<gfx::Attribute<Res>> = MyBoneVertex::generate(handle);
+    assert_eq!(attributes.len(), 2);
+    assert_eq!(attributes[0].format.elem_type,
+               Type::Int(IntSubType::Raw, IntSize::U8, SignFlag::Unsigned));
+    assert_eq!(attributes[1].format.elem_count, 1);
+    assert_eq!(attributes[1].format.elem_type,
+               Type::Int(IntSubType::Raw, IntSize::U32, SignFlag::Unsigned));
+}
+
+// Test half-float components
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone)]
+struct f16(u16);
+
+#[repr(packed)]
+#[vertex_format]
+#[derive(Copy, Clone)]
+struct MyHalfVertex {
+    pos: [f16; 4],
+    #[as_half]
+    tex_coord: [u16; 2],
+}
+
+#[test]
+fn test_half() {
+    use secret_lib::gfx::attrib::{Type, FloatSubType, FloatSize};
+    use secret_lib::{gfx, Res};
+    use secret_lib::gfx::VertexFormat;
+
+    let handle = vertex_buffer();
+
+    let attributes: Vec<gfx::Attribute<Res>> = MyHalfVertex::generate(handle);
+    assert_eq!(attributes.len(), 2);
+    for attribute in attributes.iter() {
+        assert_eq!(attribute.format.elem_type,
+                   Type::Float(FloatSubType::Default, FloatSize::F16));
+    }
+    assert_eq!(attributes[1].format.offset, 8);
+    assert_eq!(attributes[1].format.stride, 12);
+}
+
+#[test]
+fn test_attribute_formats() {
+    use secret_lib::gfx::attrib::{Type, FloatSubType, FloatSize};
+
+    let formats = MySkinnedVertex::attribute_formats();
+    let names: Vec<&str> = formats.iter().map(|&(ref name, _)| &name[..]).collect();
+    assert_eq!(names, vec!["pos", "normal", "weights"]);
+    assert_eq!(formats[1].1.elem_count, 3);
+    assert_eq!(formats[1].1.elem_type, Type::Float(FloatSubType::Default, FloatSize::F32));
+    assert_eq!(formats[1].1.offset, 12);
+    assert_eq!(formats[1].1.stride, 28);
+}
+
+#[test]
+fn test_offsets() {
+    assert_eq!(MyColorVertex::offset_color(), 0);
+    assert_eq!(MyColorVertex::offset_normal(), 4);
+    // `#[repr(C)]` is added to `MyLocatedVertex` by the attribute
+    assert_eq!(MyLocatedVertex::offset_normal(), 12);
+    assert_eq!(MyLocatedVertex::offset_color(), 24);
+    assert_eq!(::std::mem::min_align_of::<MyLocatedVertex>(), 4);
+    assert_eq!(MySkinnedVertex::offset_weights(), 24);
+}
+
+// Test explicitly declared padding in a `#[repr(C)]` struct
+#[vertex_format]
+#[derive(Copy, Clone)]
+struct MyPaddedVertex {
+    #[normalized]
+    color: [u8; 3],
+    #[skip]
+    _padding: u8,
+    pos: [f32; 3],
+}
+
+#[test]
+fn test_padding() {
+    assert_eq!(MyPaddedVertex::offset_pos(), 4);
+    let formats = MyPaddedVertex::attribute_formats();
+    assert_eq!(formats.len(), 2);
+    assert_eq!(formats[1].1.stride, 16);
+}
+
+// Test tuple structs with named elements
+#[vertex_format]
+#[derive(Copy, Clone)]
+struct MyTupleVertex(#[name = "a_Pos"] [f32; 3], #[name = "a_Color"] [f32; 4]);
+
+#[test]
+fn test_tuple_struct() {
+    let formats = MyTupleVertex::attribute_formats();
+    let names: Vec<&str> = formats.iter().map(|&(ref name, _)| &name[..]).collect();
+    assert_eq!(names, vec!["a_Pos", "a_Color"]);
+    assert_eq!(MyTupleVertex::offset_1(), 12);
+    assert_eq!(formats[1].1.offset, 12);
+}
+
+// Test the declarative form
+gfx_vertex!(MyDeclaredVertex {
+    pos: [f32; 3] = "a_Pos",
+    #[normalized]
+    color: [u8; 4] = "a_Color",
+    tex_coord: [f32; 2],
+});
+
+#[test]
+fn test_gfx_vertex() {
+    let formats = MyDeclaredVertex::attribute_formats();
+    let names: Vec<&str> = formats.iter().map(|&(ref name, _)| &name[..]).collect();
+    assert_eq!(names, vec!["a_Pos", "a_Color", "tex_coord"]);
+    assert_eq!(formats[2].1.offset, 16);
+    assert_eq!(formats[2].1.stride, 24);
+    let vertex = MyDeclaredVertex { pos: [0.0; 3], color: [0; 4], tex_coord: [0.0; 2] };
+    let _copy = vertex;
+    let _ = vertex.clone();
+}
+
+// Test formats spread over several buffers
+#[vertex_format]
+#[derive(Copy, Clone)]
+struct MyStreamedVertex {
+    pos: [f32; 3],
+    #[buffer = "1"]
+    #[normalized]
+    color: [u8; 4],
+    tex_coord: [f32; 2],
+}
+
+#[test]
+fn test_buffers() {
+    use secret_lib::{gfx, Res};
+    use secret_lib::gfx::device::{BufferRole, BufferInfo, BufferUsage};
+    use secret_lib::gfx::device::handle::{Manager, Producer};
+
+    let mut hm = Manager::new();
+    let info = |size| BufferInfo {
+        role: BufferRole::Vertex,
+        usage: BufferUsage::Static,
+        size: size,
+    };
+    // distinct handles, sized for 16 vertices each
+    let buffers = vec![hm.make_buffer((), info(16 * 20)), hm.make_buffer((), info(16 * 4))];
+    assert!(buffers[0] != buffers[1]);
+
+    let attributes: Vec<gfx::Attribute<Res>> = MyStreamedVertex::generate_buffers(&buffers);
+    let layout: Vec<_> = attributes.iter().map(|a|
+        (&a.name[..], a.buffer == buffers[0], a.buffer == buffers[1], a.format.offset, a.format.stride)
+    ).collect();
+    assert_eq!(layout, vec![
+        ("pos", true, false, 0, 20),
+        ("color", false, true, 0, 4),
+        ("tex_coord", true, false, 12, 20),
+    ]);
+}
+
+// Test structure-of-arrays companions
+#[vertex_format]
+#[soa]
+#[derive(Copy, Clone)]
+struct MyChannelVertex {
+    position: [f32; 3],
+    #[normalized]
+    color: [u8; 4],
+    tex_coords: [f32; 2],
+}
+
+#[test]
+fn test_soa() {
+    let mut channels = MyChannelVertexSoA::new();
+    channels.push(MyChannelVertex { position: [1.0; 3], color: [255; 4], tex_coords: [0.5; 2] });
+    channels.positions.push([2.0; 3]);
+    channels.colors.push([0; 4]);
+    channels.tex_coords.push([1.0; 2]);
+    assert_eq!(channels.len(), 2);
+
+    let empty = MyChannelVertex { position: [0.0; 3], color: [0; 4], tex_coords: [0.0; 2] };
+    let mut vertices = vec![empty; 2];
+    channels.interleave(&mut vertices);
+    assert_eq!(vertices[0].color, [255; 4]);
+    assert_eq!(vertices[1].position, [2.0; 3]);
+    assert_eq!(vertices[1].tex_coords, [1.0; 2]);
+}
+
+// Test conversions between formats, matched by attribute name
+#[vertex_format]
+#[derive(Copy, Clone)]
+struct MyDetailedVertex {
+    #[name = "a_Pos"]
+    pos: [f64; 3],
+    #[name = "a_Color"]
+    color: [f32; 4],
+    #[name = "a_Tangent"]
+    tangent: [f32; 4],
+}
+
+#[vertex_format]
+#[derive(Copy, Clone)]
+struct MyCompactVertex {
+    #[name = "a_Pos"]
+    position: [f32; 3],
+    #[name = "a_Color"]
+    #[normalized]
+    color: [u8; 4],
+}
+
+convert_vertex!(MyDetailedVertex => MyCompactVertex);
+
+#[test]
+fn test_convert_vertex() {
+    let source = MyDetailedVertex {
+        pos: [1.0, 2.0, 3.0],
+        color: [1.0, 0.0, 2.0, -1.0],
+        tangent: [0.0; 4],
+    };
+    let target: MyCompactVertex = source.into();
+    assert_eq!(target.position, [1.0, 2.0, 3.0]);
+    assert_eq!(target.color, [255, 0, 255, 0]);
+}
+
+#[test]
+fn test_byte_views() {
+    let vertices = [
+        MyCompactVertex { position: [1.0, 2.0, 3.0], color: [1, 2, 3, 4] },
+        MyCompactVertex { position: [4.0, 5.0, 6.0], color: [5, 6, 7, 8] },
+    ];
+    let bytes = MyCompactVertex::as_bytes(&vertices);
+    assert_eq!(bytes.len(), 32);
+    assert_eq!(&bytes[12..16], &[1, 2, 3, 4]);
+    let loaded = MyCompactVertex::from_bytes(bytes).unwrap();
+    assert_eq!(loaded.len(), 2);
+    assert_eq!(loaded[1].position, [4.0, 5.0, 6.0]);
+    assert!(MyCompactVertex::from_bytes(&bytes[..20]).is_none());
+}
+
+// Test embedding meshes at compile time
+#[vertex_format]
+#[derive(Copy, Clone)]
+struct MyMeshVertex {
+    #[name = "a_Pos"]
+    pos: [f32; 3],
+    normal: [f32; 3],
+    #[name = "a_TexCoord"]
+    tex_coord: [f32; 2],
+}
+
+#[test]
+fn test_mesh_data() {
+    let (vertices, indices) = mesh_data!("tests/meshes/quad.obj" as MyMeshVertex);
+    assert_eq!(vertices.len(), 4);
+    assert_eq!(indices.to_vec(), vec![0, 1, 2, 0, 2, 3]);
+    assert_eq!(vertices[1].pos, [1.0, -1.0, 0.0]);
+    assert_eq!(vertices[2].normal, [0.0, 0.0, 1.0]);
+    assert_eq!(vertices[3].tex_coord, [0.0, 1.0]);
+}
+
+// Test HLSL semantics
+#[vertex_format]
+#[derive(Copy, Clone)]
+struct MySemanticVertex {
+    #[name = "a_Pos"]
+    #[semantic = "POSITION"]
+    pos: [f32; 3],
+    #[semantic = "TEXCOORD1"]
+    transform: [[f32; 4]; 2],
+    color: [f32; 4],
+}
+
+#[test]
+fn test_semantics() {
+    let semantics = MySemanticVertex::attribute_semantics();
+    let semantics: Vec<(&str, &str)> = semantics.iter()
+        .map(|&(ref name, ref semantic)| (&name[..], &semantic[..])).collect();
+    assert_eq!(semantics, vec![
+        ("a_Pos", "POSITION"),
+        ("transform_0", "TEXCOORD1"),
+        ("transform_1", "TEXCOORD2"),
+        ("color", "color"),
+    ]);
+}
+
+// Test GLSL declarations of the attributes
+#[vertex_format]
+#[emit_glsl_decl]
+#[derive(Copy, Clone)]
+struct MyDeclVertex {
+    #[name = "a_Pos"]
+    pos: [f32; 3],
+    #[name = "a_Color"]
+    #[normalized]
+    color: [u8; 4],
+}
+
+#[test]
+fn test_glsl_decl() {
+    assert_eq!(MyDeclVertex::glsl_decl(), "attribute vec3 a_Pos;\nattribute vec4 a_Color;\n");
+}
+
+// Test leaving out fields stripped by `#[cfg]`
+gfx_vertex!(MyConfiguredVertex {
+    pos: [f32; 3] = "a_Pos",
+    #[cfg(feature = "skinning")]
+    weights: [f32; 4] = "a_Weights",
+    #[cfg(not(feature = "skinning"))]
+    tex_coord: [f32; 2] = "a_TexCoord",
+});
+
+#[test]
+fn test_cfg_fields() {
+    let formats = MyConfiguredVertex::attribute_formats();
+    let names: Vec<&str> = formats.iter().map(|&(ref name, _)| &name[..]).collect();
+    assert_eq!(names, vec!["a_Pos", "a_TexCoord"]);
+    assert_eq!(formats[1].1.stride, 20);
+}
+
+// Test struct level naming of the attributes
+#[vertex_format]
+#[name_convention = "PascalCase"]
+#[name_prefix = "a_"]
+#[derive(Copy, Clone)]
+struct MyNamedVertex {
+    pos: [f32; 3],
+    tex_coord: [f32; 2],
+    #[name = "a_Color0"]
+    color: [f32; 4],
+}
+
+#[test]
+fn test_name_convention() {
+    let formats = MyNamedVertex::attribute_formats();
+    let names: Vec<&str> = formats.iter().map(|&(ref name, _)| &name[..]).collect();
+    assert_eq!(names, vec!["a_Pos", "a_TexCoord", "a_Color0"]);
+}
+
+#[test]
+fn test_format_hash() {
+    assert_eq!(MyNamedVertex::format_hash(), MyNamedVertex::format_hash());
+    assert!(MySkinnedVertex::format_hash() != MyPaddedVertex::format_hash());
+    // the constant of formats laid out at expansion time
+    assert_eq!(MY_NAMED_VERTEX_FORMAT_HASH, MyNamedVertex::format_hash());
+    assert_eq!(MY_PADDED_VERTEX_FORMAT_HASH, MyPaddedVertex::format_hash());
+    // an explicit encoding, stable across gfx versions
+    assert_eq!(MyPaddedVertex::format_hash(), 0x3fe5958e229a2818);
+}
+
+#[test]
+fn test_static_formats() {
+    use secret_lib::gfx::attrib::Format;
+    // the table of a layout known at expansion time is not rebuilt per call
+    let formats: &'static [(&'static str, Format)] = MyNamedVertex::attribute_formats();
+    assert_eq!(formats.as_ptr(), MyNamedVertex::attribute_formats().as_ptr());
+    // while nested formats are laid out on call
+    let nested: Vec<(String, Format)> = MySkinnedVertex::attribute_formats();
+    assert_eq!(nested.len(), 3);
+}
+
+// Test converting sRGB colors to linear
+#[vertex_format]
+#[derive(Copy, Clone)]
+struct MySrgbVertex {
+    pos: [f32; 2],
+    #[srgb]
+    #[normalized]
+    color: [u8; 4],
+    #[srgb]
+    tint: [f32; 3],
+}
+
+#[test]
+fn test_srgb() {
+    let mut vertex = MySrgbVertex { pos: [0.0; 2], color: [0, 188, 255, 128], tint: [0.5, 1.0, 0.0] };
+    vertex.linearize();
+    assert_eq!(vertex.color, [0, 128, 255, 128]);
+    assert!((vertex.tint[0] - 0.214).abs() < 0.001);
+    assert!((vertex.tint[1] - 1.0).abs() < 0.001);
+    assert_eq!(vertex.tint[2], 0.0);
+}
+
+// Test conventional skinning attributes
+#[vertex_format]
+#[derive(Copy, Clone)]
+struct MySkinVertex {
+    #[name = "a_Pos"]
+    pos: [f32; 3],
+    #[skin_weights]
+    weights: [f32; 4],
+    #[skin_indices]
+    bones: [u8; 4],
+}
+
+#[test]
+fn test_skin() {
+    use secret_lib::gfx::attrib::{Type, IntSubType, IntSize, SignFlag};
+
+    let formats = MySkinVertex::attribute_formats();
+    let names: Vec<&str> = formats.iter().map(|&(ref name, _)| &name[..]).collect();
+    assert_eq!(names, vec!["a_Pos", "a_BoneWeights", "a_BoneIndices"]);
+    assert_eq!(formats[2].1.elem_type, Type::Int(IntSubType::Raw, IntSize::U8, SignFlag::Unsigned));
+}
+
+// Test matching the padded layout of a binary mesh format
+#[vertex_format]
+#[stride = "32"]
+#[derive(Copy, Clone)]
+struct MyStridedVertex {
+    #[pad_after = "4"]
+    pos: [f32; 3],
+    normal: [f32; 3],
+}
+
+#[test]
+fn test_stride() {
+    let vertex = MyStridedVertex {
+        pos: [0.0; 3],
+        _pad_after_pos: [0; 4],
+        normal: [0.0, 0.0, 1.0],
+        _pad_stride: [0; 4],
+    };
+    assert_eq!(vertex.normal[2], 1.0);
+    assert_eq!(::std::mem::size_of::<MyStridedVertex>(), 32);
+    assert_eq!(MyStridedVertex::offset_normal(), 16);
+    let formats = MyStridedVertex::attribute_formats();
+    assert_eq!(formats.len(), 2);
+    assert_eq!(formats[1].1.offset, 16);
+    assert_eq!(formats[1].1.stride, 32);
+}
+
+// Test the generated layout test
+#[vertex_format]
+#[gen_tests]
+#[derive(Copy, Clone)]
+struct MyTestedVertex {
+    pos: [f32; 3],
+    #[normalized]
+    color: [u8; 4],
+    #[location = "0"]
+    tex_coord: [f32; 2],
+}
+
+#[test]
+fn test_gen_tests() {
+    // also run by the harness on its own
+    gen_tests_MyTestedVertex();
+}